@@ -1,54 +1,649 @@
-use clap::{Parser, Subcommand};
+use crate::logic::anonymize::{AnonymizeOptions, TagPattern};
+use crate::logic::convert::{OutputFormat, PngCompression, WindowSelection};
+use crate::logic::workflow::{ConvertOptions, IfExists, OnError, DEFAULT_CHANNEL_CAPACITY};
+use crate::utils::discovery::{count_dicom_files, read_input_list, DiscoveryOptions};
+use crate::utils::logging::{self, EventFormat, Verbosity};
+use crate::utils::metadata_export::{CsvFormat, MetadataColumns};
+use crate::utils::watch::FolderWatcher;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use dicom::core::dictionary::DataDictionary;
+use dicom::core::Tag;
+use dicom::dictionary_std::StandardDataDictionary;
+use serde::Deserialize;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// How long the input folder must stay unchanged before --watch runs a pass.
+const WATCH_QUIET_PERIOD: std::time::Duration = std::time::Duration::from_secs(2);
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Only print the final summary and fatal errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Also print per-file details such as source paths
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+}
+
+impl Cli {
+    /// `try_parse`, with the options of a convert `--preset-file` filled in
+    /// wherever the command line does not set them. Convert switches also
+    /// accept `=false`, e.g. `--overwrite=false`, to turn off one the
+    /// preset turns on.
+    pub fn try_parse_with_preset() -> Result<Self, clap::Error> {
+        let mut command = Self::command().mut_subcommand("convert", switches_take_values);
+        let matches = command.try_get_matches_from_mut(std::env::args())?;
+        let mut cli = Self::from_arg_matches(&matches)?;
+
+        let Commands::Convert {
+            preset_file: Some(preset_file),
+            ..
+        } = &cli.command
+        else {
+            return Ok(cli);
+        };
+        let convert_matches = matches
+            .subcommand_matches("convert")
+            .expect("convert matches");
+        let convert = command
+            .find_subcommand("convert")
+            .expect("convert subcommand");
+        let cli_wins = |id: &str| set_on_command_line(convert, convert_matches, id);
+
+        ConvertPreset::load(preset_file)
+            .and_then(|preset| preset.apply(&mut cli.command, &cli_wins))
+            .map_err(|e| command.error(clap::error::ErrorKind::InvalidValue, e))?;
+        Ok(cli)
+    }
+}
+
+/// Let every `--switch` of `command` also take an explicit `=true` or
+/// `=false`.
+fn switches_take_values(mut command: clap::Command) -> clap::Command {
+    let switches: Vec<clap::Id> = command
+        .get_arguments()
+        .filter(|arg| matches!(arg.get_action(), ArgAction::SetTrue))
+        .map(|arg| arg.get_id().clone())
+        .collect();
+    for id in switches {
+        command = command.mut_arg(id, |arg| {
+            arg.action(ArgAction::Set)
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("true")
+        });
+    }
+    command
+}
+
+/// Whether the command line sets `id`, or an argument that conflicts with
+/// it, so that a preset value for `id` must not apply.
+fn set_on_command_line(command: &clap::Command, matches: &ArgMatches, id: &str) -> bool {
+    let given = |arg: &clap::Arg| {
+        matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+    };
+    let Some(target) = command.get_arguments().find(|arg| arg.get_id() == id) else {
+        return false;
+    };
+    given(target)
+        || command
+            .get_arg_conflicts_with(target)
+            .into_iter()
+            .any(given)
+        || command.get_arguments().filter(|arg| given(arg)).any(|arg| {
+            command
+                .get_arg_conflicts_with(arg)
+                .iter()
+                .any(|conflict| conflict.get_id() == id)
+        })
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
     /// Convert DICOM files to PNG
     Convert {
+        /// TOML file of convert options to start from, keyed by flag name,
+        /// e.g. `format = "tiff"`, `window = "40,400"`, `exclude = ["*.tmp"]`.
+        /// Options given on the command line take precedence over the file
+        /// (a conflicting flag such as --window-index drops its `window`);
+        /// list options add to the file's lists
+        #[arg(long, value_name = "TOML")]
+        preset_file: Option<String>,
+
         /// Input folder containing DICOM files
         #[arg(short, long)]
-        input: String,
+        input: Option<String>,
+
+        /// File listing the DICOM files to process, one path per line, instead
+        /// of scanning --input. Output mirrors their common parent folder
+        #[arg(long, conflicts_with = "input")]
+        input_list: Option<String>,
 
-        /// Output folder for PNG files
+        /// Output folder for PNG files (required unless --zip is given)
         #[arg(short, long)]
-        output: String,
+        output: Option<String>,
+
+        /// Write the PNGs, metadata and logs into this single zip archive
+        /// instead of an output folder
+        #[arg(long, conflicts_with_all = ["output", "contact_sheet", "watch"])]
+        zip: Option<String>,
 
         /// Skip generating Excel metadata file
         #[arg(long, default_value_t = false)]
         skip_excel: bool,
 
+        /// Only write metadata rows for converted files. By default skipped and
+        /// failed files get a row too (with the header fields that could be
+        /// read); they are always listed in logs.csv and errors.csv
+        #[arg(long, default_value_t = false)]
+        metadata_only_success: bool,
+
+        /// Field delimiter of the metadata CSVs, e.g. ";" for European Excel
+        /// ("tab" for tab-separated)
+        #[arg(long, value_parser = parse_delimiter, default_value = ",")]
+        csv_delimiter: u8,
+
+        /// Start the metadata CSVs with a UTF-8 BOM so spreadsheet apps detect
+        /// the encoding of non-ASCII names
+        #[arg(long, default_value_t = false)]
+        csv_bom: bool,
+
+        /// Columns of the metadata CSVs, in order: built-in names such as
+        /// "F_name,Modality", DICOM keywords such as "PatientAge", or tags as
+        /// eight hex digits. Defaults to every built-in column
+        #[arg(long, value_parser = MetadataColumns::parse)]
+        columns: Option<MetadataColumns>,
+
+        /// Also write metadata_all.parquet with typed columns (for large datasets)
+        #[arg(long, default_value_t = false)]
+        parquet: bool,
+
         /// Flatten output directory structure
         #[arg(long, default_value_t = false)]
         flatten_output: bool,
+
+        /// Name of the PNG folder under the output root (default "png_file");
+        /// "" writes the PNGs into the root itself
+        #[arg(long)]
+        output_subdir: Option<String>,
+
+        /// Mirror the folder structure below this folder instead of below
+        /// the input folder, e.g. a common parent of an --input-list; files
+        /// outside it are written under their file name
+        #[arg(long)]
+        relative_to: Option<String>,
+
+        /// Organize PNGs as <SeriesInstanceUID>/<InstanceNumber>.png instead of
+        /// mirroring the input folders (files without a series go to unsorted/)
+        #[arg(long, default_value_t = false)]
+        group_by_series: bool,
+
+        /// Also probe extensionless files without a DICOM preamble (slower)
+        #[arg(long, default_value_t = false)]
+        force: bool,
+
+        /// Glob pattern (relative to the input folder) to exclude, e.g. "**/SR/**".
+        /// Can be specified multiple times
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Also process hidden files and folders (names starting with '.',
+        /// e.g. macOS ._ files), which are skipped by default
+        #[arg(long, default_value_t = false)]
+        include_hidden: bool,
+
+        /// Also accept files with "DICM" at offset 0 (no 128-byte preamble),
+        /// as written by some older tools
+        #[arg(long, default_value_t = false)]
+        lenient_preamble: bool,
+
+        /// Only process the first N discovered files (sorted by path), e.g. to
+        /// try out options on a large dataset
+        #[arg(long)]
+        max_files: Option<usize>,
+
+        /// Only process files with this Modality, e.g. "CT"; others are skipped
+        /// as filtered. Can be specified multiple times
+        #[arg(long)]
+        only_modality: Vec<String>,
+
+        /// Embed PixelSpacing as physical pixel size (pHYs) in the PNG
+        #[arg(long, default_value_t = false)]
+        embed_spacing: bool,
+
+        /// Image format: "png" or "tiff". Both keep 16-bit grayscale where the
+        /// decoder produces it (e.g. float pixel data)
+        #[arg(long, value_parser = parse_output_format, default_value = "png")]
+        format: OutputFormat,
+
+        /// With --format tiff, write all frames of a multi-frame file as pages
+        /// of one TIFF instead of only the first frame
+        #[arg(long, default_value_t = false)]
+        tiff_multipage: bool,
+
+        /// Re-read every written image and check its dimensions, rewriting it
+        /// once before recording a failure (extra I/O, for irreplaceable batches)
+        #[arg(long, default_value_t = false)]
+        verify_output: bool,
+
+        /// PNG compression: "fast" (larger files, quicker batches), "default" or "best"
+        #[arg(long, value_parser = parse_png_compression, default_value = "default")]
+        png_compression: PngCompression,
+
+        /// Draw DICOM overlay planes (e.g. annotations) onto the PNG in white
+        #[arg(long, default_value_t = false)]
+        with_overlays: bool,
+
+        /// Invert the output (e.g. black-on-white X-ray prints), after all other
+        /// processing. MONOCHROME1 files are already shown inverted, so with
+        /// --invert they come out in their stored orientation; overlays turn black
+        #[arg(long, default_value_t = false)]
+        invert: bool,
+
+        /// Also write one <SeriesInstanceUID>_montage.png contact sheet per series
+        #[arg(long, default_value_t = false)]
+        contact_sheet: bool,
+
+        /// Number of thumbnail columns in each contact sheet
+        #[arg(long, default_value_t = 8)]
+        contact_sheet_columns: usize,
+
+        /// Also write an index.html gallery of the converted PNGs (and contact
+        /// sheets), grouped by output folder, in the output root
+        #[arg(long, default_value_t = false)]
+        html_index: bool,
+
+        /// Convert series by series and show each file's position within its
+        /// series in the progress lines (reads every header once more up front)
+        #[arg(long, default_value_t = false)]
+        series_progress: bool,
+
+        /// Record a SHA-256 of each source file in the metadata (adds I/O)
+        #[arg(long, default_value_t = false)]
+        hash: bool,
+
+        /// Record min/max/mean pixel values (after rescale) in the metadata,
+        /// e.g. to spot blank or saturated images (scans every pixel)
+        #[arg(long, default_value_t = false)]
+        pixel_stats: bool,
+
+        /// Maximum number of converted files queued for the metadata/log writer
+        #[arg(long, default_value_t = DEFAULT_CHANNEL_CAPACITY)]
+        channel_capacity: usize,
+
+        /// Override the files' window (format: "Center,Width", e.g., "40,400")
+        #[arg(long, value_parser = parse_window, allow_hyphen_values = true)]
+        window: Option<(f64, f64)>,
+
+        /// Use the file's Nth window (0-based) when it carries several
+        #[arg(long, conflicts_with_all = ["window", "window_label"])]
+        window_index: Option<usize>,
+
+        /// Use the file's window whose explanation (0028,1055) matches, e.g. "BONE"
+        #[arg(long, conflicts_with = "window")]
+        window_label: Option<String>,
+
+        /// For files without their own window, window to percentiles of the
+        /// pixel values instead of the full min-max range
+        #[arg(long, default_value_t = false)]
+        auto_window: bool,
+
+        /// Low and high percentiles for --auto-window (format: "Low,High")
+        #[arg(long, value_parser = parse_percentiles, default_value = "1,99")]
+        auto_window_percentiles: (f64, f64),
+
+        /// For files without their own window, use a default for their
+        /// Modality: CT soft tissue (40/400), MR auto window, CR/DX the full
+        /// stored range. Other modalities keep min-max normalization
+        #[arg(long, default_value_t = false)]
+        modality_defaults: bool,
+
+        /// Reconvert files even if the PNG already exists
+        #[arg(long, default_value_t = false)]
+        overwrite: bool,
+
+        /// What to do when a file fails: "continue" records it and goes on,
+        /// "abort" stops starting new files and exits with status 1
+        #[arg(long, value_parser = parse_on_error, default_value = "continue")]
+        on_error: OnError,
+
+        /// Report progress as machine-readable events on stderr instead of
+        /// the per-file console output: "jsonl" writes one JSON object per
+        /// line with an "event" field (discovery, progress, log or warning).
+        /// The final summary stays on stdout
+        #[arg(long, value_parser = parse_events)]
+        events: Option<EventFormat>,
+
+        /// When the output root already holds files: "append" writes into it,
+        /// "error" refuses to start, "suffix" uses `<root>_2`, `<root>_3`, ...
+        #[arg(long, value_parser = parse_if_exists, default_value = "append")]
+        if_exists: IfExists,
+
+        /// After the first pass, keep watching the input folder and convert new
+        /// files as they arrive, until interrupted
+        #[arg(long, default_value_t = false, conflicts_with_all = ["input_list", "overwrite"])]
+        watch: bool,
     },
     /// Anonymize DICOM files
     Anonymize {
+        /// Input folder containing DICOM files
+        #[arg(short, long, required_unless_present = "input_list")]
+        input: Option<String>,
+
+        /// File listing the DICOM files to process, one path per line, instead
+        /// of scanning --input. Output mirrors their common parent folder
+        #[arg(long, conflicts_with = "input")]
+        input_list: Option<String>,
+
+        /// Output folder for anonymized DICOM files
+        #[arg(short, long)]
+        output: String,
+
+        /// Tags to anonymize (format: "Group,Element", e.g., "0010,0010", or a keyword
+        /// such as "PatientName"), optionally with their own replacement value
+        /// ("0010,0020=PSEUDO-001"). Ranges: "0010,*" is every element of group
+        /// 0010 in the file, "60xx,*" every element of groups 6000-60FF, including
+        /// inside sequences; elements whose VR cannot hold the value are emptied
+        /// (audited as "emptied"), sequences removed.
+        /// Can be specified multiple times
+        #[arg(short, long, visible_alias = "tag", value_parser = parse_tag_replacement)]
+        tags: Vec<TagReplacement>,
+
+        /// File with one tag per line (same formats as --tags); blank lines and
+        /// lines starting with '#' are ignored. Merged with any --tags
+        #[arg(long)]
+        tags_file: Option<String>,
+
+        /// Replacement value for tags listed without their own value
+        #[arg(short, long, default_value = "ANONYMIZED")]
+        replacement: String,
+
+        /// Also probe extensionless files without a DICOM preamble (slower)
+        #[arg(long, default_value_t = false)]
+        force: bool,
+
+        /// Glob pattern (relative to the input folder) to exclude, e.g. "**/SR/**".
+        /// Can be specified multiple times
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Also process hidden files and folders (names starting with '.',
+        /// e.g. macOS ._ files), which are skipped by default
+        #[arg(long, default_value_t = false)]
+        include_hidden: bool,
+
+        /// Also accept files with "DICM" at offset 0 (no 128-byte preamble),
+        /// as written by some older tools
+        #[arg(long, default_value_t = false)]
+        lenient_preamble: bool,
+
+        /// Only process the first N discovered files (sorted by path), e.g. to
+        /// try out options on a large dataset
+        #[arg(long)]
+        max_files: Option<usize>,
+
+        /// Only process files with this Modality, e.g. "CT"; others are skipped
+        /// as filtered. Can be specified multiple times
+        #[arg(long)]
+        only_modality: Vec<String>,
+
+        /// Name of the anonymized file folder under the output root (default
+        /// "dicom_file"); "" writes the files into the root itself
+        #[arg(long)]
+        output_subdir: Option<String>,
+
+        /// Mirror the folder structure below this folder instead of below
+        /// the input folder, e.g. a common parent of an --input-list; files
+        /// outside it are written under their file name
+        #[arg(long)]
+        relative_to: Option<String>,
+
+        /// Write directly into the output folder and put all files in one
+        /// folder; duplicate names get a numeric suffix
+        #[arg(long, default_value_t = false)]
+        flatten_output: bool,
+
+        /// Append .dcm to output names that lack a .dcm/.dicom/.ima extension
+        /// (e.g. extensionless files found by their DICM header). By default
+        /// output names are the input names
+        #[arg(long, default_value_t = false, conflicts_with = "preserve_names")]
+        add_extension: bool,
+
+        /// Keep every output name byte-identical to its input name: with
+        /// --flatten-output, fail instead of suffixing duplicate names
+        #[arg(long, default_value_t = false)]
+        preserve_names: bool,
+
+        /// Re-anonymize files even if the output already exists
+        #[arg(long, default_value_t = false)]
+        overwrite: bool,
+
+        /// What to do when a file fails: "continue" records it and goes on,
+        /// "abort" stops starting new files and exits with status 1
+        #[arg(long, value_parser = parse_on_error, default_value = "continue")]
+        on_error: OnError,
+
+        /// Report progress as machine-readable events on stderr instead of
+        /// the per-file console output: "jsonl" writes one JSON object per
+        /// line with an "event" field (discovery, progress, log or warning).
+        /// The final summary stays on stdout
+        #[arg(long, value_parser = parse_events)]
+        events: Option<EventFormat>,
+
+        /// Set SOPClassUID to this UID (e.g. "1.2.840.10008.5.1.4.1.1.2");
+        /// by default each file keeps its original SOP class
+        #[arg(long, value_parser = parse_uid)]
+        force_sop_class: Option<String>,
+
+        /// Derive new UIDs from this seed instead of at random, so anonymizing
+        /// the same files again gives identical output (e.g. for regression tests)
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Replace PatientID with the first 16 hex characters of
+        /// SHA-256(PatientID + SALT), so each patient keeps one consistent,
+        /// non-reversible pseudonym across the dataset. Keep the salt secret
+        /// and reuse it to link later exports
+        #[arg(long)]
+        salt: Option<String>,
+
+        /// Remove PixelData from the output, keeping only the anonymized header
+        /// (for sharing headers without images that may contain burned-in PHI)
+        #[arg(long, default_value_t = false)]
+        strip_pixel_data: bool,
+
+        /// Also convert each anonymized file to PNG (under png_file/ in the
+        /// output root, default settings) in the same pass, reading every
+        /// input only once
+        #[arg(long, default_value_t = false)]
+        and_convert: bool,
+
+        /// Record a SHA-256 of each source file in the metadata (adds I/O)
+        #[arg(long, default_value_t = false)]
+        hash: bool,
+
+        /// Field delimiter of the metadata CSVs, e.g. ";" for European Excel
+        /// ("tab" for tab-separated)
+        #[arg(long, value_parser = parse_delimiter, default_value = ",")]
+        csv_delimiter: u8,
+
+        /// Start the metadata CSVs with a UTF-8 BOM so spreadsheet apps detect
+        /// the encoding of non-ASCII names
+        #[arg(long, default_value_t = false)]
+        csv_bom: bool,
+
+        /// Columns of the metadata CSVs, in order: built-in names such as
+        /// "F_name,Modality", DICOM keywords such as "PatientAge", or tags as
+        /// eight hex digits. Defaults to every built-in column
+        #[arg(long, value_parser = MetadataColumns::parse)]
+        columns: Option<MetadataColumns>,
+    },
+    /// Stack each series into a 3D volume and write it as NIfTI (.nii.gz)
+    ConvertVolume {
         /// Input folder containing DICOM files
         #[arg(short, long)]
         input: String,
 
-        /// Output folder for anonymized DICOM files
+        /// Output folder for the .nii.gz volumes
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Render the content tree of Structured Reports (SR) as Markdown
+    ExtractSr {
+        /// Input folder containing DICOM files
+        #[arg(short, long)]
+        input: String,
+
+        /// Output folder for the .md documents
         #[arg(short, long)]
         output: String,
+    },
+    /// Rewrite DICOM files as uncompressed Explicit VR Little Endian, e.g. for
+    /// tools that cannot read JPEG 2000 or RLE
+    Transcode {
+        /// Input folder containing DICOM files
+        #[arg(short, long)]
+        input: String,
 
-        /// Tags to anonymize (format: "Group,Element", e.g., "0010,0010")
+        /// Output folder for the transcoded files (mirrors the input folders)
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Count the DICOM files discovery finds, without opening them
+    Count {
+        /// Input folder containing DICOM files
+        #[arg(short, long)]
+        input: String,
+
+        /// Also count extensionless files without a DICOM preamble
+        #[arg(long, default_value_t = false)]
+        force: bool,
+
+        /// Glob pattern (relative to the input folder) to exclude, e.g. "**/SR/**".
         /// Can be specified multiple times
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Also process hidden files and folders (names starting with '.',
+        /// e.g. macOS ._ files), which are skipped by default
+        #[arg(long, default_value_t = false)]
+        include_hidden: bool,
+
+        /// Also accept files with "DICM" at offset 0 (no 128-byte preamble),
+        /// as written by some older tools
+        #[arg(long, default_value_t = false)]
+        lenient_preamble: bool,
+    },
+    /// Write one tag's value for every file to a CSV (file_name, file_path, value)
+    Extract {
+        /// Input folder containing DICOM files
+        #[arg(short, long)]
+        input: String,
+
+        /// Tag to extract ("Group,Element", e.g., "0018,0060", or a keyword such as "KVP")
         #[arg(short, long, value_parser = parse_tag)]
+        tag: (u16, u16),
+
+        /// Output CSV file
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Print the distribution of values of one or more tags, most frequent first
+    Stats {
+        /// Input folder containing DICOM files
+        #[arg(short, long)]
+        input: String,
+
+        /// Tags to count ("Group,Element", e.g., "0008,0060", or a keyword such
+        /// as "Modality"). Can be specified multiple times
+        #[arg(short, long, visible_alias = "tag", required = true, value_parser = parse_tag)]
         tags: Vec<(u16, u16)>,
 
-        /// Replacement value for anonymized tags
-        #[arg(short, long, default_value = "ANONYMIZED")]
-        replacement: String,
+        /// Also write the full distribution to this CSV (tag, name, value, count)
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Value counted for files that lack the tag
+        #[arg(long, default_value = "Missing")]
+        missing_label: String,
+
+        /// Value counted for files where the tag is present but empty
+        #[arg(long, default_value = "")]
+        empty_label: String,
+
+        /// Value counted for files where the tag holds binary data
+        #[arg(long, default_value = "Binary")]
+        binary_label: String,
+    },
+    /// Report series whose files differ in Rows, Columns or PixelSpacing
+    CheckSeries {
+        /// Input folder containing DICOM files
+        #[arg(short, long)]
+        input: String,
+
+        /// Exit with status 1 if any series is inconsistent (e.g. in scripts
+        /// that stack series into volumes)
+        #[arg(long, default_value_t = false)]
+        strict: bool,
+    },
+    /// Preview the PHI in a folder before anonymizing: how many files
+    /// populate PatientName, PatientID, ReferringPhysicianName and other
+    /// identifying tags. Nothing is modified.
+    ScanPhi {
+        /// Input folder containing DICOM files
+        #[arg(short, long)]
+        input: String,
+
+        /// Also write every file's values to this CSV (file_path, one column
+        /// per tag)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Render one DICOM file to PNG and write the image bytes to stdout, e.g.
+    /// `cli convert-one -i scan.dcm | display -`
+    ConvertOne {
+        /// DICOM file to convert (first frame)
+        #[arg(short, long)]
+        input: String,
+
+        /// Override the file's window (format: "Center,Width", e.g., "40,400")
+        #[arg(long, value_parser = parse_window, allow_hyphen_values = true)]
+        window: Option<(f64, f64)>,
+    },
+    /// Print which common transfer syntaxes this build can decode and encode
+    Codecs,
+    /// Print how many files use each transfer syntax
+    TsSummary {
+        /// Input folder containing DICOM files
+        #[arg(short, long)]
+        input: String,
     },
 }
 
 fn parse_tag(s: &str) -> Result<(u16, u16), String> {
+    let s = s.trim().trim_start_matches('(').trim_end_matches(')');
+    if !s.contains(',') {
+        // Not "Group,Element": try a data dictionary keyword such as "PatientName"
+        return StandardDataDictionary
+            .by_name(s)
+            .map(|entry| {
+                let tag = entry.tag.inner();
+                (tag.0, tag.1)
+            })
+            .ok_or_else(|| {
+                format!(
+                    "Invalid tag: {}. Expected 'Group,Element' (hex) or a DICOM keyword",
+                    s
+                )
+            });
+    }
     let parts: Vec<&str> = s.split(',').collect();
     if parts.len() != 2 {
         return Err(format!(
@@ -56,97 +651,853 @@ fn parse_tag(s: &str) -> Result<(u16, u16), String> {
             s
         ));
     }
-    let group = u16::from_str_radix(parts[0], 16).map_err(|e| format!("Invalid group: {}", e))?;
+    let group =
+        u16::from_str_radix(parts[0].trim(), 16).map_err(|e| format!("Invalid group: {}", e))?;
     let element =
-        u16::from_str_radix(parts[1], 16).map_err(|e| format!("Invalid element: {}", e))?;
+        u16::from_str_radix(parts[1].trim(), 16).map_err(|e| format!("Invalid element: {}", e))?;
     Ok((group, element))
 }
 
+/// A tag to anonymize and, if given, its own replacement value.
+type TagReplacement = (TagPattern, Option<String>);
+
+/// "Tag" or "Tag=Value"; everything after the first '=' is the value.
+fn parse_tag_replacement(s: &str) -> Result<TagReplacement, String> {
+    match s.split_once('=') {
+        Some((tag, value)) => Ok((parse_tag_pattern(tag)?, Some(value.to_string()))),
+        None => Ok((parse_tag_pattern(s)?, None)),
+    }
+}
+
+/// A tag as accepted by `parse_tag`, or "Group,Element" with 'x' wildcard
+/// digits ("60xx,3000") and '*' for any element ("0010,*").
+fn parse_tag_pattern(s: &str) -> Result<TagPattern, String> {
+    let trimmed = s.trim().trim_start_matches('(').trim_end_matches(')');
+    let Some((group, element)) = trimmed.split_once(',') else {
+        return parse_tag(s).map(|(group, element)| TagPattern::exact(Tag(group, element)));
+    };
+    if !trimmed.contains(['x', 'X', '*']) {
+        return parse_tag(s).map(|(group, element)| TagPattern::exact(Tag(group, element)));
+    }
+
+    // Each hex digit contributes 4 bits to the value, each 'x' 4 zero mask bits
+    let masked = |part: &str, what: &str| -> Result<(u16, u16), String> {
+        let part = part.trim();
+        if part == "*" {
+            return Ok((0, 0));
+        }
+        if part.len() != 4 {
+            return Err(format!("Invalid {} in tag pattern: {}", what, s));
+        }
+        part.chars().try_fold((0u16, 0u16), |(value, mask), c| {
+            if c.eq_ignore_ascii_case(&'x') {
+                Ok((value << 4, mask << 4))
+            } else {
+                let digit = c
+                    .to_digit(16)
+                    .ok_or_else(|| format!("Invalid {} in tag pattern: {}", what, s))?;
+                Ok((value << 4 | digit as u16, mask << 4 | 0xF))
+            }
+        })
+    };
+    let (group, group_mask) = masked(group, "group")?;
+    let (element, element_mask) = masked(element, "element")?;
+    Ok(TagPattern {
+        group,
+        group_mask,
+        element,
+        element_mask,
+    })
+}
+
+fn parse_on_error(s: &str) -> Result<OnError, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "continue" => Ok(OnError::Continue),
+        "abort" => Ok(OnError::Abort),
+        _ => Err(format!(
+            "Invalid value: {}. Expected 'continue' or 'abort'",
+            s
+        )),
+    }
+}
+
+fn parse_events(s: &str) -> Result<EventFormat, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "jsonl" => Ok(EventFormat::Jsonl),
+        _ => Err(format!("Invalid value: {}. Expected 'jsonl'", s)),
+    }
+}
+
+fn parse_if_exists(s: &str) -> Result<IfExists, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "append" => Ok(IfExists::Append),
+        "error" => Ok(IfExists::Error),
+        "suffix" => Ok(IfExists::Suffix),
+        _ => Err(format!(
+            "Invalid value: {}. Expected 'append', 'error' or 'suffix'",
+            s
+        )),
+    }
+}
+
+fn parse_window(s: &str) -> Result<(f64, f64), String> {
+    let (center, width) = s
+        .split_once(',')
+        .ok_or_else(|| format!("Invalid window: {}. Expected 'Center,Width'", s))?;
+    let center: f64 = center
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid window center: {}", e))?;
+    let width: f64 = width
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid window width: {}", e))?;
+    if width <= 0.0 {
+        return Err(format!(
+            "Invalid window width: {} (must be positive)",
+            width
+        ));
+    }
+    Ok((center, width))
+}
+
+fn parse_percentiles(s: &str) -> Result<(f64, f64), String> {
+    let (low, high) = s
+        .split_once(',')
+        .ok_or_else(|| format!("Invalid percentiles: {}. Expected 'Low,High'", s))?;
+    let low: f64 = low
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid low percentile: {}", e))?;
+    let high: f64 = high
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid high percentile: {}", e))?;
+    if !(0.0..=100.0).contains(&low) || !(0.0..=100.0).contains(&high) || low >= high {
+        return Err(format!(
+            "Invalid percentiles: {}. Expected 0 <= Low < High <= 100",
+            s
+        ));
+    }
+    Ok((low, high))
+}
+
+fn parse_output_format(s: &str) -> Result<OutputFormat, String> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "png" => Ok(OutputFormat::Png),
+        "tiff" | "tif" => Ok(OutputFormat::Tiff),
+        _ => Err(format!(
+            "Invalid output format: {}. Expected png or tiff",
+            s
+        )),
+    }
+}
+
+fn parse_png_compression(s: &str) -> Result<PngCompression, String> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "fast" => Ok(PngCompression::Fast),
+        "default" => Ok(PngCompression::Default),
+        "best" => Ok(PngCompression::Best),
+        _ => Err(format!(
+            "Invalid PNG compression: {}. Expected fast, default or best",
+            s
+        )),
+    }
+}
+
+fn parse_uid(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    let valid = !s.is_empty()
+        && s.len() <= 64
+        && s.split('.')
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+    if !valid {
+        return Err(format!(
+            "Invalid UID: {}. Expected dot-separated digits, at most 64 characters",
+            s
+        ));
+    }
+    Ok(s.to_string())
+}
+
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(format!(
+            "Invalid CSV delimiter: {}. Expected a single ASCII character or 'tab'",
+            s
+        )),
+    }
+}
+
+/// The input folder to run on plus, with --input-list, the explicit files
+/// within it.
+fn resolve_input(
+    input: Option<String>,
+    input_list: Option<String>,
+) -> Result<(PathBuf, Option<Vec<PathBuf>>), String> {
+    match (input, input_list) {
+        (_, Some(list)) => read_input_list(Path::new(&list))
+            .map(|(root, files)| (root, Some(files)))
+            .map_err(|e| format!("{:#}", e)),
+        (Some(input), None) => Ok((PathBuf::from(input), None)),
+        (None, None) => Err("Either --input or --input-list is required".to_string()),
+    }
+}
+
+fn read_tags_file(path: &str) -> Result<Vec<TagReplacement>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read tags file {}: {}", path, e))?;
+
+    let mut tags = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let tag =
+            parse_tag_replacement(line).map_err(|e| format!("{}:{}: {}", path, index + 1, e))?;
+        tags.push(tag);
+    }
+    Ok(tags)
+}
+
+/// Convert options read from a `--preset-file`, keyed like the flags
+/// (with `_` or `-`). Switches are booleans, lists are arrays and options
+/// with a format of their own, such as `window`, are strings.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConvertPreset {
+    input: Option<String>,
+    input_list: Option<String>,
+    output: Option<String>,
+    zip: Option<String>,
+    skip_excel: Option<bool>,
+    metadata_only_success: Option<bool>,
+    csv_delimiter: Option<String>,
+    csv_bom: Option<bool>,
+    columns: Option<String>,
+    parquet: Option<bool>,
+    flatten_output: Option<bool>,
+    output_subdir: Option<String>,
+    relative_to: Option<String>,
+    group_by_series: Option<bool>,
+    force: Option<bool>,
+    exclude: Option<Vec<String>>,
+    include_hidden: Option<bool>,
+    lenient_preamble: Option<bool>,
+    max_files: Option<usize>,
+    only_modality: Option<Vec<String>>,
+    embed_spacing: Option<bool>,
+    format: Option<String>,
+    tiff_multipage: Option<bool>,
+    verify_output: Option<bool>,
+    png_compression: Option<String>,
+    with_overlays: Option<bool>,
+    invert: Option<bool>,
+    contact_sheet: Option<bool>,
+    contact_sheet_columns: Option<usize>,
+    html_index: Option<bool>,
+    series_progress: Option<bool>,
+    hash: Option<bool>,
+    pixel_stats: Option<bool>,
+    channel_capacity: Option<usize>,
+    window: Option<String>,
+    window_index: Option<usize>,
+    window_label: Option<String>,
+    auto_window: Option<bool>,
+    auto_window_percentiles: Option<String>,
+    modality_defaults: Option<bool>,
+    overwrite: Option<bool>,
+    on_error: Option<String>,
+    events: Option<String>,
+    if_exists: Option<String>,
+    watch: Option<bool>,
+}
+
+impl ConvertPreset {
+    fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read preset file {}: {}", path, e))?;
+        let table: toml::Table = content
+            .parse()
+            .map_err(|e| format!("Invalid preset file {}: {}", path, e))?;
+        let table: toml::Table = table
+            .into_iter()
+            .map(|(key, value)| (key.replace('-', "_"), value))
+            .collect();
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("Invalid preset file {}: {}", path, e))
+    }
+
+    /// Fill the options of a `Commands::Convert` from the preset, except
+    /// where `cli_wins` says the command line decides.
+    fn apply(self, command: &mut Commands, cli_wins: &dyn Fn(&str) -> bool) -> Result<(), String> {
+        let Commands::Convert {
+            preset_file: _,
+            input,
+            input_list,
+            output,
+            zip,
+            skip_excel,
+            metadata_only_success,
+            csv_delimiter,
+            csv_bom,
+            columns,
+            parquet,
+            flatten_output,
+            output_subdir,
+            relative_to,
+            group_by_series,
+            force,
+            exclude,
+            include_hidden,
+            lenient_preamble,
+            max_files,
+            only_modality,
+            embed_spacing,
+            format,
+            tiff_multipage,
+            verify_output,
+            png_compression,
+            with_overlays,
+            invert,
+            contact_sheet,
+            contact_sheet_columns,
+            html_index,
+            series_progress,
+            hash,
+            pixel_stats,
+            channel_capacity,
+            window,
+            window_index,
+            window_label,
+            auto_window,
+            auto_window_percentiles,
+            modality_defaults,
+            overwrite,
+            on_error,
+            events,
+            if_exists,
+            watch,
+        } = command
+        else {
+            return Ok(());
+        };
+        let merge = |id: &str| !cli_wins(id);
+
+        if merge("input") {
+            *input = self.input.or(input.take());
+        }
+        if merge("input_list") {
+            *input_list = self.input_list.or(input_list.take());
+        }
+        if merge("output") {
+            *output = self.output.or(output.take());
+        }
+        if merge("zip") {
+            *zip = self.zip.or(zip.take());
+        }
+        if merge("output_subdir") {
+            *output_subdir = self.output_subdir.or(output_subdir.take());
+        }
+        if merge("relative_to") {
+            *relative_to = self.relative_to.or(relative_to.take());
+        }
+        if merge("window_label") {
+            *window_label = self.window_label.or(window_label.take());
+        }
+        if merge("max_files") {
+            *max_files = self.max_files.or(*max_files);
+        }
+        if merge("window_index") {
+            *window_index = self.window_index.or(*window_index);
+        }
+
+        for (id, target, value) in [
+            ("skip_excel", skip_excel, self.skip_excel),
+            (
+                "metadata_only_success",
+                metadata_only_success,
+                self.metadata_only_success,
+            ),
+            ("csv_bom", csv_bom, self.csv_bom),
+            ("parquet", parquet, self.parquet),
+            ("flatten_output", flatten_output, self.flatten_output),
+            ("group_by_series", group_by_series, self.group_by_series),
+            ("force", force, self.force),
+            ("include_hidden", include_hidden, self.include_hidden),
+            ("lenient_preamble", lenient_preamble, self.lenient_preamble),
+            ("embed_spacing", embed_spacing, self.embed_spacing),
+            ("tiff_multipage", tiff_multipage, self.tiff_multipage),
+            ("verify_output", verify_output, self.verify_output),
+            ("with_overlays", with_overlays, self.with_overlays),
+            ("invert", invert, self.invert),
+            ("contact_sheet", contact_sheet, self.contact_sheet),
+            ("html_index", html_index, self.html_index),
+            ("series_progress", series_progress, self.series_progress),
+            ("hash", hash, self.hash),
+            ("pixel_stats", pixel_stats, self.pixel_stats),
+            ("auto_window", auto_window, self.auto_window),
+            (
+                "modality_defaults",
+                modality_defaults,
+                self.modality_defaults,
+            ),
+            ("overwrite", overwrite, self.overwrite),
+            ("watch", watch, self.watch),
+        ] {
+            if let Some(value) = value.filter(|_| merge(id)) {
+                *target = value;
+            }
+        }
+        for (id, target, value) in [
+            (
+                "contact_sheet_columns",
+                contact_sheet_columns,
+                self.contact_sheet_columns,
+            ),
+            ("channel_capacity", channel_capacity, self.channel_capacity),
+        ] {
+            if let Some(value) = value.filter(|_| merge(id)) {
+                *target = value;
+            }
+        }
+
+        // The command line adds to the preset's lists
+        for (target, value) in [(exclude, self.exclude), (only_modality, self.only_modality)] {
+            if let Some(mut values) = value {
+                values.append(target);
+                *target = values;
+            }
+        }
+
+        // Options with a format of their own go through the flag's parser
+        let parsed =
+            |id: &str, value: Option<String>| -> Option<String> { value.filter(|_| merge(id)) };
+        let invalid = |id: &'static str| move |e: String| format!("preset option '{}': {}", id, e);
+        if let Some(value) = parsed("csv_delimiter", self.csv_delimiter) {
+            *csv_delimiter = parse_delimiter(&value).map_err(invalid("csv_delimiter"))?;
+        }
+        if let Some(value) = parsed("columns", self.columns) {
+            *columns = Some(MetadataColumns::parse(&value).map_err(invalid("columns"))?);
+        }
+        if let Some(value) = parsed("format", self.format) {
+            *format = parse_output_format(&value).map_err(invalid("format"))?;
+        }
+        if let Some(value) = parsed("png_compression", self.png_compression) {
+            *png_compression = parse_png_compression(&value).map_err(invalid("png_compression"))?;
+        }
+        if let Some(value) = parsed("window", self.window) {
+            *window = Some(parse_window(&value).map_err(invalid("window"))?);
+        }
+        if let Some(value) = parsed("auto_window_percentiles", self.auto_window_percentiles) {
+            *auto_window_percentiles =
+                parse_percentiles(&value).map_err(invalid("auto_window_percentiles"))?;
+        }
+        if let Some(value) = parsed("on_error", self.on_error) {
+            *on_error = parse_on_error(&value).map_err(invalid("on_error"))?;
+        }
+        if let Some(value) = parsed("events", self.events) {
+            *events = Some(parse_events(&value).map_err(invalid("events"))?);
+        }
+        if let Some(value) = parsed("if_exists", self.if_exists) {
+            *if_exists = parse_if_exists(&value).map_err(invalid("if_exists"))?;
+        }
+        Ok(())
+    }
+}
+
 pub fn run_cli(cli: Cli) {
+    logging::set_verbosity(if cli.quiet {
+        Verbosity::Quiet
+    } else if cli.verbose {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    });
+
     match cli.command {
         Commands::Convert {
+            preset_file: _,
             input,
+            input_list,
             output,
+            zip,
             skip_excel,
+            metadata_only_success,
+            csv_delimiter,
+            csv_bom,
+            columns,
+            parquet,
             flatten_output,
+            output_subdir,
+            relative_to,
+            group_by_series,
+            force,
+            exclude,
+            include_hidden,
+            lenient_preamble,
+            max_files,
+            only_modality,
+            embed_spacing,
+            format,
+            tiff_multipage,
+            verify_output,
+            png_compression,
+            with_overlays,
+            invert,
+            contact_sheet,
+            contact_sheet_columns,
+            html_index,
+            series_progress,
+            hash,
+            pixel_stats,
+            channel_capacity,
+            window,
+            window_index,
+            window_label,
+            auto_window,
+            auto_window_percentiles,
+            modality_defaults,
+            overwrite,
+            on_error,
+            events,
+            if_exists,
+            watch,
         } => {
-            println!("Starting conversion...");
-            println!("Input: {}", input);
-            println!("Output: {}", output);
+            if let Some(format) = events {
+                logging::enable_events(format);
+            }
+            if tiff_multipage && format != OutputFormat::Tiff {
+                eprintln!("Conversion failed: --tiff-multipage requires --format tiff");
+                std::process::exit(1);
+            }
+            // Every pass after the first writes into the root the first created
+            if watch && if_exists != IfExists::Append {
+                eprintln!("Conversion failed: --watch requires --if-exists append");
+                std::process::exit(1);
+            }
+            if output.is_none() && zip.is_none() {
+                eprintln!("Conversion failed: Either --output or --zip is required");
+                std::process::exit(1);
+            }
+            let (input, files) = match resolve_input(input, input_list) {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    eprintln!("Conversion failed: {}", e);
+                    std::process::exit(1);
+                }
+            };
 
-            let res = crate::logic::workflow::convert_dicom_to_png(
-                std::path::Path::new(&input),
-                std::path::Path::new(&output),
-                !skip_excel,
-                flatten_output,
-                |progress| {
-                    let percentage = if progress.total > 0 {
-                        (progress.current as f64 / progress.total as f64) * 100.0
-                    } else {
-                        0.0
-                    };
-                    println!(
-                        "Progress: {}/{} ({:.1}%) - {} [{}]",
-                        progress.current,
-                        progress.total,
-                        percentage,
-                        progress.filename,
-                        progress.status
-                    );
+            logging::info("Starting conversion...");
+            logging::info(format_args!("Input: {}", input.display()));
+            logging::info(format_args!(
+                "Output: {}",
+                zip.as_deref().or(output.as_deref()).unwrap_or_default()
+            ));
+            // Not used when writing a zip archive
+            let output = output.unwrap_or_default();
+
+            let options = ConvertOptions {
+                save_excel: !skip_excel,
+                metadata_only_success,
+                csv_format: CsvFormat {
+                    delimiter: csv_delimiter,
+                    bom: csv_bom,
                 },
-                |log| {
-                    println!("[{}] {}", log.status, log.message);
+                metadata_columns: columns.unwrap_or_default(),
+                save_parquet: parquet,
+                flatten_output,
+                output_subdir,
+                relative_to: relative_to.map(PathBuf::from),
+                group_by_series,
+                discovery: DiscoveryOptions {
+                    force,
+                    exclude,
+                    files,
+                    max_files,
+                    only_modality,
+                    include_hidden,
+                    lenient_preamble,
                 },
-            );
+                embed_spacing,
+                output_format: format,
+                tiff_multipage,
+                verify_output,
+                png_compression,
+                with_overlays,
+                invert,
+                contact_sheet_columns: contact_sheet.then_some(contact_sheet_columns),
+                html_index,
+                series_progress,
+                hash,
+                pixel_stats,
+                channel_capacity,
+                window,
+                window_selection: window_index
+                    .map(WindowSelection::Index)
+                    .or(window_label.map(WindowSelection::Label)),
+                auto_window: auto_window.then_some(auto_window_percentiles),
+                modality_defaults,
+                overwrite,
+                if_exists,
+                on_error,
+                zip: zip.map(PathBuf::from),
+                ..Default::default()
+            };
 
-            match res {
-                Ok(report) => {
-                    println!("Conversion completed successfully!");
-                    println!("Total: {}", report.total);
-                    println!("Successful: {}", report.successful);
-                    println!("Skipped: {}", report.skipped_non_image);
-                    println!("Failed: {}", report.failed);
-                    println!("Output folder: {:?}", report.output_folder);
+            // One full pass; files converted earlier are skipped because
+            // their PNG already exists
+            let run = || {
+                let res = crate::logic::workflow::convert_dicom_to_png(
+                    &input,
+                    std::path::Path::new(&output),
+                    &options,
+                    |discovery| {
+                        logging::event("discovery", &discovery);
+                        logging::debug(format_args!(
+                            "Discovering files: {} found in {} scanned",
+                            discovery.found, discovery.scanned
+                        ));
+                    },
+                    |progress| {
+                        logging::event("progress", &progress);
+                        let percentage = if progress.total > 0 {
+                            (progress.current as f64 / progress.total as f64) * 100.0
+                        } else {
+                            0.0
+                        };
+                        let series = progress
+                            .series
+                            .as_ref()
+                            .map(|series| {
+                                format!(
+                                    " - series {}/{}: {}/{}",
+                                    series.series_index,
+                                    series.series_count,
+                                    series.series_current,
+                                    series.series_total
+                                )
+                            })
+                            .unwrap_or_default();
+                        logging::info(format_args!(
+                            "Progress: {}/{} ({:.1}%){} - {} [{}]",
+                            progress.current,
+                            progress.total,
+                            percentage,
+                            series,
+                            progress.filename,
+                            progress.status
+                        ));
+                    },
+                    |log| {
+                        logging::event("log", &log);
+                        logging::info(format_args!("[{}] {}", log.status, log.message));
+                        logging::debug(format_args!("    {}", log.file_path));
+                    },
+                );
+
+                match res {
+                    Ok(report) => {
+                        println!("Conversion completed successfully!");
+                        println!("Total: {}", report.total);
+                        println!("Successful: {}", report.successful);
+                        println!("Skipped: {}", report.skipped_non_image);
+                        println!("Failed: {}", report.failed);
+                        let mut failures: Vec<_> =
+                            report.failures_by_transfer_syntax.iter().collect();
+                        failures.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+                        for (transfer_syntax, count) in failures {
+                            println!("  {}: {}", transfer_syntax, count);
+                        }
+                        println!("Output folder: {:?}", report.output_folder);
+                        if let Some(path) = &report.aborted_by {
+                            eprintln!("Conversion aborted after {} failed", path);
+                            std::process::exit(1);
+                        }
+                        if let Some(error) = &report.write_error {
+                            eprintln!("Conversion stopped: {}", error);
+                            std::process::exit(1);
+                        }
+                        true
+                    }
+                    Err(e) => {
+                        eprintln!("Conversion failed: {}", e);
+                        false
+                    }
                 }
-                Err(e) => {
-                    eprintln!("Conversion failed: {}", e);
-                    std::process::exit(1);
+            };
+
+            // Watch before the first pass so files arriving during it are not missed
+            let watcher = if watch {
+                match FolderWatcher::new(&input, Some(std::path::Path::new(&output))) {
+                    Ok(watcher) => Some(watcher),
+                    Err(e) => {
+                        eprintln!("Conversion failed: {:#}", e);
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                None
+            };
+
+            if !run() {
+                std::process::exit(1);
+            }
+
+            if let Some(watcher) = watcher {
+                logging::info(format_args!(
+                    "Watching {} for new files (Ctrl+C to stop)...",
+                    input.display()
+                ));
+                loop {
+                    if let Err(e) = watcher.wait_for_changes(WATCH_QUIET_PERIOD) {
+                        eprintln!("Watch stopped: {:#}", e);
+                        std::process::exit(1);
+                    }
+                    run();
                 }
             }
         }
         Commands::Anonymize {
             input,
+            input_list,
             output,
-            tags,
+            mut tags,
+            tags_file,
             replacement,
+            force,
+            exclude,
+            include_hidden,
+            lenient_preamble,
+            max_files,
+            only_modality,
+            output_subdir,
+            relative_to,
+            flatten_output,
+            add_extension,
+            preserve_names,
+            overwrite,
+            on_error,
+            events,
+            force_sop_class,
+            seed,
+            salt,
+            strip_pixel_data,
+            and_convert,
+            hash,
+            csv_delimiter,
+            csv_bom,
+            columns,
         } => {
-            println!("Starting anonymization...");
-            println!("Input: {}", input);
-            println!("Output: {}", output);
-            println!("Tags: {:?}", tags);
+            if let Some(format) = events {
+                logging::enable_events(format);
+            }
+            if let Some(tags_file) = tags_file {
+                match read_tags_file(&tags_file) {
+                    Ok(file_tags) => tags.extend(file_tags),
+                    Err(e) => {
+                        eprintln!("Anonymization failed: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            let (input, files) = match resolve_input(input, input_list) {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    eprintln!("Anonymization failed: {}", e);
+                    std::process::exit(1);
+                }
+            };
+
+            logging::info("Starting anonymization...");
+            logging::info(format_args!("Input: {}", input.display()));
+            logging::info(format_args!("Output: {}", output));
+            logging::info(format_args!("Tags: {:?}", tags));
+
+            // Exact tags keep their audit rows even when absent from a file;
+            // patterns are expanded per file
+            let mut tag_patterns = Vec::new();
+            let tags = tags
+                .into_iter()
+                .filter_map(|(pattern, value)| {
+                    let value = value.unwrap_or_else(|| replacement.clone());
+                    match pattern.as_tag() {
+                        Some(tag) => Some((tag, value)),
+                        None => {
+                            tag_patterns.push((pattern, value));
+                            None
+                        }
+                    }
+                })
+                .collect();
 
             let res = crate::logic::anonymize::anonymize_dicom(
-                std::path::Path::new(&input),
+                &input,
                 std::path::Path::new(&output),
                 tags,
-                replacement,
+                &AnonymizeOptions {
+                    discovery: DiscoveryOptions {
+                        force,
+                        exclude,
+                        files,
+                        max_files,
+                        only_modality,
+                        include_hidden,
+                        lenient_preamble,
+                    },
+                    flatten_output,
+                    add_extension,
+                    preserve_names,
+                    output_subdir,
+                    relative_to: relative_to.map(PathBuf::from),
+                    overwrite,
+                    on_error,
+                    hash,
+                    force_sop_class,
+                    seed,
+                    pseudonym_salt: salt,
+                    tag_patterns,
+                    strip_pixel_data,
+                    and_convert,
+                    csv_format: CsvFormat {
+                        delimiter: csv_delimiter,
+                        bom: csv_bom,
+                    },
+                    metadata_columns: columns.unwrap_or_default(),
+                    ..Default::default()
+                },
+                |discovery| {
+                    logging::event("discovery", &discovery);
+                    logging::debug(format_args!(
+                        "Discovering files: {} found in {} scanned",
+                        discovery.found, discovery.scanned
+                    ));
+                },
                 |progress| {
+                    logging::event("progress", &progress);
                     let percentage = if progress.total > 0 {
                         (progress.current as f64 / progress.total as f64) * 100.0
                     } else {
                         0.0
                     };
-                    println!(
+                    logging::info(format_args!(
                         "Progress: {}/{} ({:.1}%) - {} [{}]",
                         progress.current,
                         progress.total,
                         percentage,
                         progress.filename,
                         progress.status
-                    );
+                    ));
                 },
                 |log| {
-                    println!("[{}] {}", log.status, log.message);
+                    logging::event("log", &log);
+                    logging::info(format_args!("[{}] {}", log.status, log.message));
+                    logging::debug(format_args!("    {}", log.file_path));
                 },
             );
 
@@ -157,7 +1508,19 @@ pub fn run_cli(cli: Cli) {
                     println!("Successful: {}", report.successful);
                     println!("Skipped: {}", report.skipped);
                     println!("Failed: {}", report.failed);
+                    if and_convert {
+                        println!("Converted to PNG: {}", report.converted);
+                        println!("PNG failures: {}", report.convert_failed_files.len());
+                    }
                     println!("Output folder: {:?}", report.output_folder);
+                    if let Some(path) = &report.aborted_by {
+                        eprintln!("Anonymization aborted after {} failed", path);
+                        std::process::exit(1);
+                    }
+                    if let Some(error) = &report.write_error {
+                        eprintln!("Anonymization stopped: {}", error);
+                        std::process::exit(1);
+                    }
                 }
                 Err(e) => {
                     eprintln!("Anonymization failed: {}", e);
@@ -165,5 +1528,284 @@ pub fn run_cli(cli: Cli) {
                 }
             }
         }
+        Commands::ConvertVolume { input, output } => {
+            logging::info("Starting volume conversion...");
+            logging::info(format_args!("Input: {}", input));
+            logging::info(format_args!("Output: {}", output));
+
+            match crate::logic::nifti::convert_dicom_to_nifti(
+                std::path::Path::new(&input),
+                std::path::Path::new(&output),
+            ) {
+                Ok(report) => {
+                    println!("Volume conversion completed successfully!");
+                    println!("Series written: {}", report.series_written);
+                    println!("Series skipped: {}", report.series_skipped);
+                    println!("Output folder: {:?}", report.output_folder);
+                }
+                Err(e) => {
+                    eprintln!("Volume conversion failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::ExtractSr { input, output } => {
+            logging::info("Starting SR extraction...");
+            logging::info(format_args!("Input: {}", input));
+            logging::info(format_args!("Output: {}", output));
+
+            match crate::logic::sr::extract_sr_text(
+                std::path::Path::new(&input),
+                std::path::Path::new(&output),
+            ) {
+                Ok(report) => {
+                    println!("SR extraction completed successfully!");
+                    println!("Documents written: {}", report.documents_written);
+                    println!("Not SR: {}", report.skipped);
+                    println!("Failed: {}", report.failed_files.len());
+                    println!("Output folder: {:?}", report.output_folder);
+                }
+                Err(e) => {
+                    eprintln!("SR extraction failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Transcode { input, output } => {
+            logging::info("Starting transcoding...");
+            logging::info(format_args!("Input: {}", input));
+            logging::info(format_args!("Output: {}", output));
+
+            match crate::logic::transcode::transcode_to_uncompressed(
+                std::path::Path::new(&input),
+                std::path::Path::new(&output),
+            ) {
+                Ok(report) => {
+                    println!("Transcoding completed successfully!");
+                    println!("Decompressed: {}", report.decompressed);
+                    println!("Already uncompressed: {}", report.already_uncompressed);
+                    println!("Failed: {}", report.failed_files.len());
+                    println!("Output folder: {:?}", report.output_folder);
+                }
+                Err(e) => {
+                    eprintln!("Transcoding failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Count {
+            input,
+            force,
+            exclude,
+            include_hidden,
+            lenient_preamble,
+        } => {
+            let input = std::path::Path::new(&input);
+            if !input.is_dir() {
+                eprintln!(
+                    "Count failed: Input folder '{}' does not exist",
+                    input.display()
+                );
+                std::process::exit(1);
+            }
+            match count_dicom_files(
+                input,
+                &DiscoveryOptions {
+                    force,
+                    exclude,
+                    include_hidden,
+                    lenient_preamble,
+                    ..Default::default()
+                },
+            ) {
+                Ok(count) => {
+                    println!("DICOM files: {}", count.total);
+                    println!("  By extension: {}", count.by_extension);
+                    println!("  By DICM magic: {}", count.by_magic);
+                    if lenient_preamble {
+                        println!("  By DICM without preamble: {}", count.by_no_preamble);
+                    }
+                    if force {
+                        println!("  By --force: {}", count.by_force);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Count failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Extract { input, tag, output } => {
+            logging::info("Starting tag extraction...");
+            logging::info(format_args!("Input: {}", input));
+            logging::info(format_args!("Output: {}", output));
+
+            match crate::logic::stats::extract_tag_values(
+                std::path::Path::new(&input),
+                tag.0,
+                tag.1,
+                std::path::Path::new(&output),
+            ) {
+                Ok(report) => {
+                    println!("Tag extraction completed successfully!");
+                    println!("Rows: {}", report.rows);
+                    println!("Unreadable: {}", report.unreadable);
+                    println!("Output file: {:?}", report.output_file);
+                }
+                Err(e) => {
+                    eprintln!("Tag extraction failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Stats {
+            input,
+            tags,
+            output,
+            missing_label,
+            empty_label,
+            binary_label,
+        } => {
+            let input = std::path::Path::new(&input);
+            if !input.is_dir() {
+                eprintln!(
+                    "Stats failed: Input folder '{}' does not exist",
+                    input.display()
+                );
+                std::process::exit(1);
+            }
+            let headers = crate::logic::stats::HeaderCache::default();
+            let placeholders = crate::logic::stats::Placeholders {
+                missing: missing_label,
+                empty: empty_label,
+                binary: binary_label,
+            };
+            let stats = match crate::logic::stats::calculate_stats(
+                input,
+                tags,
+                &headers,
+                &placeholders,
+                |_| {},
+            ) {
+                Ok(stats) => stats,
+                Err(e) => {
+                    eprintln!("Stats failed: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            for stat in &stats {
+                println!("{} ({:04X},{:04X}):", stat.name, stat.group, stat.element);
+                for (value, count) in stat.sorted_counts() {
+                    println!("{:>8}  {}", count, value);
+                }
+            }
+            if let Some(output) = output {
+                if let Err(e) =
+                    crate::logic::stats::write_stats_csv(&stats, std::path::Path::new(&output))
+                {
+                    eprintln!("Stats failed: {}", e);
+                    std::process::exit(1);
+                }
+                println!("Output file: {:?}", output);
+            }
+        }
+        Commands::CheckSeries { input, strict } => {
+            match crate::logic::stats::check_series_geometry(std::path::Path::new(&input)) {
+                Ok(report) => {
+                    println!("Series checked: {}", report.series);
+                    println!("Files without SeriesInstanceUID: {}", report.without_series);
+                    println!("Unreadable: {}", report.unreadable);
+                    println!("Inconsistent series: {}", report.inconsistent.len());
+                    for series in &report.inconsistent {
+                        println!("{} ({} files):", series.series_uid, series.file_count);
+                        for (geometry, count) in &series.geometries {
+                            println!("{:>8}  {}", count, geometry);
+                        }
+                    }
+                    if strict && !report.inconsistent.is_empty() {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Series check failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::ScanPhi { input, output } => {
+            let report = match crate::logic::stats::scan_phi(std::path::Path::new(&input)) {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("PHI scan failed: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            println!("Files: {}", report.files.len());
+            println!("Unreadable: {}", report.unreadable);
+            println!("{:>10}{:>8}{:>10}  Tag", "Populated", "Empty", "Missing");
+            for tag in &report.tags {
+                println!(
+                    "{:>10}{:>8}{:>10}  {} ({:04X},{:04X})",
+                    tag.populated, tag.empty, tag.missing, tag.name, tag.group, tag.element
+                );
+            }
+            if let Some(output) = output {
+                if let Err(e) =
+                    crate::logic::stats::write_phi_csv(&report, std::path::Path::new(&output))
+                {
+                    eprintln!("PHI scan failed: {}", e);
+                    std::process::exit(1);
+                }
+                println!("Output file: {:?}", output);
+            }
+        }
+        Commands::ConvertOne { input, window } => {
+            logging::reserve_stdout();
+            let mut stdout = std::io::stdout().lock();
+            if stdout.is_terminal() {
+                eprintln!("Conversion failed: refusing to write PNG data to a terminal; redirect or pipe stdout");
+                std::process::exit(1);
+            }
+            let written = crate::logic::convert::render_preview(Path::new(&input), window)
+                .and_then(|png| {
+                    stdout.write_all(&png)?;
+                    stdout.flush()?;
+                    Ok(())
+                });
+            if let Err(e) = written {
+                eprintln!("Conversion failed: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Codecs => {
+            println!("{:<8}{:<8}Transfer syntax", "Decode", "Encode");
+            for codec in crate::logic::transfer_syntax::codec_support() {
+                let yes_no = |supported: bool| if supported { "yes" } else { "no" };
+                println!(
+                    "{:<8}{:<8}{} ({})",
+                    yes_no(codec.decode),
+                    yes_no(codec.encode),
+                    codec.name,
+                    codec.uid
+                );
+            }
+        }
+        Commands::TsSummary { input } => {
+            match crate::logic::transfer_syntax::summarize_transfer_syntaxes(std::path::Path::new(
+                &input,
+            )) {
+                Ok(summary) => {
+                    let total: usize = summary.iter().map(|entry| entry.count).sum();
+                    println!("Transfer syntax distribution ({} files):", total);
+                    for entry in summary {
+                        println!("{:>8}  {} ({})", entry.count, entry.name, entry.uid);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Transfer syntax summary failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
     }
 }
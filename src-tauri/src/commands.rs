@@ -1,20 +1,30 @@
+use std::sync::atomic::Ordering;
 use tauri::{AppHandle, Emitter};
 use tauri_plugin_opener::OpenerExt;
 
 #[tauri::command]
 pub async fn convert_dicom(
     app: AppHandle,
+    runs: tauri::State<'_, crate::logic::workflow::ActiveRuns>,
     input: String,
     output: String,
     skip_excel: bool,
     flatten_output: bool,
 ) -> Result<crate::logic::workflow::ConversionReport, String> {
-    use crate::logic::workflow::convert_dicom_to_png;
+    use crate::logic::workflow::{convert_dicom_to_png, ConvertOptions};
+    let run = start_run(&app, &runs);
     match convert_dicom_to_png(
         std::path::Path::new(&input),
         std::path::Path::new(&output),
-        !skip_excel,
-        flatten_output,
+        &ConvertOptions {
+            save_excel: !skip_excel,
+            flatten_output,
+            cancel: run.cancel.clone(),
+            ..Default::default()
+        },
+        |discovery| {
+            let _ = app.emit("discovery_progress", discovery);
+        },
         |progress| {
             let _ = app.emit("conversion_progress", progress);
         },
@@ -40,17 +50,25 @@ pub async fn convert_dicom(
 #[tauri::command]
 pub async fn anonymize_dicom(
     app: AppHandle,
+    runs: tauri::State<'_, crate::logic::workflow::ActiveRuns>,
     input: String,
     output: String,
     tags: Vec<(u16, u16)>,
     replacement: String,
 ) -> Result<crate::logic::anonymize::AnonymizationReport, String> {
     use crate::logic::anonymize::anonymize_dicom;
+    let run = start_run(&app, &runs);
     match anonymize_dicom(
         std::path::Path::new(&input),
         std::path::Path::new(&output),
-        tags,
-        replacement,
+        with_replacement(tags, &replacement),
+        &crate::logic::anonymize::AnonymizeOptions {
+            cancel: run.cancel.clone(),
+            ..Default::default()
+        },
+        |discovery| {
+            let _ = app.emit("discovery_progress", discovery);
+        },
         |progress| {
             let _ = app.emit("anonymization_progress", progress);
         },
@@ -72,6 +90,13 @@ pub async fn anonymize_dicom(
     }
 }
 
+/// Pair every tag with the same replacement value.
+fn with_replacement(tags: Vec<(u16, u16)>, replacement: &str) -> Vec<(dicom::core::Tag, String)> {
+    tags.into_iter()
+        .map(|(group, element)| (dicom::core::Tag(group, element), replacement.to_string()))
+        .collect()
+}
+
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -80,6 +105,18 @@ pub struct DicomToPngInput {
     pub output: String,
     pub skip_excel: bool,
     pub flatten_output: bool,
+    #[serde(default)]
+    pub overwrite: bool,
+    #[serde(default)]
+    pub metadata_only_success: bool,
+    #[serde(default)]
+    pub series_progress: bool,
+    #[serde(default)]
+    pub if_exists: crate::logic::workflow::IfExists,
+    #[serde(default)]
+    pub modality_defaults: bool,
+    #[serde(default)]
+    pub verify_output: bool,
 }
 
 #[derive(Deserialize)]
@@ -88,6 +125,12 @@ pub struct AnonymizeDicomInput {
     pub output: String,
     pub tags: Vec<(u16, u16)>,
     pub replacement: String,
+    #[serde(default)]
+    pub flatten_output: bool,
+    #[serde(default)]
+    pub overwrite: bool,
+    #[serde(default)]
+    pub and_convert: bool,
 }
 
 #[derive(Deserialize, Default)]
@@ -99,20 +142,38 @@ pub struct DicomProcessInput {
 #[tauri::command]
 pub async fn process_dicom(
     app: AppHandle,
+    runs: tauri::State<'_, crate::logic::workflow::ActiveRuns>,
     input: DicomProcessInput,
 ) -> Result<ProcessReport, String> {
     use crate::logic::anonymize::anonymize_dicom as do_anonymize;
-    use crate::logic::workflow::convert_dicom_to_png;
+    use crate::logic::workflow::{convert_dicom_to_png, ConvertOptions};
 
-    let mut report = ProcessReport::default();
+    let run = start_run(&app, &runs);
+    let mut report = ProcessReport {
+        run_id: run.id.clone(),
+        ..Default::default()
+    };
 
     // Process conversion if requested
     if let Some(convert_input) = input.convert {
         match convert_dicom_to_png(
             std::path::Path::new(&convert_input.input),
             std::path::Path::new(&convert_input.output),
-            !convert_input.skip_excel,
-            convert_input.flatten_output,
+            &ConvertOptions {
+                save_excel: !convert_input.skip_excel,
+                flatten_output: convert_input.flatten_output,
+                overwrite: convert_input.overwrite,
+                metadata_only_success: convert_input.metadata_only_success,
+                series_progress: convert_input.series_progress,
+                if_exists: convert_input.if_exists,
+                modality_defaults: convert_input.modality_defaults,
+                verify_output: convert_input.verify_output,
+                cancel: run.cancel.clone(),
+                ..Default::default()
+            },
+            |discovery| {
+                let _ = app.emit("discovery_progress", discovery);
+            },
             |progress| {
                 let _ = app.emit("conversion_progress", progress);
             },
@@ -134,13 +195,27 @@ pub async fn process_dicom(
         }
     }
 
+    if run.cancel.load(Ordering::Relaxed) {
+        report.summary = ProcessSummary::of(&report);
+        return Ok(report);
+    }
+
     // Process anonymization if requested
     if let Some(anonymize_input) = input.anonymize {
         match do_anonymize(
             std::path::Path::new(&anonymize_input.input),
             std::path::Path::new(&anonymize_input.output),
-            anonymize_input.tags,
-            anonymize_input.replacement,
+            with_replacement(anonymize_input.tags, &anonymize_input.replacement),
+            &crate::logic::anonymize::AnonymizeOptions {
+                flatten_output: anonymize_input.flatten_output,
+                overwrite: anonymize_input.overwrite,
+                and_convert: anonymize_input.and_convert,
+                cancel: run.cancel.clone(),
+                ..Default::default()
+            },
+            |discovery| {
+                let _ = app.emit("discovery_progress", discovery);
+            },
             |progress| {
                 let _ = app.emit("anonymization_progress", progress);
             },
@@ -165,13 +240,82 @@ pub async fn process_dicom(
         }
     }
 
+    report.summary = ProcessSummary::of(&report);
     Ok(report)
 }
 
+/// Register a run and tell the frontend its id, which `cancel_conversion`
+/// takes, before any work starts.
+fn start_run<'a>(
+    app: &AppHandle,
+    runs: &'a crate::logic::workflow::ActiveRuns,
+) -> crate::logic::workflow::RunGuard<'a> {
+    let run = runs.start();
+    let _ = app.emit("run_started", &run.id);
+    run
+}
+
+/// Stop the conversion/anonymization with id `run_id`, as announced by the
+/// `run_started` event. Files already in progress finish; the run's command
+/// then returns a report with `cancelled` set. Returns false when the run
+/// is no longer active.
+#[tauri::command]
+pub async fn cancel_conversion(
+    runs: tauri::State<'_, crate::logic::workflow::ActiveRuns>,
+    run_id: String,
+) -> Result<bool, String> {
+    Ok(runs.cancel(&run_id))
+}
+
 #[derive(serde::Serialize, Default)]
 pub struct ProcessReport {
+    /// Id the run was announced with in `run_started`
+    pub run_id: String,
     pub conversion: Option<crate::logic::workflow::ConversionReport>,
     pub anonymization: Option<crate::logic::anonymize::AnonymizationReport>,
+    pub summary: ProcessSummary,
+}
+
+/// Totals over both phases, so the frontend can show one overall status.
+/// A file processed by both phases counts once per phase.
+#[derive(serde::Serialize, Default)]
+pub struct ProcessSummary {
+    pub total: usize,
+    pub successful: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// Failed files of either phase, each name listed once
+    pub failed_files: Vec<String>,
+    pub cancelled: bool,
+}
+
+impl ProcessSummary {
+    fn of(report: &ProcessReport) -> Self {
+        let mut summary = Self::default();
+        if let Some(conversion) = &report.conversion {
+            summary.total += conversion.total;
+            summary.successful += conversion.successful;
+            summary.failed += conversion.failed;
+            summary.skipped += conversion.skipped_non_image;
+            summary
+                .failed_files
+                .extend(conversion.failed_files.iter().cloned());
+            summary.cancelled |= conversion.cancelled;
+        }
+        if let Some(anonymization) = &report.anonymization {
+            summary.total += anonymization.total;
+            summary.successful += anonymization.successful;
+            summary.failed += anonymization.failed;
+            summary.skipped += anonymization.skipped;
+            for file in &anonymization.failed_files {
+                if !summary.failed_files.contains(file) {
+                    summary.failed_files.push(file.clone());
+                }
+            }
+            summary.cancelled |= anonymization.cancelled;
+        }
+        summary
+    }
 }
 
 #[tauri::command]
@@ -179,6 +323,27 @@ pub async fn get_dicom_tags(path: String) -> Result<Vec<crate::logic::tags::Dico
     crate::logic::tags::read_all_tags(std::path::Path::new(&path)).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+pub async fn preview_dicom(
+    path: String,
+    center: Option<f64>,
+    width: Option<f64>,
+) -> Result<Vec<u8>, String> {
+    let window = center.zip(width);
+    crate::logic::convert::render_preview(std::path::Path::new(&path), window)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn preview_dicom_windowed(
+    path: String,
+    center: f64,
+    width: f64,
+) -> Result<Vec<u8>, String> {
+    crate::logic::convert::render_preview(std::path::Path::new(&path), Some((center, width)))
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub async fn list_dicom_files(folder: String) -> Result<Vec<String>, String> {
     let path = std::path::Path::new(&folder);
@@ -196,31 +361,42 @@ pub async fn list_dicom_files(folder: String) -> Result<Vec<String>, String> {
 pub async fn get_pinned_tags_stats(
     app: AppHandle,
     cache: tauri::State<'_, crate::logic::stats::StatsCache>,
+    headers: tauri::State<'_, crate::logic::stats::HeaderCache>,
     folder: String,
     tags: Vec<(u16, u16)>,
+    placeholders: Option<crate::logic::stats::Placeholders>,
 ) -> Result<Vec<crate::logic::stats::TagStat>, String> {
     let path = std::path::Path::new(&folder);
     if !path.exists() || !path.is_dir() {
         return Err("Invalid folder path".to_string());
     }
 
+    let placeholders = placeholders.unwrap_or_default();
+
     // Check cache
     {
         let cache_lock = cache.0.lock().map_err(|e| e.to_string())?;
-        if let Some(cached_result) = cache_lock.get(&(folder.clone(), tags.clone())) {
+        let key = (folder.clone(), tags.clone(), placeholders.clone());
+        if let Some(cached_result) = cache_lock.get(&key) {
             return Ok(cached_result.clone());
         }
     }
 
-    let result = crate::logic::stats::calculate_stats(path, tags.clone(), |progress| {
-        let _ = app.emit("stats_progress", progress);
-    })
+    let result = crate::logic::stats::calculate_stats(
+        path,
+        tags.clone(),
+        &headers,
+        &placeholders,
+        |progress| {
+            let _ = app.emit("stats_progress", progress);
+        },
+    )
     .map_err(|e| e.to_string())?;
 
     // Update cache
     {
         let mut cache_lock = cache.0.lock().map_err(|e| e.to_string())?;
-        cache_lock.insert((folder, tags), result.clone());
+        cache_lock.insert((folder, tags, placeholders), result.clone());
     }
 
     Ok(result)
@@ -229,16 +405,27 @@ pub async fn get_pinned_tags_stats(
 #[tauri::command]
 pub async fn get_tag_details(
     app: AppHandle,
+    headers: tauri::State<'_, crate::logic::stats::HeaderCache>,
     folder: String,
     group: u16,
     element: u16,
+    placeholders: Option<crate::logic::stats::Placeholders>,
+    summary_only: Option<bool>,
 ) -> Result<crate::logic::stats::TagDetails, String> {
     let path = std::path::Path::new(&folder);
     if !path.exists() || !path.is_dir() {
         return Err("Invalid folder path".to_string());
     }
-    crate::logic::stats::get_tag_details(path, group, element, |progress| {
-        let _ = app.emit("tag_details_progress", progress);
-    })
+    crate::logic::stats::get_tag_details(
+        path,
+        group,
+        element,
+        &headers,
+        &placeholders.unwrap_or_default(),
+        summary_only.unwrap_or(false),
+        |progress| {
+            let _ = app.emit("tag_details_progress", progress);
+        },
+    )
     .map_err(|e| e.to_string())
 }
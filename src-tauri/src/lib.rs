@@ -12,13 +12,18 @@ pub mod utils;
 pub fn run() {
     tauri::Builder::default()
         .manage(logic::stats::StatsCache::default())
+        .manage(logic::stats::HeaderCache::default())
+        .manage(logic::workflow::ActiveRuns::default())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
             convert_dicom,
             anonymize_dicom,
             process_dicom,
+            commands::cancel_conversion,
             commands::get_dicom_tags,
+            commands::preview_dicom,
+            commands::preview_dicom_windowed,
             commands::list_dicom_files,
             commands::get_pinned_tags_stats,
             commands::get_tag_details
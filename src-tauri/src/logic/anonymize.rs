@@ -1,15 +1,31 @@
-use crate::models::metadata::FileMetadata;
-use crate::utils::discovery::collect_dicom_files;
+use crate::logic::convert::{convert_object, FileOutcome};
+use crate::logic::workflow::{
+    abort_on_error, aborted_path, describe_write_error, ConvertOptions, OnError, OutputBase,
+};
+use crate::models::metadata::{
+    dicom_text, extra_tag_values, file_sha256, frame_positions, sop_class_name, FileMetadata,
+    TextDecoder, STUDY_DATE_FALLBACKS,
+};
+use crate::utils::audit::{AuditAction, AuditEntry};
+use crate::utils::discovery::{
+    collect_dicom_files_with_progress, has_dicom_extension, DiscoveryOptions, DiscoveryProgress,
+};
 use crate::utils::logging::LogEntry;
-use anyhow::{bail, Context, Result};
+use crate::utils::metadata_export::{CsvFormat, MetadataColumns};
+use crate::utils::retry::open_file_with_retry;
+use anyhow::{anyhow, bail, Context, Result};
+use dicom::core::header::Header;
 use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
 use dicom::object::open_file;
 use dicom::object::{FileDicomObject, InMemDicomObject};
 use owo_colors::OwoColorize;
 use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use uuid::Uuid;
 
 #[derive(Clone, serde::Serialize)]
@@ -21,6 +37,102 @@ pub struct AnonymizationReport {
     pub failed_files: Vec<String>,
     pub skipped_files: Vec<String>,
     pub output_folder: PathBuf,
+    /// The run was stopped early; files not yet started are not counted
+    pub cancelled: bool,
+    /// With `OnError::Abort`, the file whose failure stopped the run
+    pub aborted_by: Option<String>,
+    /// PNGs written with `and_convert`
+    pub converted: usize,
+    /// Anonymized files whose PNG could not be written
+    pub convert_failed_files: Vec<String>,
+    /// Writing the outputs failed (e.g. the disk is full) and the run was
+    /// stopped; files recorded before that are kept
+    pub write_error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AnonymizeOptions {
+    pub discovery: DiscoveryOptions,
+    /// Write to `output_folder` itself instead of `<input>_output`, with all
+    /// files directly in the output subfolder rather than mirroring the
+    /// input folders
+    pub flatten_output: bool,
+    /// Folder under the output root that holds the anonymized files; `None`
+    /// uses `dicom_file`, an empty name the root itself
+    pub output_subdir: Option<String>,
+    /// Mirror the folders below this one instead of below the input
+    /// folder; files outside it are written under their file name
+    pub relative_to: Option<PathBuf>,
+    /// Append `.dcm` to output names without a .dcm/.dicom/.ima extension
+    /// (e.g. files found by their DICM magic bytes)
+    pub add_extension: bool,
+    /// Fail instead of renaming when `flatten_output` puts two files with
+    /// the same name in one folder, so every output keeps its input name
+    pub preserve_names: bool,
+    /// Re-anonymize files whose output already exists instead of skipping them
+    pub overwrite: bool,
+    /// Record a SHA-256 of each source file in the metadata
+    pub hash: bool,
+    /// Overwrite SOPClassUID with this UID; by default the original is kept
+    pub force_sop_class: Option<String>,
+    /// Derive new UIDs from this seed and the original UID instead of at
+    /// random, so re-running on the same files gives the same output
+    pub seed: Option<u64>,
+    /// Replace PatientID with a pseudonym derived from it and this salt, so
+    /// a patient's files stay linked without exposing the real ID
+    pub pseudonym_salt: Option<String>,
+    /// Tag ranges replaced in addition to the listed tags, expanded per file
+    /// against the elements it contains, including inside sequences
+    pub tag_patterns: Vec<(TagPattern, String)>,
+    /// Remove the pixel data so only the (anonymized) header is shared
+    pub strip_pixel_data: bool,
+    /// Also render each anonymized object to `png_file/` under the output
+    /// root with the default conversion settings, in the same pass, so no
+    /// file is read twice
+    pub and_convert: bool,
+    /// Delimiter and BOM of the metadata CSVs
+    pub csv_format: CsvFormat,
+    /// Columns of the metadata CSVs
+    pub metadata_columns: MetadataColumns,
+    /// Keep going after a file fails, or stop the whole run
+    pub on_error: OnError,
+    /// Checked before each file; once set, remaining files are not started
+    pub cancel: Arc<AtomicBool>,
+}
+
+/// Tag with wildcard digits: `0010,*` matches every element of group 0010
+/// and `60xx,*` every element of the repeating groups 6000-60FF. A mask
+/// bit of 0 means "any" for that bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TagPattern {
+    pub group: u16,
+    pub group_mask: u16,
+    pub element: u16,
+    pub element_mask: u16,
+}
+
+impl TagPattern {
+    pub fn exact(tag: Tag) -> Self {
+        Self {
+            group: tag.0,
+            group_mask: 0xFFFF,
+            element: tag.1,
+            element_mask: 0xFFFF,
+        }
+    }
+
+    /// The single tag this pattern matches, if it has no wildcards.
+    pub fn as_tag(&self) -> Option<Tag> {
+        (self.group_mask == 0xFFFF && self.element_mask == 0xFFFF)
+            .then_some(Tag(self.group, self.element))
+    }
+
+    /// Group length elements (gggg,0000) only match when named exactly.
+    pub fn matches(&self, tag: Tag) -> bool {
+        tag.0 & self.group_mask == self.group & self.group_mask
+            && tag.1 & self.element_mask == self.element & self.element_mask
+            && (tag.1 != 0x0000 || self.element_mask == 0xFFFF)
+    }
 }
 
 #[derive(Clone, serde::Serialize)]
@@ -31,21 +143,24 @@ pub struct ProgressPayload {
     pub status: String,
 }
 
-pub fn anonymize_dicom<F, G>(
+pub fn anonymize_dicom<D, F, G>(
     input_folder: &Path,
     output_folder: &Path,
-    tags_to_anonymize: Vec<(u16, u16)>, // Group, Element
-    replacement_value: String,
+    tags_to_anonymize: Vec<(Tag, String)>, // Tag and its replacement value
+    options: &AnonymizeOptions,
+    discovery_callback: D,
     progress_callback: F,
     log_callback: G,
 ) -> Result<AnonymizationReport>
 where
+    D: Fn(DiscoveryProgress),
     F: Fn(ProgressPayload) + Sync + Send,
     G: Fn(LogEntry) + Sync + Send + 'static,
 {
     if !input_folder.exists() {
         bail!("Input folder '{}' does not exist", input_folder.display());
     }
+    let output_base = OutputBase::new(input_folder, options.relative_to.as_deref())?;
 
     // Determine the input folder name for the output directory
     let input_name = input_folder
@@ -53,8 +168,13 @@ where
         .and_then(|n| n.to_str())
         .unwrap_or("dicom");
 
-    let root_output_path = output_folder.join(format!("{}_output", input_name));
-    let dicom_output_path = root_output_path.join("dicom_file");
+    let root_output_path = if options.flatten_output {
+        output_folder.to_path_buf()
+    } else {
+        output_folder.join(format!("{}_output", input_name))
+    };
+    let dicom_output_path =
+        root_output_path.join(options.output_subdir.as_deref().unwrap_or("dicom_file"));
 
     fs::create_dir_all(&dicom_output_path).with_context(|| {
         format!(
@@ -63,9 +183,44 @@ where
         )
     })?;
 
-    let dicom_files = collect_dicom_files(input_folder);
-    let total = dicom_files.len();
+    let dicom_files =
+        collect_dicom_files_with_progress(input_folder, &options.discovery, discovery_callback)?;
+    // Output paths relative to the output subfolder
+    let relative_paths: Vec<PathBuf> = dicom_files
+        .iter()
+        .map(|path| {
+            let relative = output_base.relative(path);
+            if options.add_extension && !has_dicom_extension(&relative) {
+                let mut name = relative.into_os_string();
+                name.push(".dcm");
+                PathBuf::from(name)
+            } else {
+                relative
+            }
+        })
+        .collect();
+    let relative_paths = if options.flatten_output {
+        if options.preserve_names {
+            check_unique_names(&relative_paths)?;
+        }
+        flat_output_names(&relative_paths)
+    } else {
+        relative_paths
+    };
+    let png_output_path = root_output_path.join("png_file");
+    if options.and_convert {
+        fs::create_dir_all(&png_output_path).with_context(|| {
+            format!(
+                "Unable to create output folder {}",
+                png_output_path.display()
+            )
+        })?;
+    }
+    let convert_options = ConvertOptions::default();
+    let tasks: Vec<(PathBuf, PathBuf)> = dicom_files.into_iter().zip(relative_paths).collect();
+    let total = tasks.len();
     let processed_count = AtomicUsize::new(0);
+    let aborted_by: Mutex<Option<PathBuf>> = Mutex::new(None);
 
     // Channel for sending results to the writer thread
     let (tx, rx) = std::sync::mpsc::channel::<(PathBuf, Result<AnonymizeOutcome>, PathBuf)>();
@@ -74,252 +229,822 @@ where
     let writer_handle = std::thread::spawn({
         let dicom_output_path = dicom_output_path.clone();
         let root_output_path = root_output_path.clone();
+        let audited_tags: Vec<Tag> = tags_to_anonymize.iter().map(|(tag, _)| *tag).collect();
+        let overwrite = options.overwrite;
+        let csv_format = options.csv_format;
+        let metadata_columns = options.metadata_columns.clone();
+        let cancel = options.cancel.clone();
         move || -> Result<AnonymizationReport> {
             let mut successful = 0usize;
             let mut skipped = 0usize;
             let mut failed_files = Vec::new();
             let mut skipped_files = Vec::new();
+            let mut converted = 0usize;
+            let mut convert_failed_files = Vec::new();
 
-            let mut metadata_writer =
-                crate::utils::metadata_export::MetadataWriter::new(&dicom_output_path)?;
-            let mut log_writer = crate::utils::logging::LogWriter::new(&root_output_path)?;
-
-            for (dicom_path, outcome, folder_relative) in rx {
-                match outcome {
-                    Ok(AnonymizeOutcome::Success(mut metadata)) => {
-                        metadata.folder_relative = folder_relative;
-                        metadata_writer.write_record(&metadata)?;
-                        successful += 1;
-                        let entry = LogEntry {
-                            file_name: dicom_path
-                                .file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string(),
-                            file_path: dicom_path.to_string_lossy().to_string(),
-                            success: true,
-                            status: "Success".to_string(),
-                            message: "Anonymized successfully".to_string(),
-                            conversion_type: "ANONYMIZE".to_string(),
-                        };
-                        log_callback(entry.clone());
-                        log_writer.write_entry(&entry)?;
-                    }
-                    Ok(AnonymizeOutcome::Skipped(metadata_opt)) => {
-                        if let Some(mut metadata) = metadata_opt {
+            let mut metadata_writer = crate::utils::metadata_export::MetadataWriter::new(
+                &root_output_path,
+                &dicom_output_path,
+                csv_format,
+                metadata_columns,
+            )?;
+            let mut log_writer =
+                crate::utils::logging::LogWriter::new(&root_output_path, overwrite)?;
+            let mut audit_writer = crate::utils::audit::AuditWriter::new(&root_output_path)?;
+            let mut error_writer = crate::utils::errors::ErrorWriter::new(&root_output_path)?;
+
+            // Same as for conversion: a failed write stops the run and is
+            // reported, keeping the rows already written
+            let recorded = (|| -> Result<()> {
+                for (dicom_path, outcome, folder_relative) in rx {
+                    match outcome {
+                        Ok(AnonymizeOutcome::Success(mut metadata, audit, png)) => {
                             metadata.folder_relative = folder_relative;
+                            if let Some(FileOutcome::Converted(converted, ..)) = png.as_deref() {
+                                metadata.decoded_with_fallback = converted.decoded_with_fallback;
+                            }
                             metadata_writer.write_record(&metadata)?;
+                            audit_writer.write_entries(
+                                &metadata.file_name,
+                                &dicom_path.to_string_lossy(),
+                                &audit,
+                            )?;
+                            successful += 1;
+                            let entry = LogEntry {
+                                file_name: dicom_path
+                                    .file_name()
+                                    .and_then(|n| n.to_str())
+                                    .unwrap_or("unknown")
+                                    .to_string(),
+                                file_path: dicom_path.to_string_lossy().to_string(),
+                                success: true,
+                                status: "Success".to_string(),
+                                message: "Anonymized successfully".to_string(),
+                                conversion_type: "ANONYMIZE".to_string(),
+                            };
+                            log_callback(entry.clone());
+                            log_writer.write_entry(&entry)?;
+
+                            let (success, status, message) = match png.map(|png| *png) {
+                                Some(FileOutcome::Converted(..)) => {
+                                    converted += 1;
+                                    (true, "Success", "Converted successfully".to_string())
+                                }
+                                Some(FileOutcome::Failed { error, .. }) => {
+                                    crate::utils::logging::warn(format_args!(
+                                        "{} Failed to convert {}:\n{:#}",
+                                        "✖".red(),
+                                        dicom_path.display(),
+                                        error
+                                    ));
+                                    error_writer.write_error(&dicom_path, &error)?;
+                                    convert_failed_files.push(entry.file_name.clone());
+                                    (false, "Failed", error.to_string())
+                                }
+                                // The object was already filtered by modality
+                                Some(FileOutcome::Skipped { .. }) | None => continue,
+                            };
+                            let entry = LogEntry {
+                                success,
+                                status: status.to_string(),
+                                message,
+                                conversion_type: "PNG".to_string(),
+                                ..entry
+                            };
+                            log_callback(entry.clone());
+                            log_writer.write_entry(&entry)?;
+                        }
+                        Ok(AnonymizeOutcome::Skipped {
+                            metadata: metadata_opt,
+                            reason,
+                        }) => {
+                            if let Some(mut metadata) = metadata_opt {
+                                metadata.folder_relative = folder_relative;
+                                metadata_writer.write_record(&metadata)?;
+                            }
+                            skipped += 1;
+                            skipped_files.push(
+                                dicom_path
+                                    .file_name()
+                                    .and_then(|name| name.to_str())
+                                    .map(String::from)
+                                    .unwrap_or_else(|| dicom_path.to_string_lossy().to_string()),
+                            );
+                            crate::utils::logging::info(format_args!(
+                                "{} Skipping {} ({})",
+                                "∙".cyan(),
+                                dicom_path.display(),
+                                reason
+                            ));
+                            let entry = LogEntry {
+                                file_name: dicom_path
+                                    .file_name()
+                                    .and_then(|n| n.to_str())
+                                    .unwrap_or("unknown")
+                                    .to_string(),
+                                file_path: dicom_path.to_string_lossy().to_string(),
+                                success: true,
+                                status: "Skipped".to_string(),
+                                message: reason,
+                                conversion_type: "ANONYMIZE".to_string(),
+                            };
+                            log_callback(entry.clone());
+                            log_writer.write_entry(&entry)?;
+                        }
+                        Err(err) => {
+                            crate::utils::logging::warn(format_args!(
+                                "{} Failed to anonymize {}:\n{:#}",
+                                "✖".red(),
+                                dicom_path.display(),
+                                err
+                            ));
+                            error_writer.write_error(&dicom_path, &err)?;
+                            let audit: Vec<AuditEntry> = audited_tags
+                                .iter()
+                                .map(|&tag| AuditEntry {
+                                    tag,
+                                    action: AuditAction::Error,
+                                })
+                                .collect();
+                            audit_writer.write_entries(
+                                dicom_path
+                                    .file_name()
+                                    .and_then(|n| n.to_str())
+                                    .unwrap_or("unknown"),
+                                &dicom_path.to_string_lossy(),
+                                &audit,
+                            )?;
+                            failed_files.push(
+                                dicom_path
+                                    .file_name()
+                                    .and_then(|name| name.to_str())
+                                    .map(String::from)
+                                    .unwrap_or_else(|| dicom_path.to_string_lossy().to_string()),
+                            );
+                            let entry = LogEntry {
+                                file_name: dicom_path
+                                    .file_name()
+                                    .and_then(|n| n.to_str())
+                                    .unwrap_or("unknown")
+                                    .to_string(),
+                                file_path: dicom_path.to_string_lossy().to_string(),
+                                success: false,
+                                status: "Failed".to_string(),
+                                message: err.to_string(),
+                                conversion_type: "ANONYMIZE".to_string(),
+                            };
+                            log_callback(entry.clone());
+                            log_writer.write_entry(&entry)?;
                         }
-                        skipped += 1;
-                        skipped_files.push(
-                            dicom_path
-                                .file_name()
-                                .and_then(|name| name.to_str())
-                                .map(String::from)
-                                .unwrap_or_else(|| dicom_path.to_string_lossy().to_string()),
-                        );
-                        println!(
-                            "{} Skipping {} (already exists)",
-                            "∙".cyan(),
-                            dicom_path.display()
-                        );
-                        let entry = LogEntry {
-                            file_name: dicom_path
-                                .file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string(),
-                            file_path: dicom_path.to_string_lossy().to_string(),
-                            success: true,
-                            status: "Skipped".to_string(),
-                            message: "Output file already exists".to_string(),
-                            conversion_type: "ANONYMIZE".to_string(),
-                        };
-                        log_callback(entry.clone());
-                        log_writer.write_entry(&entry)?;
-                    }
-                    Err(err) => {
-                        eprintln!(
-                            "{} Failed to anonymize {}:\n{:#}",
-                            "✖".red(),
-                            dicom_path.display(),
-                            err
-                        );
-                        failed_files.push(
-                            dicom_path
-                                .file_name()
-                                .and_then(|name| name.to_str())
-                                .map(String::from)
-                                .unwrap_or_else(|| dicom_path.to_string_lossy().to_string()),
-                        );
-                        let entry = LogEntry {
-                            file_name: dicom_path
-                                .file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string(),
-                            file_path: dicom_path.to_string_lossy().to_string(),
-                            success: false,
-                            status: "Failed".to_string(),
-                            message: err.to_string(),
-                            conversion_type: "ANONYMIZE".to_string(),
-                        };
-                        log_callback(entry.clone());
-                        log_writer.write_entry(&entry)?;
                     }
                 }
-            }
+                Ok(())
+            })();
+            let write_error = recorded.err().map(|e| {
+                cancel.store(true, Ordering::Relaxed);
+                describe_write_error(&e)
+            });
 
             Ok(AnonymizationReport {
                 total,
                 successful,
-                failed: total.saturating_sub(successful + skipped),
+                failed: failed_files.len(),
                 skipped,
                 failed_files,
                 skipped_files,
                 output_folder: root_output_path,
+                cancelled: false,
+                aborted_by: None,
+                converted,
+                convert_failed_files,
+                write_error,
             })
         }
     });
 
-    dicom_files.par_iter().for_each_with(tx, |tx, dicom_path| {
-        let current = processed_count.fetch_add(1, Ordering::Relaxed) + 1;
-        let filename = dicom_path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown")
-            .to_string();
+    let extra_tags = options.metadata_columns.tags();
+    tasks
+        .par_iter()
+        .for_each_with(tx, |tx, (dicom_path, relative_path)| {
+            if options.cancel.load(Ordering::Relaxed) {
+                return;
+            }
 
-        // Calculate output path preserving relative structure
-        let relative_path = dicom_path
-            .strip_prefix(input_folder)
-            .unwrap_or_else(|_| Path::new(&filename));
+            let current = processed_count.fetch_add(1, Ordering::Relaxed) + 1;
+            let filename = dicom_path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("unknown")
+                .to_string();
 
-        let output_path = dicom_output_path.join(relative_path);
+            let output_path = dicom_output_path.join(relative_path);
 
-        if let Some(parent) = output_path.parent() {
-            let _ = fs::create_dir_all(parent);
-        }
+            if let Some(parent) = output_path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
 
-        let folder_relative = relative_path
-            .parent()
-            .map(PathBuf::from)
-            .unwrap_or_else(|| PathBuf::from("."));
+            let folder_relative = relative_path
+                .parent()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("."));
+
+            // Check if output file already exists
+            if !options.overwrite && output_path.exists() {
+                progress_callback(ProgressPayload {
+                    current,
+                    total,
+                    filename: filename.clone(),
+                    status: "skipped".to_string(),
+                });
+
+                // We need to read metadata even if skipped to include in the report
+                // Try to read from the existing output file first, or the input file if that fails
+                let metadata = match open_file(&output_path) {
+                    Ok(obj) => extract_metadata(&obj, dicom_path, &extra_tags).ok(),
+                    Err(_) => {
+                        // Fallback to input file
+                        open_file(dicom_path)
+                            .ok()
+                            .and_then(|obj| extract_metadata(&obj, dicom_path, &extra_tags).ok())
+                    }
+                }
+                .map(|mut metadata| {
+                    if options.hash {
+                        metadata.file_hash = file_sha256(dicom_path).ok();
+                    }
+                    metadata
+                });
+
+                let _ = tx.send((
+                    dicom_path.clone(),
+                    Ok(AnonymizeOutcome::Skipped {
+                        metadata,
+                        reason: "Output file already exists".to_string(),
+                    }),
+                    folder_relative,
+                ));
+                return;
+            }
 
-        // Check if output file already exists
-        if output_path.exists() {
             progress_callback(ProgressPayload {
                 current,
                 total,
                 filename: filename.clone(),
-                status: "skipped".to_string(),
+                status: "anonymizing".to_string(),
             });
 
-            // We need to read metadata even if skipped to include in the report
-            // Try to read from the existing output file first, or the input file if that fails
-            let metadata = match open_file(&output_path) {
-                Ok(obj) => extract_metadata(&obj, dicom_path).ok(),
-                Err(_) => {
-                    // Fallback to input file
-                    open_file(dicom_path)
-                        .ok()
-                        .and_then(|obj| extract_metadata(&obj, dicom_path).ok())
+            let png_path = options.and_convert.then(|| {
+                let mut png_path = png_output_path.join(relative_path);
+                png_path.set_extension("png");
+                png_path
+            });
+            let outcome = anonymize_single_file(
+                dicom_path,
+                &output_path,
+                &tags_to_anonymize,
+                options,
+                png_path
+                    .as_deref()
+                    .map(|png_path| (png_path, &convert_options)),
+            );
+            let failed = match &outcome {
+                Ok(AnonymizeOutcome::Success(_, _, Some(png))) => {
+                    matches!(**png, FileOutcome::Failed { .. })
                 }
+                Ok(_) => false,
+                Err(_) => true,
             };
+            if failed {
+                abort_on_error(options.on_error, &options.cancel, &aborted_by, dicom_path);
+            }
 
-            let _ = tx.send((
-                dicom_path.clone(),
-                Ok(AnonymizeOutcome::Skipped(metadata)),
-                folder_relative,
-            ));
-            return;
-        }
-
-        progress_callback(ProgressPayload {
-            current,
-            total,
-            filename: filename.clone(),
-            status: "anonymizing".to_string(),
+            let _ = tx.send((dicom_path.clone(), outcome, folder_relative));
         });
 
-        let outcome = anonymize_single_file(
-            dicom_path,
-            &output_path,
-            &tags_to_anonymize,
-            &replacement_value,
-        );
+    // Wait for writer thread to finish; it still records every file that
+    // completed before a cancellation
+    let mut report = writer_handle
+        .join()
+        .map_err(|_| anyhow!("Writer thread panicked"))??;
+    report.cancelled = options.cancel.load(Ordering::Relaxed) && report.write_error.is_none();
+    report.aborted_by = aborted_path(aborted_by);
+    Ok(report)
+}
 
-        let final_outcome = match outcome {
-            Ok(meta) => Ok(AnonymizeOutcome::Success(meta)),
-            Err(e) => Err(e),
-        };
+enum AnonymizeOutcome {
+    /// With the outcome of the PNG conversion when `and_convert` is set
+    Success(FileMetadata, Vec<AuditEntry>, Option<Box<FileOutcome>>),
+    Skipped {
+        metadata: Option<FileMetadata>,
+        reason: String,
+    },
+}
 
-        let _ = tx.send((dicom_path.clone(), final_outcome, folder_relative));
-    });
+const PATIENT_IDENTITY_REMOVED: Tag = Tag(0x0012, 0x0062);
+const DEIDENTIFICATION_METHOD: Tag = Tag(0x0012, 0x0063);
 
-    // Wait for writer thread to finish
-    writer_handle.join().unwrap()
+/// `2.25` UID from a hash of `seed` and `original`. Depends only on its
+/// inputs, so it does not matter which worker thread anonymizes a file.
+fn seeded_uid(seed: u64, original: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(seed.to_le_bytes())
+        .chain_update(original.as_bytes())
+        .finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    // Version 4 layout, like the unseeded UIDs
+    let uuid = uuid::Builder::from_random_bytes(bytes).into_uuid();
+    format!("2.25.{}", uuid.as_u128())
 }
 
-enum AnonymizeOutcome {
-    Success(FileMetadata),
-    Skipped(Option<FileMetadata>),
+const PATIENT_ID: Tag = Tag(0x0010, 0x0020);
+const PATIENT_BIRTH_DATE: Tag = Tag(0x0010, 0x0030);
+const PATIENT_AGE: Tag = Tag(0x0010, 0x1010);
+
+/// Once PatientBirthDate or a study date was replaced, the original
+/// PatientAge would contradict them and give away the removed dates.
+/// Recompute it from the dates as they are now, or empty it when either is
+/// gone. Left alone when the file has no PatientAge or it was replaced
+/// itself; returns the audit entry when it was changed.
+fn reconcile_patient_age(obj: &mut InMemDicomObject, audit: &[AuditEntry]) -> Option<AuditEntry> {
+    let changed = |tag: Tag| {
+        audit.iter().any(|entry| {
+            entry.tag == tag && matches!(entry.action, AuditAction::Replaced | AuditAction::Removed)
+        })
+    };
+    let dates_changed =
+        changed(PATIENT_BIRTH_DATE) || STUDY_DATE_FALLBACKS.iter().any(|&(tag, _)| changed(tag));
+    if !dates_changed || changed(PATIENT_AGE) || obj.element(PATIENT_AGE).is_err() {
+        return None;
+    }
+
+    let date = |tag: Tag| {
+        obj.element(tag)
+            .ok()
+            .and_then(|e| e.to_str().ok())
+            .and_then(|v| chrono::NaiveDate::parse_from_str(v.trim(), "%Y%m%d").ok())
+    };
+    let birth_date = date(PATIENT_BIRTH_DATE);
+    let study_date = STUDY_DATE_FALLBACKS.iter().find_map(|&(tag, _)| date(tag));
+    let value = match birth_date
+        .zip(study_date)
+        .and_then(|(birth, at)| patient_age(birth, at))
+    {
+        Some(age) => PrimitiveValue::from(age),
+        None => PrimitiveValue::Empty,
+    };
+    obj.put_element(DataElement::new(PATIENT_AGE, VR::AS, value));
+    Some(AuditEntry {
+        tag: PATIENT_AGE,
+        action: AuditAction::Recomputed,
+    })
+}
+
+/// Age string (AS) at `at` of someone born on `birth`: whole years, or
+/// months or days below one year / month. `None` if `at` is before `birth`.
+fn patient_age(birth: chrono::NaiveDate, at: chrono::NaiveDate) -> Option<String> {
+    use chrono::Datelike;
+
+    let days = (at - birth).num_days();
+    if days < 0 {
+        return None;
+    }
+    let months = (at.year() - birth.year()) * 12 + at.month() as i32
+        - birth.month() as i32
+        - i32::from(at.day() < birth.day());
+    Some(if months >= 12 {
+        format!("{:03}Y", (months / 12).min(999))
+    } else if months >= 1 {
+        format!("{:03}M", months)
+    } else {
+        format!("{:03}D", days)
+    })
+}
+
+/// First 16 hex characters of SHA-256(PatientID + salt). The same patient
+/// always gets the same pseudonym within a run (and across runs with the
+/// same salt); without the salt it cannot be recomputed from a known ID.
+fn patient_pseudonym(patient_id: &str, salt: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(patient_id.as_bytes())
+        .chain_update(salt.as_bytes())
+        .finalize();
+    digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `preserve_names` with `flatten_output`: renaming is not allowed, so
+/// two inputs with the same file name are an error.
+fn check_unique_names(paths: &[PathBuf]) -> Result<()> {
+    let mut seen = HashSet::new();
+    for path in paths {
+        let name = path.file_name().unwrap_or_default();
+        if !seen.insert(name) {
+            bail!(
+                "Several input files are named '{}'; flattening them would require renaming",
+                name.to_string_lossy()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// File names for `flatten_output`. Files from different input folders may
+/// share a name, so later ones get a numeric suffix (`ct1.dcm`, `ct1_1.dcm`).
+fn flat_output_names(files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut used = HashSet::new();
+    files
+        .iter()
+        .map(|path| {
+            let name = path
+                .file_name()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("unknown"));
+            if used.insert(name.clone()) {
+                return name;
+            }
+            // Only split off real extensions, not the last part of a UID file name
+            let extension = name
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .filter(|ext| ext.chars().all(|c| c.is_ascii_alphabetic()));
+            let stem = match extension {
+                Some(_) => name.file_stem().unwrap_or_default(),
+                None => name.as_os_str(),
+            }
+            .to_string_lossy()
+            .to_string();
+            (1..)
+                .map(|n| {
+                    let mut candidate = PathBuf::from(format!("{}_{}", stem, n));
+                    if let Some(extension) = extension {
+                        candidate.set_extension(extension);
+                    }
+                    candidate
+                })
+                .find(|candidate| used.insert(candidate.clone()))
+                .unwrap_or(name)
+        })
+        .collect()
+}
+
+/// Replace `tag` in `obj` and in the items of all of its sequences, at any
+/// depth, keeping the VR of each occurrence. Returns whether it was found.
+fn replace_tag(obj: &mut InMemDicomObject, tag: Tag, replacement: &str) -> Result<bool> {
+    let mut found = false;
+    if let Ok(elem) = obj.element(tag) {
+        let vr = elem.vr();
+        // Construct new element with same VR but replaced value
+        let value = coerce_replacement(vr, replacement).with_context(|| {
+            format!(
+                "Cannot use replacement '{}' for tag ({:04X},{:04X}) with VR {}",
+                replacement,
+                tag.0,
+                tag.1,
+                vr.to_string()
+            )
+        })?;
+        obj.put_element(DataElement::new(tag, vr, value));
+        found = true;
+    }
+
+    let sequences: Vec<Tag> = obj
+        .iter()
+        .filter(|elem| elem.vr() == VR::SQ)
+        .map(|elem| elem.tag())
+        .collect();
+    let mut result = Ok(());
+    for sequence in sequences {
+        obj.update_value(sequence, |value| {
+            let Some(items) = value.items_mut() else {
+                return;
+            };
+            for item in items.iter_mut() {
+                match replace_tag(item, tag, replacement) {
+                    Ok(found_in_item) => found |= found_in_item,
+                    Err(e) if result.is_ok() => result = Err(e),
+                    Err(_) => {}
+                }
+            }
+        });
+    }
+    result.map(|()| found)
+}
+
+/// Replace every element matching `pattern` in `obj` and in the items of its
+/// sequences, at any depth, like `replace_tag`. One replacement string
+/// rarely fits every VR in a range, so elements that cannot hold it are
+/// emptied, and matching sequences removed. Returns one entry per tag and
+/// action.
+fn replace_pattern(
+    obj: &mut InMemDicomObject,
+    pattern: &TagPattern,
+    replacement: &str,
+) -> Vec<AuditEntry> {
+    fn record(audit: &mut Vec<AuditEntry>, entry: AuditEntry) {
+        if !audit
+            .iter()
+            .any(|seen| seen.tag == entry.tag && seen.action == entry.action)
+        {
+            audit.push(entry);
+        }
+    }
+
+    let mut audit = Vec::new();
+    let elements: Vec<(Tag, VR)> = obj.iter().map(|elem| (elem.tag(), elem.vr())).collect();
+    for (tag, vr) in elements {
+        if pattern.matches(tag) {
+            let action = if vr == VR::SQ {
+                obj.remove_element(tag);
+                AuditAction::Removed
+            } else {
+                let (value, action) = match coerce_replacement(vr, replacement) {
+                    Ok(value) => (value, AuditAction::Replaced),
+                    Err(_) => (PrimitiveValue::Empty, AuditAction::Emptied),
+                };
+                obj.put_element(DataElement::new(tag, vr, value));
+                action
+            };
+            record(&mut audit, AuditEntry { tag, action });
+        } else if vr == VR::SQ {
+            let mut nested = Vec::new();
+            obj.update_value(tag, |value| {
+                if let Some(items) = value.items_mut() {
+                    for item in items.iter_mut() {
+                        nested.extend(replace_pattern(item, pattern, replacement));
+                    }
+                }
+            });
+            for entry in nested {
+                record(&mut audit, entry);
+            }
+        }
+    }
+    audit
 }
 
+/// Pixel Data plus its float and double precision variants.
+const PIXEL_DATA_TAGS: [Tag; 3] = [
+    Tag(0x7FE0, 0x0010),
+    Tag(0x7FE0, 0x0008),
+    Tag(0x7FE0, 0x0009),
+];
+
 fn anonymize_single_file(
     input_path: &Path,
     output_path: &Path,
-    tags_to_anonymize: &[(u16, u16)],
-    replacement_value: &str,
-) -> Result<FileMetadata> {
-    let mut obj = open_file(input_path).context("Failed to open DICOM file")?;
-
-    // Anonymize tags
-    for &(group, element) in tags_to_anonymize {
-        let tag = Tag(group, element);
-        if let Ok(elem) = obj.element(tag) {
-            let vr = elem.vr();
-            // Construct new element with same VR but replaced value
-            // Note: This assumes the replacement value string is valid for the VR.
-            // For complex VRs this might fail or be invalid, but for standard anonymization it's usually fine.
-            let new_elem =
-                DataElement::new(tag, vr, PrimitiveValue::from(replacement_value.to_string()));
-            obj.put_element(new_elem);
+    tags_to_anonymize: &[(Tag, String)],
+    options: &AnonymizeOptions,
+    convert_to: Option<(&Path, &ConvertOptions)>,
+) -> Result<AnonymizeOutcome> {
+    let mut obj = open_file_with_retry(input_path).context("Failed to open DICOM file")?;
+    let extra_tags = options.metadata_columns.tags();
+
+    // Re-running on anonymized output would only re-randomize its UIDs
+    if obj
+        .element(PATIENT_IDENTITY_REMOVED)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .is_some_and(|value| value.trim() == "YES")
+    {
+        let mut metadata = extract_metadata(&obj, input_path, &extra_tags)?;
+        if options.hash {
+            metadata.file_hash = file_sha256(input_path).ok();
+        }
+        return Ok(AnonymizeOutcome::Skipped {
+            metadata: Some(metadata),
+            reason: "Already anonymized (PatientIdentityRemoved is YES)".to_string(),
+        });
+    }
+
+    let metadata = extract_metadata(&obj, input_path, &extra_tags)?;
+    if let Some(reason) = options
+        .discovery
+        .modality_filter(metadata.modality.as_deref())
+    {
+        return Ok(AnonymizeOutcome::Skipped {
+            metadata: Some(metadata),
+            reason,
+        });
+    }
+    let mut audit = Vec::with_capacity(tags_to_anonymize.len());
+    // Read before the tag replacements, which may include PatientID
+    let patient_id = dicom_text(&obj, PATIENT_ID);
+
+    // Anonymize tags, including inside sequence items
+    for (tag, replacement_value) in tags_to_anonymize {
+        let tag = *tag;
+        let action = if replace_tag(&mut obj, tag, replacement_value)? {
+            AuditAction::Replaced
+        } else {
+            AuditAction::NotPresent
+        };
+        audit.push(AuditEntry { tag, action });
+    }
+
+    for (pattern, replacement_value) in &options.tag_patterns {
+        audit.extend(replace_pattern(&mut obj, pattern, replacement_value));
+    }
+
+    if let Some(entry) = reconcile_patient_age(&mut obj, &audit) {
+        audit.push(entry);
+    }
+
+    if let (Some(salt), Some(patient_id)) = (&options.pseudonym_salt, patient_id) {
+        replace_tag(&mut obj, PATIENT_ID, &patient_pseudonym(&patient_id, salt))?;
+        audit.push(AuditEntry {
+            tag: PATIENT_ID,
+            action: AuditAction::Pseudonymized,
+        });
+    }
+
+    // Burned-in annotations cannot be de-identified by tag replacement,
+    // so header-only exports drop the image entirely
+    if options.strip_pixel_data {
+        for tag in PIXEL_DATA_TAGS {
+            if obj.remove_element(tag) {
+                audit.push(AuditEntry {
+                    tag,
+                    action: AuditAction::Removed,
+                });
+            }
         }
     }
 
+    // Mark the output as de-identified, so it is skipped if anonymized again
+    obj.put_element(DataElement::new(
+        PATIENT_IDENTITY_REMOVED,
+        VR::CS,
+        PrimitiveValue::from("YES"),
+    ));
+    obj.put_element(DataElement::new(
+        DEIDENTIFICATION_METHOD,
+        VR::LO,
+        PrimitiveValue::from("DICOM App tag replacement"),
+    ));
+
     // Regenerate SOP Instance UID
     let sop_class_uid_tag = Tag(0x0008, 0x0016);
     let sop_instance_uid_tag = Tag(0x0008, 0x0018);
 
-    // Set SOP Class UID to CT Image Storage (as per python script)
-    // 1.2.840.10008.5.1.4.1.1.2
-    let class_uid_elem = DataElement::new(
-        sop_class_uid_tag,
-        VR::UI,
-        PrimitiveValue::from("1.2.840.10008.5.1.4.1.1.2"),
-    );
-    obj.put_element(class_uid_elem);
+    // SOP Class UID is kept unless explicitly overridden, so MR/US/CR
+    // objects are not relabeled as CT
+    if let Some(sop_class) = &options.force_sop_class {
+        let class_uid_elem = DataElement::new(
+            sop_class_uid_tag,
+            VR::UI,
+            PrimitiveValue::from(sop_class.as_str()),
+        );
+        obj.put_element(class_uid_elem);
+    }
 
     // Generate a new UUID for SOP Instance UID
-    let new_uid = format!("2.25.{}", Uuid::new_v4().as_u128());
-    let instance_uid_elem =
-        DataElement::new(sop_instance_uid_tag, VR::UI, PrimitiveValue::from(new_uid));
+    let new_uid = match options.seed {
+        Some(seed) => {
+            let original = obj
+                .element(sop_instance_uid_tag)
+                .ok()
+                .and_then(|e| e.to_str().ok())
+                .map(|uid| uid.trim_end_matches(['\0', ' ']).to_string())
+                .unwrap_or_else(|| input_path.to_string_lossy().to_string());
+            seeded_uid(seed, &original)
+        }
+        None => format!("2.25.{}", Uuid::new_v4().as_u128()),
+    };
+    let instance_uid_elem = DataElement::new(
+        sop_instance_uid_tag,
+        VR::UI,
+        PrimitiveValue::from(new_uid.as_str()),
+    );
     obj.put_element(instance_uid_elem);
 
+    // The file meta group repeats both UIDs; keep it consistent with the
+    // data set so it does not contradict it or leak the original instance
+    obj.update_meta(|meta| {
+        meta.media_storage_sop_instance_uid = new_uid;
+        if let Some(sop_class) = &options.force_sop_class {
+            meta.media_storage_sop_class_uid = sop_class.clone();
+        }
+    });
+
     // Save
     obj.write_to_file(output_path)
         .context("Failed to save anonymized file")?;
 
-    extract_metadata(&obj, input_path)
+    let mut metadata = extract_metadata(&obj, input_path, &extra_tags)?;
+    if options.strip_pixel_data {
+        metadata.pixel_data = Some("Stripped".to_string());
+    }
+    if options.hash {
+        metadata.file_hash = Some(
+            file_sha256(input_path)
+                .with_context(|| format!("Unable to hash {}", input_path.display()))?,
+        );
+    }
+
+    // Rendered from the anonymized object in memory; a PNG failure does
+    // not undo the written DICOM
+    let png = match convert_to {
+        Some((png_path, convert_options)) => {
+            if let Some(parent) = png_path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            Some(Box::new(convert_object(
+                &obj,
+                metadata.clone(),
+                input_path,
+                png_path,
+                convert_options,
+            )?))
+        }
+        None => None,
+    };
+
+    Ok(AnonymizeOutcome::Success(metadata, audit, png))
 }
 
-fn extract_metadata(obj: &FileDicomObject<InMemDicomObject>, path: &Path) -> Result<FileMetadata> {
+/// Convert a replacement string into a value of the element's VR.
+/// Multiple values are separated by a backslash; an empty string clears the element.
+fn coerce_replacement(vr: VR, value: &str) -> Result<PrimitiveValue> {
+    fn parse_all<T: std::str::FromStr>(value: &str) -> Result<Vec<T>>
+    where
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        value
+            .split('\\')
+            .map(|v| Ok(v.trim().parse::<T>()?))
+            .collect()
+    }
+
+    if value.is_empty() {
+        return Ok(PrimitiveValue::Empty);
+    }
+
+    Ok(match vr {
+        VR::US => PrimitiveValue::U16(parse_all(value)?.into()),
+        VR::SS => PrimitiveValue::I16(parse_all(value)?.into()),
+        VR::UL => PrimitiveValue::U32(parse_all(value)?.into()),
+        VR::SL => PrimitiveValue::I32(parse_all(value)?.into()),
+        VR::UV => PrimitiveValue::U64(parse_all(value)?.into()),
+        VR::SV => PrimitiveValue::I64(parse_all(value)?.into()),
+        VR::FL => PrimitiveValue::F32(parse_all(value)?.into()),
+        VR::FD => PrimitiveValue::F64(parse_all(value)?.into()),
+        // Numeric strings: validate, but keep the text as written
+        VR::IS => {
+            parse_all::<i64>(value)?;
+            PrimitiveValue::from(value)
+        }
+        VR::DS => {
+            parse_all::<f64>(value)?;
+            PrimitiveValue::from(value)
+        }
+        VR::DA => {
+            let dates = value
+                .split('\\')
+                .map(|v| {
+                    let v = v.trim();
+                    let date = chrono::NaiveDate::parse_from_str(v, "%Y%m%d")
+                        .or_else(|_| chrono::NaiveDate::parse_from_str(v, "%Y-%m-%d"))
+                        .with_context(|| format!("'{}' is not a YYYYMMDD date", v))?;
+                    Ok(date.format("%Y%m%d").to_string())
+                })
+                .collect::<Result<Vec<_>>>()?;
+            PrimitiveValue::Strs(dates.into())
+        }
+        VR::TM | VR::DT => {
+            if !value
+                .chars()
+                .all(|c| c.is_ascii_digit() || matches!(c, '.' | '+' | '-' | '\\'))
+            {
+                bail!("'{}' is not a valid {} value", value, vr.to_string());
+            }
+            PrimitiveValue::from(value)
+        }
+        VR::AE
+        | VR::AS
+        | VR::CS
+        | VR::LO
+        | VR::LT
+        | VR::PN
+        | VR::SH
+        | VR::ST
+        | VR::UC
+        | VR::UI
+        | VR::UR
+        | VR::UT => PrimitiveValue::from(value),
+        _ => bail!("replacing {} values is not supported", vr.to_string()),
+    })
+}
+
+fn extract_metadata(
+    obj: &FileDicomObject<InMemDicomObject>,
+    path: &Path,
+    extra_tags: &[Tag],
+) -> Result<FileMetadata> {
+    let text = TextDecoder::of(obj);
     let get_str = |tag: Tag| -> Option<String> {
         obj.element(tag)
             .ok()
-            .and_then(|e| e.to_str().ok())
-            .map(|s| s.to_string())
+            .and_then(|e| text.element_text(e))
             .filter(|s| !s.is_empty())
     };
 
@@ -330,12 +1055,23 @@ fn extract_metadata(obj: &FileDicomObject<InMemDicomObject>, path: &Path) -> Res
         .and_then(|n| n.to_str())
         .unwrap_or("unknown")
         .to_string();
+    let sop_class_uid = get_str(Tag(0x0008, 0x0016));
+    let (study_date, study_date_source) = STUDY_DATE_FALLBACKS
+        .iter()
+        .find_map(|&(tag, source)| get_str(tag).map(|date| (date, source.to_string())))
+        .unzip();
 
     Ok(FileMetadata {
         folder_relative: PathBuf::new(), // Filled later
         file_name: filename,
-        study_date: get_str(Tag(0x0008, 0x0020)),
+        study_date,
+        study_date_source,
         modality: get_str(Tag(0x0008, 0x0060)),
+        sop_class_name: sop_class_uid.as_deref().and_then(sop_class_name),
+        sop_class_uid,
+        series_instance_uid: get_str(Tag(0x0020, 0x000E)),
+        instance_number: get_str(Tag(0x0020, 0x0013)).and_then(|v| v.trim().parse().ok()),
+        image_type: get_str(Tag(0x0008, 0x0008)),
         manufacturer: get_str(Tag(0x0008, 0x0070)),
         study_description: get_str(Tag(0x0008, 0x1030)),
         series_description: get_str(Tag(0x0008, 0x103E)),
@@ -344,5 +1080,168 @@ fn extract_metadata(obj: &FileDicomObject<InMemDicomObject>, path: &Path) -> Res
         im_width: get_u32(Tag(0x0028, 0x0011)),  // Columns
         im_height: get_u32(Tag(0x0028, 0x0010)), // Rows
         pixel_spacing: get_str(Tag(0x0028, 0x0030)),
+        frame_positions: frame_positions(&obj),
+        spacing_embedded: None,
+        pixel_min: None,
+        pixel_max: None,
+        pixel_mean: None,
+        decoded_with_fallback: false,
+        file_hash: None,
+        extra_tags: extra_tag_values(obj, extra_tags),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::value::DataSetSequence;
+
+    const PATIENT_NAME: Tag = Tag(0x0010, 0x0010);
+
+    fn patient_name(name: &str) -> DataElement<InMemDicomObject> {
+        DataElement::new(PATIENT_NAME, VR::PN, PrimitiveValue::from(name))
+    }
+
+    fn sequence(tag: Tag, items: Vec<InMemDicomObject>) -> DataElement<InMemDicomObject> {
+        DataElement::new(tag, VR::SQ, DataSetSequence::from(items))
+    }
+
+    fn nested_name(obj: &InMemDicomObject, path: &[Tag]) -> Option<String> {
+        let mut item = obj;
+        for &tag in path {
+            item = item.element(tag).ok()?.items()?.first()?;
+        }
+        Some(item.element(PATIENT_NAME).ok()?.to_str().ok()?.into_owned())
+    }
+
+    /// Write a small CT object with the given instance UID to `path`.
+    fn write_ct(path: &Path, instance_uid: &str) {
+        let ct = "1.2.840.10008.5.1.4.1.1.2";
+        InMemDicomObject::from_element_iter([
+            DataElement::new(Tag(0x0008, 0x0016), VR::UI, PrimitiveValue::from(ct)),
+            DataElement::new(
+                Tag(0x0008, 0x0018),
+                VR::UI,
+                PrimitiveValue::from(instance_uid),
+            ),
+            patient_name("Doe^John"),
+        ])
+        .with_meta(
+            dicom::object::meta::FileMetaTableBuilder::new()
+                .transfer_syntax("1.2.840.10008.1.2.1")
+                .media_storage_sop_class_uid(ct)
+                .media_storage_sop_instance_uid(instance_uid),
+        )
+        .unwrap()
+        .write_to_file(path)
+        .unwrap();
+    }
+
+    #[test]
+    fn anonymized_file_meta_matches_the_new_uids() {
+        let dir = std::env::temp_dir().join(format!("dicom-app-meta-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("in.dcm"), dir.join("out.dcm"));
+        write_ct(&input, "1.2.3.4.5");
+        let secondary_capture = "1.2.840.10008.5.1.4.1.1.7";
+        let options = AnonymizeOptions {
+            force_sop_class: Some(secondary_capture.to_string()),
+            ..Default::default()
+        };
+
+        anonymize_single_file(&input, &output, &[], &options, None).unwrap();
+        let obj = open_file(&output).unwrap();
+        let text = |tag: Tag| dicom_text(&obj, tag).unwrap();
+        let meta = obj.meta();
+        let trimmed = |uid: &str| uid.trim_end_matches(['\0', ' ']).to_string();
+        assert_eq!(text(Tag(0x0008, 0x0016)), secondary_capture);
+        assert_eq!(
+            trimmed(&meta.media_storage_sop_class_uid),
+            secondary_capture
+        );
+        assert_ne!(text(Tag(0x0008, 0x0018)), "1.2.3.4.5");
+        assert_eq!(
+            trimmed(&meta.media_storage_sop_instance_uid),
+            text(Tag(0x0008, 0x0018))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replace_tag_reaches_nested_sequences() {
+        let referenced = Tag(0x0008, 0x1120);
+        let other = Tag(0x0038, 0x0004);
+        let inner = InMemDicomObject::from_element_iter([patient_name("Inner^Name")]);
+        let item = InMemDicomObject::from_element_iter([
+            patient_name("Item^Name"),
+            sequence(other, vec![inner]),
+        ]);
+        let mut obj = InMemDicomObject::from_element_iter([
+            patient_name("Root^Name"),
+            sequence(referenced, vec![item]),
+        ]);
+
+        assert!(replace_tag(&mut obj, PATIENT_NAME, "ANON").unwrap());
+        assert_eq!(nested_name(&obj, &[]).as_deref(), Some("ANON"));
+        assert_eq!(nested_name(&obj, &[referenced]).as_deref(), Some("ANON"));
+        assert_eq!(
+            nested_name(&obj, &[referenced, other]).as_deref(),
+            Some("ANON")
+        );
+        let vr = obj.element(PATIENT_NAME).unwrap().vr();
+        assert_eq!(vr, VR::PN);
+    }
+
+    #[test]
+    fn tag_patterns_reach_nested_sequences() {
+        let referenced = Tag(0x0008, 0x1120);
+        let birth_date = Tag(0x0010, 0x0030);
+        let item = InMemDicomObject::from_element_iter([
+            patient_name("Item^Name"),
+            DataElement::new(birth_date, VR::DA, PrimitiveValue::from("19700101")),
+        ]);
+        let mut obj = InMemDicomObject::from_element_iter([
+            patient_name("Root^Name"),
+            sequence(referenced, vec![item.clone(), item]),
+            sequence(Tag(0x0010, 0x1002), vec![InMemDicomObject::new_empty()]),
+        ]);
+        let pattern = TagPattern {
+            group: 0x0010,
+            group_mask: 0xFFFF,
+            element: 0,
+            element_mask: 0,
+        };
+
+        let audit = replace_pattern(&mut obj, &pattern, "ANON");
+        assert_eq!(nested_name(&obj, &[]).as_deref(), Some("ANON"));
+        assert_eq!(nested_name(&obj, &[referenced]).as_deref(), Some("ANON"));
+        let item = &obj.element(referenced).unwrap().items().unwrap()[1];
+        assert_eq!(item.element(birth_date).unwrap().to_str().unwrap(), "");
+        assert!(obj.element(Tag(0x0010, 0x1002)).is_err());
+
+        let actions: Vec<(Tag, AuditAction)> = audit
+            .iter()
+            .map(|entry| (entry.tag, entry.action))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                (PATIENT_NAME, AuditAction::Replaced),
+                (birth_date, AuditAction::Emptied),
+                (Tag(0x0010, 0x1002), AuditAction::Removed),
+            ]
+        );
+    }
+
+    #[test]
+    fn replace_tag_finds_a_name_only_present_in_a_sequence() {
+        let referenced = Tag(0x0008, 0x1120);
+        let item = InMemDicomObject::from_element_iter([patient_name("Item^Name")]);
+        let mut obj = InMemDicomObject::from_element_iter([sequence(referenced, vec![item])]);
+
+        assert!(replace_tag(&mut obj, PATIENT_NAME, "ANON").unwrap());
+        assert!(obj.element(PATIENT_NAME).is_err());
+        assert_eq!(nested_name(&obj, &[referenced]).as_deref(), Some("ANON"));
+        assert!(!replace_tag(&mut obj, Tag(0x0010, 0x0020), "ID").unwrap());
+    }
+}
@@ -1,13 +1,31 @@
-use crate::models::metadata::{dicom_date, dicom_text, pixel_spacing, FileMetadata};
-use anyhow::{Context, Result};
+use crate::logic::transfer_syntax::transfer_syntax_name;
+use crate::logic::workflow::ConvertOptions;
+use crate::models::metadata::{
+    dicom_text, extra_tag_values, file_sha256, frame_element, frame_positions, frame_text,
+    pixel_spacing, pixel_spacing_mm, sop_class_name, study_date, FileMetadata,
+};
+use crate::utils::retry::open_file_with_retry;
+use anyhow::{bail, Context, Result};
+use dicom::core::value::Value;
 use dicom::core::Tag;
-use dicom_object::{open_file, DefaultDicomObject};
-use dicom_pixeldata::{image::DynamicImage, PixelDecoder as _};
-use std::fs;
+use dicom_object::file::{OddLengthStrategy, ReadPreamble};
+use dicom_object::{open_file, DefaultDicomObject, OpenFileOptions};
+use dicom_pixeldata::image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use dicom_pixeldata::image::{DynamicImage, ImageBuffer, ImageFormat, Luma, RgbImage};
+use dicom_pixeldata::{DecodedPixelData, PixelDecoder as _, VoiLutOption, WindowLevel};
+use owo_colors::OwoColorize;
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor, Seek, Write};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tiff::encoder::{colortype, Rational, TiffEncoder};
+use tiff::tags::ResolutionUnit;
 
 pub enum FileOutcome {
-    Converted(FileMetadata),
+    /// Metadata and the path of the PNG. With a zip archive the PNG is not
+    /// written; its encoded bytes are passed on for the writer thread to add.
+    Converted(FileMetadata, PathBuf, Option<Vec<u8>>),
     Skipped {
         metadata: FileMetadata,
         reason: String,
@@ -15,57 +33,872 @@ pub enum FileOutcome {
     Failed {
         metadata: FileMetadata,
         error: anyhow::Error,
+        /// Name of the file's transfer syntax (its UID if unregistered),
+        /// so failures can be grouped by codec
+        transfer_syntax: String,
     },
 }
 
-pub fn convert_single_file(dicom_path: &Path, png_path: &Path) -> Result<FileOutcome> {
-    let obj: DefaultDicomObject = open_file(dicom_path)
+pub fn convert_single_file(
+    dicom_path: &Path,
+    png_path: &Path,
+    options: &ConvertOptions,
+) -> Result<FileOutcome> {
+    let obj: DefaultDicomObject = open_file_with_retry(dicom_path)
         .with_context(|| format!("Failed to open DICOM file {}", dicom_path.display()))?;
 
-    let mut metadata = extract_metadata(dicom_path)?;
+    let mut metadata = extract_metadata(dicom_path, &options.metadata_columns.tags())?;
+    if let Some(reason) = options
+        .discovery
+        .modality_filter(metadata.modality.as_deref())
+    {
+        return Ok(FileOutcome::Skipped { metadata, reason });
+    }
 
-    if !has_pixel_data(&obj) {
+    if options.hash {
+        metadata.file_hash = Some(
+            file_sha256(dicom_path)
+                .with_context(|| format!("Unable to hash {}", dicom_path.display()))?,
+        );
+    }
+
+    convert_object(&obj, metadata, dicom_path, png_path, options)
+}
+
+/// Render an already opened object to `png_path`, filling in the parts of
+/// `metadata` that come from decoding. `dicom_path` is only reopened by the
+/// fallback decoder. Lets the anonymizer convert its in-memory result
+/// without reading the file again.
+pub fn convert_object(
+    obj: &DefaultDicomObject,
+    mut metadata: FileMetadata,
+    dicom_path: &Path,
+    png_path: &Path,
+    options: &ConvertOptions,
+) -> Result<FileOutcome> {
+    let ts_uid = obj.meta().transfer_syntax();
+    let transfer_syntax = transfer_syntax_name(ts_uid)
+        .unwrap_or_else(|| ts_uid.trim_end_matches(['\0', ' ']).to_string());
+
+    if !has_pixel_data(obj) {
         let modality = metadata
             .modality
             .clone()
             .unwrap_or_else(|| "Unknown".to_string());
-        let sop_class = dicom_text(&obj, Tag(0x0008, 0x0016)).unwrap_or_else(|| "Unknown".into());
+        let sop_class = dicom_text(obj, Tag(0x0008, 0x0016)).unwrap_or_else(|| "Unknown".into());
         return Ok(FileOutcome::Failed {
             metadata,
             error: anyhow::anyhow!("no pixel data (Modality={modality}, SOPClass={sop_class})"),
+            transfer_syntax,
         });
     }
 
-    let pixel_data = match obj.decode_pixel_data() {
-        Ok(data) => data,
+    let multipage = options.output_format == OutputFormat::Tiff && options.tiff_multipage;
+    let complete_frames = match check_pixel_data_length(obj, dicom_path, multipage) {
+        Ok(complete_frames) => complete_frames,
         Err(e) => {
             return Ok(FileOutcome::Failed {
                 metadata,
-                error: e.into(),
+                error: e,
+                transfer_syntax,
             })
         }
     };
 
-    let image = match pixel_data.to_dynamic_image(0) {
-        Ok(img) => img,
+    let (window, auto_window) = frame_window(obj, options, 0);
+    let (mut image, stats) = match decode_image(obj, window, auto_window, options.pixel_stats) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            match decode_image_permissive(dicom_path, window, auto_window, options.pixel_stats) {
+                Ok(decoded) => {
+                    metadata.decoded_with_fallback = true;
+                    decoded
+                }
+                Err(fallback_error) => {
+                    return Ok(FileOutcome::Failed {
+                        metadata,
+                        error: anyhow::anyhow!(
+                            "{e:#}; fallback decoder also failed: {fallback_error:#}"
+                        ),
+                        transfer_syntax,
+                    })
+                }
+            }
+        }
+    };
+
+    if let Some((min, max, mean)) = stats {
+        metadata.pixel_min = Some(min);
+        metadata.pixel_max = Some(max);
+        metadata.pixel_mean = Some(mean);
+    }
+
+    if options.with_overlays {
+        crate::logic::overlay::burn_overlays(obj, &mut image);
+    }
+
+    // Last step, so it flips whatever MONOCHROME1 and PresentationLUTShape
+    // already produced
+    if options.invert {
+        image.invert();
+    }
+
+    let spacing = if options.embed_spacing {
+        pixel_spacing_mm(obj)
+    } else {
+        None
+    };
+
+    let (width, height) = (image.width(), image.height());
+    let mut pages = vec![image];
+    if multipage {
+        match decode_later_frames(obj, options, complete_frames) {
+            Ok(frames) => pages.extend(frames.into_iter().map(|mut frame| {
+                if options.invert {
+                    frame.invert();
+                }
+                frame
+            })),
+            Err(e) => crate::utils::logging::warn(format_args!(
+                "{} Writing only the first frame of {}: {:#}",
+                "⚠".yellow(),
+                dicom_path.display(),
+                e
+            )),
+        }
+    }
+
+    let written = if options.zip.is_some() {
+        encode_image(&pages, spacing, options).map(Some)
+    } else {
+        save_image(&pages, png_path, spacing, options).map(|()| None)
+    };
+    let png = match written {
+        Ok(png) => png,
         Err(e) => {
             return Ok(FileOutcome::Failed {
                 metadata,
-                error: e.into(),
+                error: e,
+                transfer_syntax,
             })
         }
     };
 
-    if let Err(e) = save_image(&image, png_path) {
-        return Ok(FileOutcome::Failed { metadata, error: e });
+    if options.embed_spacing {
+        metadata.spacing_embedded = Some(spacing.is_some());
+    }
+
+    // Files that fail before this point keep Columns/Rows from the header
+    metadata.im_width = Some(width);
+    metadata.im_height = Some(height);
+
+    Ok(FileOutcome::Converted(
+        metadata,
+        png_path.to_path_buf(),
+        png,
+    ))
+}
+
+/// Output path used with `group_by_series`:
+/// `<SeriesInstanceUID>/<InstanceNumber>.png` under `png_output_path`, or
+/// `unsorted/<file stem>.png` when the series is unknown, with `extension`
+/// instead of `png`. Only the header is read. Falls back to the file stem
+/// when InstanceNumber is missing.
+pub fn series_png_path(dicom_path: &Path, png_output_path: &Path, extension: &str) -> PathBuf {
+    let header = OpenFileOptions::new()
+        .read_until(Tag(0x7FE0, 0x0010))
+        .open_file(dicom_path)
+        .ok();
+
+    let file_stem = dicom_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("unknown")
+        .to_string();
+
+    let series_uid = header
+        .as_ref()
+        .and_then(|obj| dicom_text(obj, Tag(0x0020, 0x000E)))
+        .map(|uid| sanitize_uid(&uid))
+        .filter(|uid| !uid.is_empty());
+
+    let Some(series_uid) = series_uid else {
+        return png_output_path
+            .join("unsorted")
+            .join(format!("{}.{}", file_stem, extension));
+    };
+
+    let instance_number = header
+        .as_ref()
+        .and_then(|obj| dicom_text(obj, Tag(0x0020, 0x0013)))
+        .and_then(|v| v.parse::<i64>().ok())
+        .map(|n| n.to_string())
+        .unwrap_or(file_stem);
+
+    png_output_path
+        .join(series_uid)
+        .join(format!("{}.{}", instance_number, extension))
+}
+
+/// SeriesInstanceUID of a file, reading only the header up to it.
+pub fn read_series_uid(dicom_path: &Path) -> Option<String> {
+    let header = OpenFileOptions::new()
+        .read_until(Tag(0x0020, 0x0010))
+        .open_file(dicom_path)
+        .ok()?;
+    dicom_text(&header, Tag(0x0020, 0x000E))
+        .map(|uid| uid.trim_end_matches(['\0', ' ']).to_string())
+        .filter(|uid| !uid.is_empty())
+}
+
+/// Keep only characters that are safe in a file or folder name.
+pub fn sanitize_uid(uid: &str) -> String {
+    uid.chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-'))
+        .collect()
+}
+
+/// Render the first frame of one file to PNG bytes without touching the
+/// filesystem, optionally overriding the file's window with (center, width).
+pub fn render_preview(dicom_path: &Path, window: Option<(f64, f64)>) -> Result<Vec<u8>> {
+    let obj: DefaultDicomObject = open_file(dicom_path)
+        .with_context(|| format!("Failed to open DICOM file {}", dicom_path.display()))?;
+    let (image, _) = decode_image(&obj, window, None, false)?;
+
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .context("Unable to encode preview PNG")?;
+    Ok(bytes)
+}
+
+/// Which of a file's WindowCenter/WindowWidth pairs to use when it has
+/// several (e.g. brain and bone presets on a CT).
+#[derive(Clone, Debug)]
+pub enum WindowSelection {
+    /// 0-based position in the multi-valued WindowCenter/WindowWidth
+    Index(usize),
+    /// Matched case-insensitively against WindowCenterWidthExplanation (0028,1055)
+    Label(String),
+}
+
+/// Image format written for each converted file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    /// Baseline TIFF; like PNG it keeps 16-bit grayscale samples
+    Tiff,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Tiff => "tiff",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "PNG",
+            OutputFormat::Tiff => "TIFF",
+        }
     }
+}
+
+/// DEFLATE effort for written PNGs; `Fast` trades file size for encoding speed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PngCompression {
+    Fast,
+    /// The encoder's own default
+    #[default]
+    Default,
+    Best,
+}
+
+/// (window, auto_window) to render `frame` with: the explicit window, else
+/// the selected window of the file, else the modality default, else the
+/// frame's own window from the functional groups of an enhanced
+/// multi-frame object, which dicom-pixeldata does not read.
+fn frame_window(obj: &DefaultDicomObject, options: &ConvertOptions, frame: u32) -> WindowChoice {
+    let window = options.window.or_else(|| {
+        options
+            .window_selection
+            .as_ref()
+            .and_then(|selection| selected_window(obj, selection, frame))
+    });
+    let (window, auto_window) = match window {
+        None if options.modality_defaults => modality_default_window(obj, options.auto_window),
+        _ => (window, options.auto_window),
+    };
+    // Ranks like a root-level window: percentiles still replace it
+    let window = window.or_else(|| {
+        auto_window
+            .is_none()
+            .then(|| functional_group_window(obj, frame))
+            .flatten()
+    });
+    (window, auto_window)
+}
+
+/// First (center, width) of the FrameVOILUTSequence that applies to
+/// `frame`, per-frame before shared.
+fn functional_group_window(obj: &DefaultDicomObject, frame: u32) -> Option<(f64, f64)> {
+    let first = |tag: Tag| -> Option<f64> {
+        frame_element(obj, frame, tag)?
+            .to_multi_float64()
+            .ok()?
+            .first()
+            .copied()
+    };
+    Some((first(Tag(0x0028, 0x1050))?, first(Tag(0x0028, 0x1051))?))
+}
 
-    metadata.im_width = Some(image.width());
-    metadata.im_height = Some(image.height());
+/// The (center, width) pair picked by `selection`, or `None` when the file
+/// has no such window, in which case its first window is used as usual.
+/// Enhanced multi-frame objects keep their windows per frame in the
+/// FrameVOILUTSequence (0028,9132) functional group, which takes
+/// precedence over root-level values.
+fn selected_window(
+    obj: &DefaultDicomObject,
+    selection: &WindowSelection,
+    frame: u32,
+) -> Option<(f64, f64)> {
+    let values = |tag: Tag| -> Option<Vec<f64>> {
+        frame_element(obj, frame, tag)
+            .or_else(|| obj.element(tag).ok())
+            .and_then(|e| e.to_multi_float64().ok())
+    };
+    let centers = values(Tag(0x0028, 0x1050))?;
+    let widths = values(Tag(0x0028, 0x1051))?;
 
-    Ok(FileOutcome::Converted(metadata))
+    let explanation = Tag(0x0028, 0x1055);
+    let index = match selection {
+        WindowSelection::Index(index) => *index,
+        WindowSelection::Label(label) => frame_text(obj, frame, explanation)
+            .or_else(|| dicom_text(obj, explanation))?
+            .split('\\')
+            .position(|explanation| explanation.trim().eq_ignore_ascii_case(label.trim()))?,
+    };
+    Some((*centers.get(index)?, *widths.get(index)?))
 }
 
+/// Default rendering of a modality for files without a window of their own.
+#[derive(Clone, Copy)]
+enum ModalityDefault {
+    /// Fixed (center, width)
+    Window(f64, f64),
+    /// Percentile window, with the configured or default percentiles
+    Auto,
+    /// The full range of the stored values, after rescale
+    Identity,
+}
+
+/// Defaults applied with `ConvertOptions::modality_defaults`; other
+/// modalities keep min-max normalization.
+const MODALITY_DEFAULTS: [(&str, ModalityDefault); 4] = [
+    // Soft tissue
+    ("CT", ModalityDefault::Window(40.0, 400.0)),
+    ("MR", ModalityDefault::Auto),
+    ("CR", ModalityDefault::Identity),
+    ("DX", ModalityDefault::Identity),
+];
+
+/// Percentiles of `ModalityDefault::Auto` when no auto window is configured.
+const DEFAULT_AUTO_WINDOW: (f64, f64) = (1.0, 99.0);
+
+/// (window, auto_window) a file is rendered with, as `decode_image` takes
+/// them: a (center, width) override and (low, high) percentiles.
+type WindowChoice = (Option<(f64, f64)>, Option<(f64, f64)>);
+
+/// (window, auto_window) for a file rendered without an explicit window:
+/// the `MODALITY_DEFAULTS` entry of its Modality, unless the file carries a
+/// window or VOI LUT of its own or is not grayscale.
+fn modality_default_window(
+    obj: &DefaultDicomObject,
+    auto_window: Option<(f64, f64)>,
+) -> WindowChoice {
+    let grayscale = dicom_text(obj, Tag(0x0028, 0x0004))
+        .is_some_and(|photometric| photometric.starts_with("MONOCHROME"));
+    if has_own_window(obj) || !grayscale {
+        return (None, auto_window);
+    }
+    let modality = dicom_text(obj, Tag(0x0008, 0x0060)).unwrap_or_default();
+    let default = MODALITY_DEFAULTS
+        .iter()
+        .find(|(name, _)| *name == modality.trim())
+        .map(|&(_, default)| default);
+    match default {
+        Some(ModalityDefault::Window(center, width)) => (Some((center, width)), auto_window),
+        Some(ModalityDefault::Auto) => (None, auto_window.or(Some(DEFAULT_AUTO_WINDOW))),
+        Some(ModalityDefault::Identity) => (identity_window(obj), auto_window),
+        None => (None, auto_window),
+    }
+}
+
+/// Window covering every value BitsStored can hold, mapped through
+/// RescaleSlope/RescaleIntercept.
+fn identity_window(obj: &DefaultDicomObject) -> Option<(f64, f64)> {
+    let float = |tag: Tag| obj.element(tag).ok().and_then(|e| e.to_float64().ok());
+    let bits_stored = obj
+        .element(Tag(0x0028, 0x0101))
+        .ok()
+        .and_then(|e| e.to_int::<u32>().ok())
+        .filter(|bits| (1..=32).contains(bits))?;
+    let signed = obj
+        .element(Tag(0x0028, 0x0103))
+        .ok()
+        .and_then(|e| e.to_int::<u16>().ok())
+        == Some(1);
+    let levels = 2f64.powi(bits_stored as i32);
+    let (low, high) = if signed {
+        (-levels / 2.0, levels / 2.0 - 1.0)
+    } else {
+        (0.0, levels - 1.0)
+    };
+    let slope = float(Tag(0x0028, 0x1053)).unwrap_or(1.0);
+    let intercept = float(Tag(0x0028, 0x1052)).unwrap_or(0.0);
+    let (low, high) = (low * slope + intercept, high * slope + intercept);
+    let (low, high) = (low.min(high), low.max(high));
+    Some(((low + high) / 2.0, high - low + 1.0))
+}
+
+/// Whether the file has a WindowCenter or VOI LUT Sequence of its own,
+/// including in the functional groups of an enhanced multi-frame object.
+fn has_own_window(obj: &DefaultDicomObject) -> bool {
+    [Tag(0x0028, 0x1050), Tag(0x0028, 0x3010)]
+        .into_iter()
+        .any(|tag| obj.element(tag).is_ok() || frame_element(obj, 0, tag).is_some())
+}
+
+/// Pixel pipeline options: a `Some((center, width))` window replaces the
+/// file's WindowCenter/WindowWidth in the VOI LUT step.
+fn render_options(window: Option<(f64, f64)>) -> dicom_pixeldata::ConvertOptions {
+    let convert_options = dicom_pixeldata::ConvertOptions::new();
+    match window {
+        Some((center, width)) => {
+            convert_options.with_voi_lut(VoiLutOption::Custom(WindowLevel { center, width }))
+        }
+        None => convert_options,
+    }
+}
+
+/// The first frame as an image and, if `with_stats` is set, the pixel
+/// statistics of the whole pixel data.
+fn decode_image(
+    obj: &DefaultDicomObject,
+    window: Option<(f64, f64)>,
+    auto_window: Option<(f64, f64)>,
+    with_stats: bool,
+) -> Result<(DynamicImage, Option<PixelStats>)> {
+    if let Some(image) = decode_native_ybr_422(obj)? {
+        return Ok((image, None));
+    }
+    if let Some(decoded) = decode_float_pixel_data(obj, window, auto_window, with_stats)? {
+        return Ok(decoded);
+    }
+    let pixel_data = obj.decode_pixel_data()?;
+    if let Some(image) = decode_palette_color(obj, &pixel_data)? {
+        return Ok((image, None));
+    }
+    let window = window.or_else(|| {
+        auto_window.and_then(|percentiles| percentile_window(obj, &pixel_data, percentiles))
+    });
+    let mut image = pixel_data.to_dynamic_image_with_options(0, &render_options(window))?;
+    apply_presentation_lut_shape(obj, &mut image);
+    let stats = with_stats.then(|| pixel_stats(&pixel_data)).flatten();
+    Ok((image, stats))
+}
+
+/// (min, max, mean)
+type PixelStats = (f64, f64, f64);
+
+/// Statistics over every frame and sample after the modality LUT (e.g. in
+/// Hounsfield units for CT), independent of any window, so blank or
+/// saturated images stand out.
+fn pixel_stats(pixel_data: &DecodedPixelData<'_>) -> Option<PixelStats> {
+    let values: Vec<f64> = pixel_data.to_vec().ok()?;
+    if values.is_empty() {
+        return None;
+    }
+    let (min, max, sum) = values.iter().fold(
+        (f64::INFINITY, f64::NEG_INFINITY, 0.0),
+        |(min, max, sum), &v| (min.min(v), max.max(v), sum + v),
+    );
+    Some((min, max, sum / values.len() as f64))
+}
+
+/// Uncompressed YBR_FULL_422 stores each horizontal pixel pair as
+/// Y1 Y2 Cb Cr, which dicom-pixeldata reads as if it were full 3-sample
+/// data and rejects. Upsample the shared chroma to both pixels and convert
+/// to RGB here. Returns `None` for any other pixel data, including
+/// compressed 422 (codecs already deliver full-resolution samples).
+fn decode_native_ybr_422(obj: &DefaultDicomObject) -> Result<Option<DynamicImage>> {
+    if dicom_text(obj, Tag(0x0028, 0x0004)).as_deref() != Some("YBR_FULL_422") {
+        return Ok(None);
+    }
+    let Ok(element) = obj.element(Tag(0x7FE0, 0x0010)) else {
+        return Ok(None);
+    };
+    if !matches!(element.value(), Value::Primitive(_)) {
+        return Ok(None);
+    }
+
+    let int = |tag: Tag| -> Option<u32> { obj.element(tag).ok().and_then(|e| e.to_int().ok()) };
+    let rows = int(Tag(0x0028, 0x0010)).context("Missing Rows")?;
+    let columns = int(Tag(0x0028, 0x0011)).context("Missing Columns")?;
+    if int(Tag(0x0028, 0x0100)) != Some(8) {
+        bail!("YBR_FULL_422 is only supported with 8 bits allocated");
+    }
+    if columns % 2 != 0 {
+        bail!(
+            "YBR_FULL_422 requires an even number of columns, got {}",
+            columns
+        );
+    }
+
+    let bytes = element.to_bytes()?;
+    let frame_len = rows as usize * columns as usize * 2;
+    let frame = bytes
+        .get(..frame_len)
+        .with_context(|| format!("PixelData is shorter than one frame ({} bytes)", frame_len))?;
+
+    let mut rgb = Vec::with_capacity(rows as usize * columns as usize * 3);
+    for pair in frame.chunks_exact(4) {
+        let (cb, cr) = (pair[2], pair[3]);
+        rgb.extend_from_slice(&ybr_full_to_rgb(pair[0], cb, cr));
+        rgb.extend_from_slice(&ybr_full_to_rgb(pair[1], cb, cr));
+    }
+    let image = RgbImage::from_raw(columns, rows, rgb).context("Invalid YBR_FULL_422 frame")?;
+    Ok(Some(DynamicImage::ImageRgb8(image)))
+}
+
+/// FloatPixelData (7fe0,0008) and DoubleFloatPixelData (7fe0,0009), used
+/// by parametric maps, hold real-valued grayscale samples that
+/// dicom-pixeldata does not decode. Window the first frame linearly into
+/// 16-bit grayscale: the given window, else the file's first
+/// WindowCenter/WindowWidth, else the `auto_window` percentiles, else
+/// min-max. The float IODs have no rescale, so samples are used as stored.
+/// Returns `None` when the file has regular PixelData or no float data.
+fn decode_float_pixel_data(
+    obj: &DefaultDicomObject,
+    window: Option<(f64, f64)>,
+    auto_window: Option<(f64, f64)>,
+    with_stats: bool,
+) -> Result<Option<(DynamicImage, Option<PixelStats>)>> {
+    if obj.element(Tag(0x7FE0, 0x0010)).is_ok() {
+        return Ok(None);
+    }
+    let Some(element) = [Tag(0x7FE0, 0x0008), Tag(0x7FE0, 0x0009)]
+        .into_iter()
+        .find_map(|tag| obj.element(tag).ok())
+    else {
+        return Ok(None);
+    };
+
+    let int = |tag: Tag| -> Option<u32> { obj.element(tag).ok().and_then(|e| e.to_int().ok()) };
+    let rows = int(Tag(0x0028, 0x0010)).context("Missing Rows")?;
+    let columns = int(Tag(0x0028, 0x0011)).context("Missing Columns")?;
+    if int(Tag(0x0028, 0x0002)).unwrap_or(1) != 1 {
+        bail!("Float pixel data is only supported with 1 sample per pixel");
+    }
+
+    let values = element
+        .to_multi_float64()
+        .context("Unable to read float pixel data")?;
+    let frame_len = rows as usize * columns as usize;
+    let frame = values.get(..frame_len).with_context(|| {
+        format!(
+            "Float pixel data is shorter than one frame ({} samples)",
+            frame_len
+        )
+    })?;
+
+    let finite =
+        |values: &[f64]| -> Vec<f64> { values.iter().copied().filter(|v| v.is_finite()).collect() };
+    let stats = with_stats
+        .then(|| {
+            let values = finite(&values);
+            let (min, max, sum) = values.iter().fold(
+                (f64::INFINITY, f64::NEG_INFINITY, 0.0),
+                |(min, max, sum), &v| (min.min(v), max.max(v), sum + v),
+            );
+            (!values.is_empty()).then(|| (min, max, sum / values.len() as f64))
+        })
+        .flatten();
+
+    let file_window = || {
+        let first = |tag: Tag| -> Option<f64> {
+            obj.element(tag)
+                .ok()
+                .and_then(|e| e.to_multi_float64().ok())
+                .and_then(|v| v.first().copied())
+        };
+        Some((first(Tag(0x0028, 0x1050))?, first(Tag(0x0028, 0x1051))?))
+    };
+    let mut sorted = finite(frame);
+    sorted.sort_unstable_by(f64::total_cmp);
+    let (low, high) = match window.or_else(file_window) {
+        Some((center, width)) => (center - width / 2.0, center + width / 2.0),
+        None if sorted.is_empty() => (0.0, 1.0),
+        None => {
+            let last = (sorted.len() - 1) as f64;
+            let at = |percentile: f64| {
+                sorted[(percentile / 100.0 * last).round().clamp(0.0, last) as usize]
+            };
+            let (low, high) = auto_window.unwrap_or((0.0, 100.0));
+            (at(low), at(high))
+        }
+    };
+    let range = (high - low).max(f64::EPSILON);
+
+    let luma: Vec<u16> = frame
+        .iter()
+        .map(|&v| {
+            // NaN maps to black
+            let scaled = ((v - low) / range).clamp(0.0, 1.0) * u16::MAX as f64;
+            if scaled.is_nan() {
+                0
+            } else {
+                scaled.round() as u16
+            }
+        })
+        .collect();
+    let image = ImageBuffer::<Luma<u16>, _>::from_raw(columns, rows, luma)
+        .context("Invalid float pixel data frame")?;
+    let mut image = DynamicImage::ImageLuma16(image);
+    apply_presentation_lut_shape(obj, &mut image);
+    Ok(Some((image, stats)))
+}
+
+/// PALETTE COLOR stores indices into the Red/Green/Blue Palette Color
+/// LUTs, which dicom-pixeldata would render as grayscale. Map the first
+/// frame through the LUTs to RGB; windows do not apply. Returns `None` for
+/// any other photometric interpretation.
+fn decode_palette_color(
+    obj: &DefaultDicomObject,
+    pixel_data: &DecodedPixelData<'_>,
+) -> Result<Option<DynamicImage>> {
+    if dicom_text(obj, Tag(0x0028, 0x0004)).as_deref() != Some("PALETTE COLOR") {
+        return Ok(None);
+    }
+    if obj.element(Tag(0x0028, 0x1221)).is_ok() {
+        bail!("Segmented palette color LUTs are not supported");
+    }
+
+    let indices: Vec<u16> = match pixel_data.bits_allocated() {
+        8 => pixel_data
+            .frame_data(0)?
+            .iter()
+            .map(|&index| index as u16)
+            .collect(),
+        16 => pixel_data.frame_data_ow(0)?,
+        bits => bail!(
+            "PALETTE COLOR with {} bits allocated is not supported",
+            bits
+        ),
+    };
+    let red = palette_lut(obj, Tag(0x0028, 0x1101), Tag(0x0028, 0x1201))?;
+    let green = palette_lut(obj, Tag(0x0028, 0x1102), Tag(0x0028, 0x1202))?;
+    let blue = palette_lut(obj, Tag(0x0028, 0x1103), Tag(0x0028, 0x1203))?;
+
+    let rgb = indices
+        .iter()
+        .flat_map(|&index| [red.map(index), green.map(index), blue.map(index)])
+        .collect();
+    let image = RgbImage::from_raw(pixel_data.columns(), pixel_data.rows(), rgb)
+        .context("Invalid PALETTE COLOR frame")?;
+    Ok(Some(DynamicImage::ImageRgb8(image)))
+}
+
+/// One channel of a palette, scaled to 8 bits.
+struct PaletteLut {
+    first_mapped: i32,
+    entries: Vec<u8>,
+}
+
+impl PaletteLut {
+    /// Indices below the first mapped value use the first entry and those
+    /// past the end the last, as PS3.3 C.7.6.3.1.5 specifies.
+    fn map(&self, index: u16) -> u8 {
+        let position = (index as i32 - self.first_mapped).clamp(0, self.entries.len() as i32 - 1);
+        self.entries[position as usize]
+    }
+}
+
+/// Read a Palette Color LUT from its descriptor (entries, first mapped
+/// value, bits per entry) and data element.
+fn palette_lut(obj: &DefaultDicomObject, descriptor: Tag, data: Tag) -> Result<PaletteLut> {
+    let descriptor: Vec<i32> = obj
+        .element(descriptor)
+        .ok()
+        .and_then(|e| e.to_multi_int().ok())
+        .with_context(|| format!("Missing palette LUT descriptor {}", descriptor))?;
+    let &[count, first_mapped, bits] = descriptor.as_slice() else {
+        bail!(
+            "Palette LUT descriptor has {} values, expected 3",
+            descriptor.len()
+        );
+    };
+    // A count of 0 means 65536 entries; US descriptors may read as negative
+    let count = match count as u16 {
+        0 => 65536,
+        count => count as usize,
+    };
+    // First mapped is US for 8/16-bit unsigned pixels and SS otherwise
+    let first_mapped = if pixel_representation(obj) == 0 {
+        first_mapped as u16 as i32
+    } else {
+        first_mapped as i16 as i32
+    };
+
+    let bytes = obj
+        .element(data)
+        .with_context(|| format!("Missing palette LUT data {}", data))?
+        .to_bytes()?;
+    let entries: Vec<u8> = match bits {
+        // 8-bit entries are either packed or one per 16-bit word
+        8 if bytes.len() == count => bytes.to_vec(),
+        8 => bytes.chunks_exact(2).map(|word| word[0]).collect(),
+        16 => bytes.chunks_exact(2).map(|word| word[1]).collect(),
+        bits => bail!("Palette LUT with {} bits per entry is not supported", bits),
+    };
+    if entries.is_empty() {
+        bail!("Palette LUT {} is empty", data);
+    }
+    Ok(PaletteLut {
+        first_mapped,
+        entries,
+    })
+}
+
+fn pixel_representation(obj: &DefaultDicomObject) -> u16 {
+    obj.element(Tag(0x0028, 0x0103))
+        .ok()
+        .and_then(|e| e.to_int().ok())
+        .unwrap_or(0)
+}
+
+/// YBR_FULL to RGB as defined in PS3.3 C.7.6.3.1.2.
+fn ybr_full_to_rgb(y: u8, cb: u8, cr: u8) -> [u8; 3] {
+    let (y, cb, cr) = (y as f64, cb as f64 - 128.0, cr as f64 - 128.0);
+    let clamp = |v: f64| v.round().clamp(0.0, 255.0) as u8;
+    [
+        clamp(y + 1.402 * cr),
+        clamp(y - 0.344136 * cb - 0.714136 * cr),
+        clamp(y + 1.772 * cb),
+    ]
+}
+
+/// Window spanning the (low, high) percentiles of the modality-LUT values
+/// of frame 0, so a few outlier pixels do not crush the contrast the way
+/// min-max normalization does. Only used for grayscale files that carry no
+/// WindowCenter or VOI LUT Sequence of their own.
+fn percentile_window(
+    obj: &DefaultDicomObject,
+    pixel_data: &DecodedPixelData<'_>,
+    (low, high): (f64, f64),
+) -> Option<(f64, f64)> {
+    if has_own_window(obj) || pixel_data.samples_per_pixel() != 1 {
+        return None;
+    }
+
+    let mut values: Vec<f32> = pixel_data.to_vec_frame(0).ok()?;
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable_by(f32::total_cmp);
+
+    let last = (values.len() - 1) as f64;
+    let at = |percentile: f64| {
+        let index = (percentile / 100.0 * last).round().clamp(0.0, last) as usize;
+        values[index] as f64
+    };
+    let (low, high) = (at(low), at(high));
+    Some(((low + high) / 2.0, (high - low).max(1.0)))
+}
+
+/// PresentationLUTShape (2050,0020) INVERSE flips the output after the VOI
+/// LUT. MONOCHROME1 is already inverted while decoding, so a MONOCHROME1
+/// image with INVERSE ends up not inverted at all, as a PACS would show it.
+fn apply_presentation_lut_shape(obj: &DefaultDicomObject, image: &mut DynamicImage) {
+    if dicom_text(obj, Tag(0x2050, 0x0020)).as_deref() == Some("INVERSE") {
+        image.invert();
+    }
+}
+
+/// Second attempt for files the default path cannot decode: reopen with a
+/// more lenient reader (odd element lengths rounded up, preamble detection)
+/// and decode only the first frame, which tolerates some fragment layouts
+/// that whole-object decoding rejects.
+fn decode_image_permissive(
+    dicom_path: &Path,
+    window: Option<(f64, f64)>,
+    auto_window: Option<(f64, f64)>,
+    with_stats: bool,
+) -> Result<(DynamicImage, Option<PixelStats>)> {
+    let obj = OpenFileOptions::new()
+        .read_preamble(ReadPreamble::Auto)
+        .odd_length_strategy(OddLengthStrategy::NextEven)
+        .open_file(dicom_path)?;
+    let pixel_data = obj.decode_pixel_data_frame(0)?;
+    if let Some(image) = decode_palette_color(&obj, &pixel_data)? {
+        return Ok((image, None));
+    }
+    let window = window.or_else(|| {
+        auto_window.and_then(|percentiles| percentile_window(&obj, &pixel_data, percentiles))
+    });
+    let mut image = pixel_data.to_dynamic_image_with_options(0, &render_options(window))?;
+    apply_presentation_lut_shape(&obj, &mut image);
+    // Only the first frame was decoded
+    let stats = with_stats.then(|| pixel_stats(&pixel_data)).flatten();
+    Ok((image, stats))
+}
+
+/// Frames after the first, rendered through the same pipeline as the first
+/// frame in `decode_image`, for multi-page output. Each frame gets its own
+/// window where an enhanced multi-frame object defines one per frame; a
+/// percentile window is taken from frame 0 for all of them. Overlays are
+/// not drawn on them. Stops after `complete_frames` when the pixel data is
+/// truncated. Objects with more than `PARALLEL_FRAMES` frames are rendered
+/// in parallel, keeping frame order.
+fn decode_later_frames(
+    obj: &DefaultDicomObject,
+    options: &ConvertOptions,
+    complete_frames: Option<u32>,
+) -> Result<Vec<DynamicImage>> {
+    if dicom_text(obj, Tag(0x0028, 0x0004)).as_deref() == Some("PALETTE COLOR") {
+        bail!("multi-page output does not support PALETTE COLOR");
+    }
+    let pixel_data = obj.decode_pixel_data()?;
+    let percentile = OnceLock::new();
+    let frame_count = complete_frames.map_or(pixel_data.number_of_frames(), |complete| {
+        complete.min(pixel_data.number_of_frames())
+    });
+    let render = |frame: u32| -> Result<DynamicImage> {
+        let (window, auto_window) = frame_window(obj, options, frame);
+        let window = window.or_else(|| {
+            let percentiles = auto_window?;
+            *percentile.get_or_init(|| percentile_window(obj, &pixel_data, percentiles))
+        });
+        let mut image = pixel_data.to_dynamic_image_with_options(frame, &render_options(window))?;
+        apply_presentation_lut_shape(obj, &mut image);
+        Ok(image)
+    };
+    if frame_count > PARALLEL_FRAMES {
+        // Nested in the per-file loop, on the same global pool, so idle
+        // workers pick up frames without adding threads
+        (1..frame_count).into_par_iter().map(render).collect()
+    } else {
+        (1..frame_count).map(render).collect()
+    }
+}
+
+/// NumberOfFrames above which `decode_later_frames` renders in parallel;
+/// below it the per-file parallelism already keeps every core busy.
+const PARALLEL_FRAMES: u32 = 16;
+
 fn has_pixel_data(obj: &DefaultDicomObject) -> bool {
     const PIXEL_TAGS: [Tag; 3] = [
         Tag(0x7FE0, 0x0010),
@@ -75,19 +908,315 @@ fn has_pixel_data(obj: &DefaultDicomObject) -> bool {
     PIXEL_TAGS.iter().any(|tag| obj.element(*tag).is_ok())
 }
 
-fn save_image(image: &DynamicImage, png_path: &Path) -> Result<()> {
+/// Compare native PixelData against the size Rows, Columns, SamplesPerPixel
+/// and BitsAllocated imply, so truncated transfers fail with the actual
+/// numbers instead of a decoder error. A short first frame fails the file;
+/// if only later frames are missing the first is still converted, or with
+/// `multipage` every complete frame, with a warning. Returns the number of
+/// complete frames when some are missing. Encapsulated (compressed) data is
+/// left to the decoder.
+fn check_pixel_data_length(
+    obj: &DefaultDicomObject,
+    dicom_path: &Path,
+    multipage: bool,
+) -> Result<Option<u32>> {
+    let Some(pixel_data) = obj
+        .element(Tag(0x7FE0, 0x0010))
+        .ok()
+        .and_then(|element| element.value().primitive())
+    else {
+        return Ok(None);
+    };
+    let int = |tag: Tag| -> Option<u64> { obj.element(tag).ok().and_then(|e| e.to_int().ok()) };
+    let (Some(rows), Some(columns), Some(bits_allocated)) = (
+        int(Tag(0x0028, 0x0010)),
+        int(Tag(0x0028, 0x0011)),
+        int(Tag(0x0028, 0x0100)),
+    ) else {
+        return Ok(None);
+    };
+    let mut samples = int(Tag(0x0028, 0x0002)).unwrap_or(1);
+    // 4:2:2 data stores two samples per pixel (Y plus alternating Cb/Cr)
+    if dicom_text(obj, Tag(0x0028, 0x0004)).is_some_and(|pi| pi.ends_with("_422")) {
+        samples = 2;
+    }
+    let frames = int(Tag(0x0028, 0x0008)).unwrap_or(1).max(1);
+    // A corrupt header can claim sizes no file could hold
+    let sizes = rows
+        .checked_mul(columns)
+        .and_then(|pixels| pixels.checked_mul(samples))
+        .and_then(|samples| samples.checked_mul(bits_allocated))
+        .and_then(|frame_bits| Some((frame_bits, frame_bits.checked_mul(frames)?)));
+    let Some((frame_bits, total_bits)) = sizes else {
+        bail!(
+            "implausible pixel data size: {} rows x {} columns x {} samples x {} bits x {} frames",
+            rows,
+            columns,
+            samples,
+            bits_allocated,
+            frames
+        );
+    };
+    let frame_len = frame_bits.div_ceil(8);
+    let total_len = total_bits.div_ceil(8);
+    let actual = pixel_data.calculate_byte_len() as u64;
+
+    if actual < frame_len {
+        bail!(
+            "pixel data truncated: expected {} bytes for the first frame, got {}",
+            frame_len,
+            actual
+        );
+    }
+    if actual < total_len {
+        // At least one, since the first frame is complete
+        let complete_frames = (actual * 8 / frame_bits.max(1)) as u32;
+        let converted = if multipage && complete_frames > 1 {
+            format!("writing the {} complete frames", complete_frames)
+        } else {
+            "converting only the first frame".to_string()
+        };
+        crate::utils::logging::warn(format_args!(
+            "{} {}: pixel data truncated: expected {} bytes for {} frames, got {}; {}",
+            "⚠".yellow(),
+            dicom_path.display(),
+            total_len,
+            frames,
+            actual,
+            converted
+        ));
+        return Ok(Some(complete_frames));
+    }
+    Ok(None)
+}
+
+/// Writes of one file with `verify_output`, including the first.
+const VERIFY_ATTEMPTS: u32 = 2;
+
+/// Write `pages` in the output format; only TIFF stores more than the
+/// first page.
+fn save_image(
+    pages: &[DynamicImage],
+    png_path: &Path,
+    spacing: Option<(f64, f64)>,
+    options: &ConvertOptions,
+) -> Result<()> {
     if let Some(parent) = png_path.parent() {
         fs::create_dir_all(parent)?;
     }
-    image
-        .save(png_path)
-        .with_context(|| format!("Unable to save PNG to {}", png_path.display()))?;
+    let mut attempt = 1;
+    loop {
+        File::create(png_path)
+            .map_err(Into::into)
+            .and_then(|file| write_image(pages, BufWriter::new(file), spacing, options))
+            .with_context(|| {
+                format!(
+                    "Unable to save {} to {}",
+                    options.output_format.name(),
+                    png_path.display()
+                )
+            })?;
+        if !options.verify_output {
+            return Ok(());
+        }
+        match verify_written(png_path, &pages[0]) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < VERIFY_ATTEMPTS => {
+                crate::utils::logging::warn(format_args!(
+                    "{} Rewriting {}: {:#}",
+                    "⚠".yellow(),
+                    png_path.display(),
+                    e
+                ));
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Decode the file just written and check it has the dimensions of
+/// `expected`, its first page.
+fn verify_written(path: &Path, expected: &DynamicImage) -> Result<()> {
+    let written = dicom_pixeldata::image::open(path)
+        .with_context(|| format!("Verification could not decode {}", path.display()))?;
+    if (written.width(), written.height()) != (expected.width(), expected.height()) {
+        bail!(
+            "Verification failed for {}: decoded {}x{}, expected {}x{}",
+            path.display(),
+            written.width(),
+            written.height(),
+            expected.width(),
+            expected.height()
+        );
+    }
     Ok(())
 }
 
-pub fn extract_metadata(dicom_path: &Path) -> Result<FileMetadata> {
-    let obj: DefaultDicomObject = open_file(dicom_path)
+/// The file contents, for output that is not written to a loose file.
+fn encode_image(
+    pages: &[DynamicImage],
+    spacing: Option<(f64, f64)>,
+    options: &ConvertOptions,
+) -> Result<Vec<u8>> {
+    let mut bytes = Cursor::new(Vec::new());
+    write_image(pages, &mut bytes, spacing, options)
+        .with_context(|| format!("Unable to encode {}", options.output_format.name()))?;
+    Ok(bytes.into_inner())
+}
+
+fn write_image<W: Write + Seek>(
+    pages: &[DynamicImage],
+    writer: W,
+    spacing: Option<(f64, f64)>,
+    options: &ConvertOptions,
+) -> Result<()> {
+    match options.output_format {
+        OutputFormat::Png => write_png(&pages[0], writer, spacing, options.png_compression),
+        OutputFormat::Tiff => write_tiff(pages, writer, spacing),
+    }
+}
+
+/// One TIFF directory per page, keeping 8/16-bit grayscale and RGB
+/// samples as they are; other layouts are written as 8-bit RGBA. Spacing
+/// becomes XResolution/YResolution in pixels per centimeter.
+fn write_tiff<W: Write + Seek>(
+    pages: &[DynamicImage],
+    writer: W,
+    spacing: Option<(f64, f64)>,
+) -> Result<()> {
+    fn write_page<W: Write + Seek, C: tiff::encoder::colortype::ColorType>(
+        encoder: &mut TiffEncoder<W>,
+        (width, height): (u32, u32),
+        data: &[C::Inner],
+        spacing: Option<(f64, f64)>,
+    ) -> Result<()>
+    where
+        [C::Inner]: tiff::encoder::TiffValue,
+    {
+        let mut image = encoder.new_image::<C>(width, height)?;
+        if let Some((row_spacing, col_spacing)) = spacing {
+            let per_cm = |spacing_mm: f64| Rational {
+                n: (10_000.0 / spacing_mm).round() as u32,
+                d: 1000,
+            };
+            image.resolution_unit(ResolutionUnit::Centimeter);
+            image.x_resolution(per_cm(col_spacing));
+            image.y_resolution(per_cm(row_spacing));
+        }
+        image.write_data(data)?;
+        Ok(())
+    }
+
+    let mut encoder = TiffEncoder::new(writer)?;
+    for page in pages {
+        let size = (page.width(), page.height());
+        match page {
+            DynamicImage::ImageLuma8(img) => {
+                write_page::<_, colortype::Gray8>(&mut encoder, size, img, spacing)?
+            }
+            DynamicImage::ImageLuma16(img) => {
+                write_page::<_, colortype::Gray16>(&mut encoder, size, img, spacing)?
+            }
+            DynamicImage::ImageRgb8(img) => {
+                write_page::<_, colortype::RGB8>(&mut encoder, size, img, spacing)?
+            }
+            DynamicImage::ImageRgb16(img) => {
+                write_page::<_, colortype::RGB16>(&mut encoder, size, img, spacing)?
+            }
+            other => {
+                write_page::<_, colortype::RGBA8>(&mut encoder, size, &other.to_rgba8(), spacing)?
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_png<W: Write>(
+    image: &DynamicImage,
+    writer: W,
+    spacing: Option<(f64, f64)>,
+    compression: PngCompression,
+) -> Result<()> {
+    if let Some(spacing) = spacing {
+        return write_png_with_spacing(image, writer, spacing, compression);
+    }
+    let compression = match compression {
+        PngCompression::Fast => CompressionType::Fast,
+        PngCompression::Default => CompressionType::default(),
+        PngCompression::Best => CompressionType::Best,
+    };
+    image.write_with_encoder(PngEncoder::new_with_quality(
+        writer,
+        compression,
+        FilterType::default(),
+    ))?;
+    Ok(())
+}
+
+/// Encode the image with the `png` crate directly so that a pHYs chunk
+/// (pixels per meter) can be written from the (row, column) spacing in mm.
+fn write_png_with_spacing<W: Write>(
+    image: &DynamicImage,
+    writer: W,
+    (row_spacing, col_spacing): (f64, f64),
+    compression: PngCompression,
+) -> Result<()> {
+    let (color_type, bit_depth, data) = match image {
+        DynamicImage::ImageLuma8(img) => (
+            png::ColorType::Grayscale,
+            png::BitDepth::Eight,
+            img.to_vec(),
+        ),
+        DynamicImage::ImageLumaA8(img) => (
+            png::ColorType::GrayscaleAlpha,
+            png::BitDepth::Eight,
+            img.to_vec(),
+        ),
+        DynamicImage::ImageRgb8(img) => (png::ColorType::Rgb, png::BitDepth::Eight, img.to_vec()),
+        DynamicImage::ImageLuma16(img) => (
+            png::ColorType::Grayscale,
+            png::BitDepth::Sixteen,
+            // PNG stores 16-bit samples big endian
+            img.iter().flat_map(|v| v.to_be_bytes()).collect(),
+        ),
+        DynamicImage::ImageRgb16(img) => (
+            png::ColorType::Rgb,
+            png::BitDepth::Sixteen,
+            img.iter().flat_map(|v| v.to_be_bytes()).collect(),
+        ),
+        other => (
+            png::ColorType::Rgba,
+            png::BitDepth::Eight,
+            other.to_rgba8().into_raw(),
+        ),
+    };
+
+    let mut encoder = png::Encoder::new(writer, image.width(), image.height());
+    encoder.set_color(color_type);
+    encoder.set_depth(bit_depth);
+    encoder.set_compression(match compression {
+        PngCompression::Fast => png::Compression::Fast,
+        PngCompression::Default => png::Compression::Default,
+        PngCompression::Best => png::Compression::Best,
+    });
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: (1000.0 / col_spacing).round() as u32,
+        yppu: (1000.0 / row_spacing).round() as u32,
+        unit: png::Unit::Meter,
+    }));
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Header fields of `dicom_path`, plus the values of `extra_tags`.
+pub fn extract_metadata(dicom_path: &Path, extra_tags: &[Tag]) -> Result<FileMetadata> {
+    let obj: DefaultDicomObject = open_file_with_retry(dicom_path)
         .with_context(|| format!("Failed to open DICOM file {}", dicom_path.display()))?;
+    let sop_class_uid = dicom_text(&obj, Tag(0x0008, 0x0016));
+    let (study_date, study_date_source) = study_date(&obj).unzip();
 
     Ok(FileMetadata {
         folder_relative: PathBuf::new(),
@@ -96,8 +1225,14 @@ pub fn extract_metadata(dicom_path: &Path) -> Result<FileMetadata> {
             .and_then(|name| name.to_str())
             .unwrap_or_default()
             .to_string(),
-        study_date: dicom_date(&obj, Tag(0x0008, 0x0020)),
+        study_date,
+        study_date_source,
         modality: dicom_text(&obj, Tag(0x0008, 0x0060)),
+        sop_class_name: sop_class_uid.as_deref().and_then(sop_class_name),
+        sop_class_uid,
+        series_instance_uid: dicom_text(&obj, Tag(0x0020, 0x000E)),
+        instance_number: dicom_text(&obj, Tag(0x0020, 0x0013)).and_then(|v| v.parse().ok()),
+        image_type: dicom_text(&obj, Tag(0x0008, 0x0008)),
         manufacturer: dicom_text(&obj, Tag(0x0008, 0x0070)),
         study_description: dicom_text(&obj, Tag(0x0008, 0x1030)),
         series_description: dicom_text(&obj, Tag(0x0008, 0x103E)),
@@ -112,5 +1247,378 @@ pub fn extract_metadata(dicom_path: &Path) -> Result<FileMetadata> {
             .ok()
             .and_then(|e| e.to_int().ok()),
         pixel_spacing: pixel_spacing(&obj),
+        frame_positions: frame_positions(&obj),
+        spacing_embedded: None,
+        pixel_min: None,
+        pixel_max: None,
+        pixel_mean: None,
+        decoded_with_fallback: false,
+        file_hash: None,
+        extra_tags: extra_tag_values(&obj, extra_tags),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::value::DataSetSequence;
+    use dicom::core::{DataElement, PrimitiveValue, VR};
+    use dicom_object::mem::InMemElement;
+    use dicom_object::meta::FileMetaTableBuilder;
+    use dicom_object::InMemDicomObject;
+
+    fn file_object(elements: impl IntoIterator<Item = InMemElement>) -> DefaultDicomObject {
+        InMemDicomObject::from_element_iter(elements)
+            .with_meta(
+                FileMetaTableBuilder::new()
+                    .transfer_syntax("1.2.840.10008.1.2.1")
+                    .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+                    .media_storage_sop_instance_uid("1.2.3.4"),
+            )
+            .unwrap()
+    }
+
+    fn sequence(tag: Tag, items: Vec<InMemDicomObject>) -> InMemElement {
+        DataElement::new(tag, VR::SQ, DataSetSequence::from(items))
+    }
+
+    /// Multi-valued text as the parser stores it.
+    fn strs(values: &str) -> PrimitiveValue {
+        PrimitiveValue::Strs(values.split('\\').map(String::from).collect())
+    }
+
+    /// Per-frame functional group item with a FrameVOILUTSequence.
+    fn voi_group(centers: &str, widths: &str, explanations: &str) -> InMemDicomObject {
+        let voi = InMemDicomObject::from_element_iter([
+            DataElement::new(Tag(0x0028, 0x1050), VR::DS, strs(centers)),
+            DataElement::new(Tag(0x0028, 0x1051), VR::DS, strs(widths)),
+            DataElement::new(Tag(0x0028, 0x1055), VR::LO, strs(explanations)),
+        ]);
+        InMemDicomObject::from_element_iter([sequence(Tag(0x0028, 0x9132), vec![voi])])
+    }
+
+    /// Native single-frame image with 8 bits per sample.
+    fn image_object(
+        photometric: &str,
+        (rows, columns): (u16, u16),
+        samples_per_pixel: u16,
+        pixels: Vec<u8>,
+    ) -> DefaultDicomObject {
+        let us = |element: u16, value: u16| {
+            DataElement::new(Tag(0x0028, element), VR::US, PrimitiveValue::from(value))
+        };
+        file_object([
+            us(0x0002, samples_per_pixel),
+            DataElement::new(
+                Tag(0x0028, 0x0004),
+                VR::CS,
+                PrimitiveValue::from(photometric),
+            ),
+            us(0x0010, rows),
+            us(0x0011, columns),
+            us(0x0100, 8),
+            us(0x0101, 8),
+            us(0x0102, 7),
+            us(0x0103, 0),
+            DataElement::new(Tag(0x7FE0, 0x0010), VR::OB, PrimitiveValue::from(pixels)),
+        ])
+    }
+
+    fn decoded_luma(obj: &DefaultDicomObject) -> Vec<u8> {
+        let (image, _) = decode_image(obj, None, None, false).unwrap();
+        image.to_luma8().into_raw()
+    }
+
+    #[test]
+    fn presentation_lut_shape_inverse_flips_the_output() {
+        let pixels = vec![0, 255];
+        let plain = image_object("MONOCHROME2", (1, 2), 1, pixels.clone());
+        assert_eq!(decoded_luma(&plain), [0, 255]);
+
+        let mut inverse = plain.clone();
+        inverse.put(DataElement::new(
+            Tag(0x2050, 0x0020),
+            VR::CS,
+            PrimitiveValue::from("INVERSE"),
+        ));
+        assert_eq!(decoded_luma(&inverse), [255, 0]);
+
+        let monochrome1 = image_object("MONOCHROME1", (1, 2), 1, pixels);
+        assert_eq!(decoded_luma(&monochrome1), [255, 0]);
+
+        // Both inversions cancel out
+        let mut both = monochrome1.clone();
+        both.put(DataElement::new(
+            Tag(0x2050, 0x0020),
+            VR::CS,
+            PrimitiveValue::from("INVERSE"),
+        ));
+        assert_eq!(decoded_luma(&both), [0, 255]);
+    }
+
+    #[test]
+    fn native_ybr_422_shares_chroma_between_pixel_pairs() {
+        // Y1 Y2 Cb Cr per pair: a neutral pair, then a reddish one
+        let pixels = vec![10, 240, 128, 128, 100, 100, 90, 200];
+        let obj = image_object("YBR_FULL_422", (1, 4), 3, pixels);
+        let image = decode_native_ybr_422(&obj).unwrap().unwrap().to_rgb8();
+        assert_eq!((image.width(), image.height()), (4, 1));
+        assert_eq!(image.get_pixel(0, 0).0, [10, 10, 10]);
+        assert_eq!(image.get_pixel(1, 0).0, [240, 240, 240]);
+        let [red, green, blue] = image.get_pixel(2, 0).0;
+        assert!(red > 100 && green < 100 && blue < 100);
+        assert_eq!(image.get_pixel(3, 0), image.get_pixel(2, 0));
+
+        let rgb = image_object("RGB", (1, 1), 3, vec![1, 2, 3]);
+        assert!(decode_native_ybr_422(&rgb).unwrap().is_none());
+    }
+
+    #[test]
+    fn float_pixel_data_is_windowed_into_16_bits() {
+        let us = |element: u16, value: u16| {
+            DataElement::new(Tag(0x0028, element), VR::US, PrimitiveValue::from(value))
+        };
+        let samples = PrimitiveValue::F32([0.0, 0.5, 1.0, f32::NAN].into_iter().collect());
+        let obj = file_object([
+            us(0x0002, 1),
+            us(0x0010, 2),
+            us(0x0011, 2),
+            DataElement::new(Tag(0x7FE0, 0x0008), VR::OF, samples),
+        ]);
+
+        let (image, stats) = decode_float_pixel_data(&obj, None, None, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(image.to_luma16().into_raw(), [0, 32768, 65535, 0]);
+        assert_eq!(stats, Some((0.0, 1.0, 0.5)));
+
+        let (image, _) = decode_float_pixel_data(&obj, Some((0.5, 0.5)), None, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(image.to_luma16().into_raw(), [0, 32768, 65535, 0]);
+        let (image, _) = decode_float_pixel_data(&obj, Some((1.0, 1.0)), None, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(image.to_luma16().into_raw(), [0, 0, 32768, 0]);
+
+        let regular = image_object("MONOCHROME2", (1, 1), 1, vec![0]);
+        assert!(decode_float_pixel_data(&regular, None, None, false)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn palette_color_maps_indices_through_each_lut() {
+        let mut obj = image_object("PALETTE COLOR", (1, 4), 1, vec![0, 1, 2, 9]);
+        let mut lut = |channel: u16, bits: u16, vr: VR, data: PrimitiveValue| {
+            obj.put(DataElement::new(
+                Tag(0x0028, 0x1100 + channel),
+                VR::US,
+                PrimitiveValue::U16([3, 1, bits].into_iter().collect()),
+            ));
+            obj.put(DataElement::new(Tag(0x0028, 0x1200 + channel), vr, data));
+        };
+        // 16-bit entries, 8-bit entries one per word, and packed 8-bit entries
+        let red = [0x1000u16, 0x8000, 0xFF00].into_iter().collect();
+        lut(1, 16, VR::OW, PrimitiveValue::U16(red));
+        let green = [0x11u16, 0x22, 0x33].into_iter().collect();
+        lut(2, 8, VR::OW, PrimitiveValue::U16(green));
+        lut(3, 8, VR::OB, PrimitiveValue::from(vec![1u8, 2, 3]));
+
+        let pixel_data = obj.decode_pixel_data().unwrap();
+        let image = decode_palette_color(&obj, &pixel_data)
+            .unwrap()
+            .unwrap()
+            .to_rgb8();
+        let pixels: Vec<[u8; 3]> = image.pixels().map(|pixel| pixel.0).collect();
+        // Index 0 is below the first mapped value 1, index 9 past the end
+        assert_eq!(
+            pixels,
+            [
+                [0x10, 0x11, 1],
+                [0x10, 0x11, 1],
+                [0x80, 0x22, 2],
+                [0xFF, 0x33, 3]
+            ]
+        );
+    }
+
+    #[test]
+    fn planar_and_interleaved_rgb_decode_alike() {
+        let interleaved = image_object("RGB", (1, 2), 3, vec![255, 0, 0, 0, 255, 0]);
+        let mut planar = image_object("RGB", (1, 2), 3, vec![255, 0, 0, 255, 0, 0]);
+        planar.put(DataElement::new(
+            Tag(0x0028, 0x0006),
+            VR::US,
+            PrimitiveValue::from(1u16),
+        ));
+
+        for obj in [interleaved, planar] {
+            let (image, _) = decode_image(&obj, None, None, false).unwrap();
+            let pixels: Vec<[u8; 3]> = image.to_rgb8().pixels().map(|pixel| pixel.0).collect();
+            assert_eq!(pixels, [[255, 0, 0], [0, 255, 0]]);
+        }
+    }
+
+    #[test]
+    fn bits_above_bits_stored_are_ignored() {
+        let mut obj = image_object("MONOCHROME2", (1, 4), 1, Vec::new());
+        for (element, value) in [(0x0100, 16u16), (0x0101, 12), (0x0102, 11)] {
+            obj.put(DataElement::new(
+                Tag(0x0028, element),
+                VR::US,
+                PrimitiveValue::from(value),
+            ));
+        }
+        // The high nibble of the last two samples is garbage
+        let samples = [0x0000u16, 0x0FFF, 0xF000, 0xFFFF].into_iter().collect();
+        obj.put(DataElement::new(
+            Tag(0x7FE0, 0x0010),
+            VR::OW,
+            PrimitiveValue::U16(samples),
+        ));
+
+        let (image, _) = decode_image(&obj, Some((2048.0, 4096.0)), None, false).unwrap();
+        assert_eq!(image.to_luma8().into_raw(), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn many_frames_keep_their_order() {
+        // Frame i is filled with the value i
+        let count = PARALLEL_FRAMES as u8 * 2;
+        let pixels = (0..count).flat_map(|frame| [frame; 4]).collect();
+        let mut obj = image_object("MONOCHROME2", (2, 2), 1, pixels);
+        obj.put(DataElement::new(
+            Tag(0x0028, 0x0008),
+            VR::IS,
+            PrimitiveValue::from(count.to_string()),
+        ));
+        let options = ConvertOptions {
+            window: Some((count as f64 / 2.0, count as f64)),
+            ..Default::default()
+        };
+
+        let frames = decode_later_frames(&obj, &options, None).unwrap();
+        assert_eq!(frames.len(), count as usize - 1);
+        let firsts: Vec<u8> = frames.iter().map(|f| f.to_luma8().into_raw()[0]).collect();
+        assert!(
+            firsts.windows(2).all(|pair| pair[0] < pair[1]),
+            "{firsts:?}"
+        );
+        let limited = decode_later_frames(&obj, &options, Some(3)).unwrap();
+        assert_eq!(limited.len(), 2);
+    }
+
+    #[test]
+    fn pixel_data_length_counts_complete_frames() {
+        let frames = |count: &str| {
+            DataElement::new(Tag(0x0028, 0x0008), VR::IS, PrimitiveValue::from(count))
+        };
+        let path = Path::new("test.dcm");
+        // Three 2x2 frames, with only two and a half present
+        let mut obj = image_object("MONOCHROME2", (2, 2), 1, vec![0; 10]);
+        obj.put(frames("3"));
+        assert_eq!(check_pixel_data_length(&obj, path, true).unwrap(), Some(2));
+        obj.put(frames("2"));
+        assert_eq!(check_pixel_data_length(&obj, path, true).unwrap(), None);
+
+        let short = image_object("MONOCHROME2", (4, 4), 1, vec![0; 10]);
+        let error = check_pixel_data_length(&short, path, false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "pixel data truncated: expected 16 bytes for the first frame, got 10"
+        );
+
+        // Sizes that overflow fail instead of wrapping around
+        let mut huge = image_object("MONOCHROME2", (u16::MAX, u16::MAX), 1, vec![0; 2]);
+        huge.put(frames("2147483647"));
+        let error = check_pixel_data_length(&huge, path, false).unwrap_err();
+        assert!(error.to_string().starts_with("implausible pixel data size"));
+    }
+
+    fn enhanced_object() -> DefaultDicomObject {
+        file_object([sequence(
+            Tag(0x5200, 0x9230),
+            vec![
+                voi_group("40\\-600", "400\\1500", "SOFT\\LUNG"),
+                voi_group("50\\-500", "350\\1400", "SOFT\\LUNG"),
+            ],
+        )])
+    }
+
+    #[test]
+    fn selected_window_reads_each_frames_functional_group() {
+        let obj = enhanced_object();
+        let lung = WindowSelection::Label("lung".to_string());
+        assert_eq!(selected_window(&obj, &lung, 0), Some((-600.0, 1500.0)));
+        assert_eq!(selected_window(&obj, &lung, 1), Some((-500.0, 1400.0)));
+        let first = WindowSelection::Index(0);
+        assert_eq!(selected_window(&obj, &first, 1), Some((50.0, 350.0)));
+        assert_eq!(selected_window(&obj, &WindowSelection::Index(2), 0), None);
+        assert!(has_own_window(&obj));
+    }
+
+    fn root_window(obj: &mut DefaultDicomObject, center: &str, width: &str) {
+        obj.put(DataElement::new(
+            Tag(0x0028, 0x1050),
+            VR::DS,
+            PrimitiveValue::from(center),
+        ));
+        obj.put(DataElement::new(
+            Tag(0x0028, 0x1051),
+            VR::DS,
+            PrimitiveValue::from(width),
+        ));
+    }
+
+    #[test]
+    fn selected_window_prefers_the_frames_window() {
+        let mut obj = enhanced_object();
+        root_window(&mut obj, "100", "200");
+        let first = WindowSelection::Index(0);
+        assert_eq!(selected_window(&obj, &first, 1), Some((50.0, 350.0)));
+
+        let mut plain = file_object([]);
+        root_window(&mut plain, "100", "200");
+        assert_eq!(selected_window(&plain, &first, 0), Some((100.0, 200.0)));
+    }
+
+    #[test]
+    fn frames_render_with_their_own_window() {
+        // Two 1x2 frames with the same samples but different windows
+        let mut obj = image_object("MONOCHROME2", (1, 2), 1, vec![100, 200, 100, 200]);
+        obj.put(DataElement::new(
+            Tag(0x0028, 0x0008),
+            VR::IS,
+            PrimitiveValue::from("2"),
+        ));
+        obj.put(sequence(
+            Tag(0x5200, 0x9230),
+            vec![
+                voi_group("150", "100", "NARROW"),
+                voi_group("100", "400", "WIDE"),
+            ],
+        ));
+        // A root window must not override the frames' own
+        root_window(&mut obj, "0", "10");
+        let options = ConvertOptions::default();
+
+        let (window, auto_window) = frame_window(&obj, &options, 0);
+        assert_eq!(window, Some((150.0, 100.0)));
+        let (first, _) = decode_image(&obj, window, auto_window, false).unwrap();
+        assert_eq!(first.to_luma8().into_raw(), [0, 255]);
+
+        let later = decode_later_frames(&obj, &options, None).unwrap();
+        assert_eq!(later.len(), 1);
+        let second = later[0].to_luma8().into_raw();
+        assert!((120..=135).contains(&second[0]), "{second:?}");
+        assert!((185..=200).contains(&second[1]), "{second:?}");
+
+        // An explicit window still applies to every frame
+        let options = ConvertOptions {
+            window: Some((150.0, 100.0)),
+            ..Default::default()
+        };
+        assert_eq!(frame_window(&obj, &options, 1).0, Some((150.0, 100.0)));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::models::metadata::FileMetadata;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One converted PNG to show in the gallery.
+pub struct IndexEntry {
+    /// Path of the PNG relative to the folder `index.html` is written to
+    pub png_path: PathBuf,
+    pub metadata: FileMetadata,
+}
+
+/// Write `<output_folder>/index.html`: a static gallery of the converted
+/// PNGs grouped by output folder (series folders with `group_by_series`),
+/// ordered by InstanceNumber, followed by any contact sheets found in
+/// `output_folder`. Returns the path of the page.
+pub fn write_html_index(entries: Vec<IndexEntry>, output_folder: &Path) -> Result<PathBuf> {
+    let mut groups: BTreeMap<PathBuf, Vec<IndexEntry>> = BTreeMap::new();
+    for entry in entries {
+        groups
+            .entry(entry.metadata.folder_relative.clone())
+            .or_default()
+            .push(entry);
+    }
+
+    let mut html = String::from(HEADER);
+    for (folder, mut entries) in groups {
+        entries.sort_by(|a, b| {
+            a.metadata
+                .instance_number
+                .unwrap_or(i32::MAX)
+                .cmp(&b.metadata.instance_number.unwrap_or(i32::MAX))
+                .then_with(|| a.png_path.cmp(&b.png_path))
+        });
+        let title = match folder.to_string_lossy().as_ref() {
+            "" | "." => "(root)".to_string(),
+            name => name.to_string(),
+        };
+        let _ = writeln!(
+            html,
+            "<h2>{} <small>({} images)</small></h2>\n<div class=\"grid\">",
+            escape(&title),
+            entries.len()
+        );
+        for entry in &entries {
+            let src = url_path(&entry.png_path);
+            let _ = writeln!(
+                html,
+                "<figure><a href=\"{src}\"><img src=\"{src}\" loading=\"lazy\" alt=\"{name}\"></a>\
+                 <figcaption>{caption}</figcaption></figure>",
+                src = escape(&src),
+                name = escape(&entry.metadata.file_name),
+                caption = caption(&entry.metadata),
+            );
+        }
+        html.push_str("</div>\n");
+    }
+
+    let sheets = contact_sheets(output_folder);
+    if !sheets.is_empty() {
+        html.push_str("<h2>Contact sheets</h2>\n");
+        for sheet in sheets {
+            let src = escape(&sheet);
+            let _ = writeln!(
+                html,
+                "<figure class=\"sheet\"><a href=\"{src}\"><img src=\"{src}\" loading=\"lazy\" alt=\"{src}\"></a>\
+                 <figcaption>{src}</figcaption></figure>"
+            );
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+
+    let index_path = output_folder.join("index.html");
+    fs::write(&index_path, html)
+        .with_context(|| format!("Unable to write {}", index_path.display()))?;
+    Ok(index_path)
+}
+
+const HEADER: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Converted DICOM images</title>
+<style>
+body { font-family: sans-serif; margin: 1rem; background: #111; color: #ddd; }
+h2 { font-size: 1.1rem; margin-top: 2rem; word-break: break-all; }
+small { color: #888; font-weight: normal; }
+.grid { display: grid; grid-template-columns: repeat(auto-fill, minmax(160px, 1fr)); gap: 0.75rem; }
+figure { margin: 0; }
+figure img { width: 100%; aspect-ratio: 1; object-fit: contain; background: #000; }
+figure.sheet img { aspect-ratio: auto; max-width: 100%; }
+figcaption { font-size: 0.75rem; color: #aaa; word-break: break-all; }
+</style>
+</head>
+<body>
+<h1>Converted DICOM images</h1>
+"#;
+
+/// File name, then whichever of modality, instance number and size are known.
+fn caption(metadata: &FileMetadata) -> String {
+    let mut details = Vec::new();
+    if let Some(modality) = &metadata.modality {
+        details.push(modality.clone());
+    }
+    if let Some(instance_number) = metadata.instance_number {
+        details.push(format!("#{}", instance_number));
+    }
+    if let (Some(width), Some(height)) = (metadata.im_width, metadata.im_height) {
+        details.push(format!("{}×{}", width, height));
+    }
+    let mut caption = escape(&metadata.file_name);
+    if !details.is_empty() {
+        caption.push_str("<br>");
+        caption.push_str(&escape(&details.join(" · ")));
+    }
+    caption
+}
+
+/// `*_montage.png` files written by the contact sheet option.
+fn contact_sheets(output_folder: &Path) -> Vec<String> {
+    let mut sheets: Vec<String> = fs::read_dir(output_folder)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| e.file_name().to_str().map(String::from))
+                .filter(|name| name.ends_with("_montage.png"))
+                .collect()
+        })
+        .unwrap_or_default();
+    sheets.sort();
+    sheets
+}
+
+/// Relative URL with `/` separators and the characters that would break
+/// it percent-encoded.
+fn url_path(path: &Path) -> String {
+    path.components()
+        .map(|c| {
+            c.as_os_str()
+                .to_string_lossy()
+                .replace('%', "%25")
+                .replace('#', "%23")
+                .replace('?', "%3F")
+                .replace(' ', "%20")
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
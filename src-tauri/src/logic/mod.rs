@@ -1,5 +1,12 @@
 pub mod anonymize;
 pub mod convert;
+pub mod html_index;
+pub mod montage;
+pub mod nifti;
+pub mod overlay;
+pub mod sr;
 pub mod stats;
 pub mod tags;
+pub mod transcode;
+pub mod transfer_syntax;
 pub mod workflow;
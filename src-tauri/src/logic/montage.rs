@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use image::imageops;
+use image::{Rgb, RgbImage};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Edge length of one square tile in the contact sheet.
+const TILE_SIZE: u32 = 128;
+/// Each glyph pixel of the label font is drawn as a square of this size.
+const LABEL_SCALE: u32 = 2;
+
+/// One converted PNG to place on its series' contact sheet.
+pub struct MontageEntry {
+    pub series_uid: String,
+    pub instance_number: Option<i32>,
+    pub png_path: PathBuf,
+}
+
+/// Tile every series' PNGs, ordered by InstanceNumber, into
+/// `<output_folder>/<SeriesInstanceUID>_montage.png`. Returns the number of
+/// sheets written.
+pub fn write_contact_sheets(
+    entries: Vec<MontageEntry>,
+    output_folder: &Path,
+    columns: usize,
+) -> Result<usize> {
+    let mut series: BTreeMap<String, Vec<MontageEntry>> = BTreeMap::new();
+    for entry in entries {
+        series
+            .entry(entry.series_uid.clone())
+            .or_default()
+            .push(entry);
+    }
+
+    let mut written = 0usize;
+    for (series_uid, mut entries) in series {
+        entries.sort_by(|a, b| {
+            a.instance_number
+                .unwrap_or(i32::MAX)
+                .cmp(&b.instance_number.unwrap_or(i32::MAX))
+                .then_with(|| a.png_path.cmp(&b.png_path))
+        });
+        let sheet_path = output_folder.join(format!("{}_montage.png", series_uid));
+        write_contact_sheet(&entries, &sheet_path, columns)?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+fn write_contact_sheet(entries: &[MontageEntry], sheet_path: &Path, columns: usize) -> Result<()> {
+    let columns = columns.clamp(1, entries.len().max(1));
+    let rows = entries.len().div_ceil(columns);
+    let mut sheet = RgbImage::new(columns as u32 * TILE_SIZE, rows as u32 * TILE_SIZE);
+
+    for (index, entry) in entries.iter().enumerate() {
+        let image = image::open(&entry.png_path)
+            .with_context(|| format!("Unable to read {}", entry.png_path.display()))?;
+        let thumbnail = image.thumbnail(TILE_SIZE, TILE_SIZE).to_rgb8();
+
+        let tile_x = (index % columns) as u32 * TILE_SIZE;
+        let tile_y = (index / columns) as u32 * TILE_SIZE;
+        // Center the thumbnail in its tile
+        let x = tile_x + (TILE_SIZE - thumbnail.width()) / 2;
+        let y = tile_y + (TILE_SIZE - thumbnail.height()) / 2;
+        imageops::overlay(&mut sheet, &thumbnail, x as i64, y as i64);
+
+        if let Some(instance_number) = entry.instance_number {
+            draw_label(
+                &mut sheet,
+                tile_x + 2,
+                tile_y + 2,
+                &instance_number.to_string(),
+            );
+        }
+    }
+
+    sheet
+        .save(sheet_path)
+        .with_context(|| format!("Unable to save contact sheet {}", sheet_path.display()))?;
+    Ok(())
+}
+
+/// 3x5 bitmap glyphs for the characters an InstanceNumber can contain;
+/// each row uses the low three bits, most significant bit on the left.
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => return None,
+    })
+}
+
+/// Yellow text on a black box, so it stays readable on any image.
+fn draw_label(sheet: &mut RgbImage, x: u32, y: u32, text: &str) {
+    let advance = 4 * LABEL_SCALE;
+    let box_width = text.chars().count() as u32 * advance + LABEL_SCALE;
+    let box_height = 5 * LABEL_SCALE + 2 * LABEL_SCALE;
+
+    let mut put = |px: u32, py: u32, color: Rgb<u8>| {
+        if px < sheet.width() && py < sheet.height() {
+            sheet.put_pixel(px, py, color);
+        }
+    };
+
+    for dy in 0..box_height {
+        for dx in 0..box_width {
+            put(x + dx, y + dy, Rgb([0, 0, 0]));
+        }
+    }
+
+    for (i, c) in text.chars().enumerate() {
+        let Some(rows) = glyph(c) else { continue };
+        let origin_x = x + LABEL_SCALE + i as u32 * advance;
+        let origin_y = y + LABEL_SCALE;
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..3u32 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for sy in 0..LABEL_SCALE {
+                    for sx in 0..LABEL_SCALE {
+                        put(
+                            origin_x + col * LABEL_SCALE + sx,
+                            origin_y + row as u32 * LABEL_SCALE + sy,
+                            Rgb([255, 255, 0]),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use crate::models::metadata::{dicom_text, pixel_spacing_mm};
+use crate::utils::discovery::collect_dicom_files;
+use anyhow::{bail, Context, Result};
+use dicom::core::Tag;
+use dicom_object::{open_file, DefaultDicomObject, OpenFileOptions};
+use dicom_pixeldata::PixelDecoder as _;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use owo_colors::OwoColorize;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, serde::Serialize)]
+pub struct VolumeReport {
+    pub series_written: usize,
+    pub series_skipped: usize,
+    pub skipped_series: Vec<String>,
+    pub output_folder: PathBuf,
+}
+
+/// Header fields needed to place one slice in its series volume.
+struct SliceInfo {
+    path: PathBuf,
+    series_uid: String,
+    instance_number: Option<i32>,
+    position: Option<[f64; 3]>,
+    orientation: Option<[f64; 6]>,
+    rows: u32,
+    columns: u32,
+    spacing: Option<(f64, f64)>,
+    slice_thickness: Option<f64>,
+}
+
+/// Group the files under `input_folder` by SeriesInstanceUID, stack each
+/// series into a 3D volume and write it as `<SeriesInstanceUID>.nii.gz`.
+pub fn convert_dicom_to_nifti(input_folder: &Path, output_folder: &Path) -> Result<VolumeReport> {
+    if !input_folder.exists() {
+        bail!("Input folder '{}' does not exist", input_folder.display());
+    }
+
+    fs::create_dir_all(output_folder)
+        .with_context(|| format!("Unable to create output folder {}", output_folder.display()))?;
+
+    let slices: Vec<SliceInfo> = collect_dicom_files(input_folder)
+        .par_iter()
+        .filter_map(|path| read_slice_info(path))
+        .collect();
+
+    let mut series: HashMap<String, Vec<SliceInfo>> = HashMap::new();
+    for slice in slices {
+        series
+            .entry(slice.series_uid.clone())
+            .or_default()
+            .push(slice);
+    }
+
+    let mut series_written = 0usize;
+    let mut skipped_series = Vec::new();
+
+    let mut series_uids: Vec<String> = series.keys().cloned().collect();
+    series_uids.sort();
+
+    for series_uid in series_uids {
+        let mut slices = series.remove(&series_uid).unwrap_or_default();
+        sort_slices(&mut slices);
+
+        let nifti_path = output_folder.join(format!("{}.nii.gz", series_uid));
+        match write_series(&slices, &nifti_path) {
+            Ok(()) => series_written += 1,
+            Err(e) => {
+                crate::utils::logging::warn(format_args!(
+                    "{} Skipping series {} ({} files): {:#}",
+                    "⚠".yellow(),
+                    series_uid,
+                    slices.len(),
+                    e
+                ));
+                skipped_series.push(series_uid);
+            }
+        }
+    }
+
+    Ok(VolumeReport {
+        series_written,
+        series_skipped: skipped_series.len(),
+        skipped_series,
+        output_folder: output_folder.to_path_buf(),
+    })
+}
+
+fn read_slice_info(path: &Path) -> Option<SliceInfo> {
+    // Stop before PixelData: only the header is needed to group and sort
+    let obj: DefaultDicomObject = OpenFileOptions::new()
+        .read_until(Tag(0x7FE0, 0x0010))
+        .open_file(path)
+        .ok()?;
+
+    let Some(series_uid) = dicom_text(&obj, Tag(0x0020, 0x000E)) else {
+        crate::utils::logging::warn(format_args!(
+            "{} Ignoring {} (no SeriesInstanceUID)",
+            "⚠".yellow(),
+            path.display()
+        ));
+        return None;
+    };
+
+    Some(SliceInfo {
+        path: path.to_path_buf(),
+        series_uid,
+        instance_number: dicom_text(&obj, Tag(0x0020, 0x0013)).and_then(|v| v.parse().ok()),
+        position: decimal_values(&obj, Tag(0x0020, 0x0032)).and_then(|v| v.try_into().ok()),
+        orientation: decimal_values(&obj, Tag(0x0020, 0x0037)).and_then(|v| v.try_into().ok()),
+        rows: obj.element(Tag(0x0028, 0x0010)).ok()?.to_int().ok()?,
+        columns: obj.element(Tag(0x0028, 0x0011)).ok()?.to_int().ok()?,
+        spacing: pixel_spacing_mm(&obj),
+        slice_thickness: dicom_text(&obj, Tag(0x0018, 0x0050)).and_then(|v| v.parse().ok()),
+    })
+}
+
+fn decimal_values(obj: &DefaultDicomObject, tag: Tag) -> Option<Vec<f64>> {
+    dicom_text(obj, tag)?
+        .split('\\')
+        .map(|v| v.trim().parse::<f64>().ok())
+        .collect()
+}
+
+fn slice_normal(orientation: &[f64; 6]) -> [f64; 3] {
+    let (r, c) = (&orientation[..3], &orientation[3..]);
+    [
+        r[1] * c[2] - r[2] * c[1],
+        r[2] * c[0] - r[0] * c[2],
+        r[0] * c[1] - r[1] * c[0],
+    ]
+}
+
+/// Sort by position along the slice normal when every slice has
+/// ImagePositionPatient, otherwise by InstanceNumber.
+fn sort_slices(slices: &mut [SliceInfo]) {
+    let normal = slices
+        .first()
+        .and_then(|s| s.orientation)
+        .map(|o| slice_normal(&o))
+        .unwrap_or([0.0, 0.0, 1.0]);
+
+    if slices.iter().all(|s| s.position.is_some()) {
+        let distance = |s: &SliceInfo| {
+            let p = s.position.unwrap_or_default();
+            p[0] * normal[0] + p[1] * normal[1] + p[2] * normal[2]
+        };
+        slices.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+    } else {
+        slices.sort_by_key(|s| s.instance_number.unwrap_or(i32::MAX));
+    }
+}
+
+fn write_series(slices: &[SliceInfo], nifti_path: &Path) -> Result<()> {
+    let first = slices.first().context("series has no slices")?;
+    let (rows, columns) = (first.rows, first.columns);
+
+    if let Some(odd) = slices
+        .iter()
+        .find(|s| s.rows != rows || s.columns != columns)
+    {
+        bail!(
+            "inconsistent dimensions: {}x{} vs {}x{} in {}",
+            columns,
+            rows,
+            odd.columns,
+            odd.rows,
+            odd.path.display()
+        );
+    }
+
+    let slice_len = rows as usize * columns as usize;
+    let decoded: Vec<Vec<f32>> = slices
+        .par_iter()
+        .map(|slice| decode_slice(&slice.path, slice_len))
+        .collect::<Result<_>>()?;
+
+    let (row_spacing, col_spacing) = first.spacing.unwrap_or((1.0, 1.0));
+    let slice_spacing = match (slices.first(), slices.get(1)) {
+        (Some(a), Some(b)) if a.position.is_some() && b.position.is_some() => {
+            let (a, b) = (
+                a.position.unwrap_or_default(),
+                b.position.unwrap_or_default(),
+            );
+            let d = ((b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2) + (b[2] - a[2]).powi(2)).sqrt();
+            Some(d).filter(|d| *d > 0.0)
+        }
+        _ => None,
+    }
+    .or(first.slice_thickness)
+    .unwrap_or(1.0);
+
+    let header = NiftiHeader {
+        dims: [columns as u16, rows as u16, slices.len() as u16],
+        spacing: [col_spacing, row_spacing, slice_spacing],
+        orientation: first.orientation.unwrap_or([1.0, 0.0, 0.0, 0.0, 1.0, 0.0]),
+        origin: first.position.unwrap_or([0.0, 0.0, 0.0]),
+    };
+
+    let file = File::create(nifti_path)
+        .with_context(|| format!("Unable to create {}", nifti_path.display()))?;
+    let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+    encoder.write_all(&header.to_bytes())?;
+    for slice in decoded {
+        for value in slice {
+            encoder.write_all(&value.to_le_bytes())?;
+        }
+    }
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+/// Decode the first frame with the modality LUT applied (e.g. Hounsfield units).
+fn decode_slice(path: &Path, expected_len: usize) -> Result<Vec<f32>> {
+    let obj =
+        open_file(path).with_context(|| format!("Failed to open DICOM file {}", path.display()))?;
+    let pixel_data = obj
+        .decode_pixel_data()
+        .with_context(|| format!("Failed to decode pixel data of {}", path.display()))?;
+    if pixel_data.samples_per_pixel() != 1 {
+        bail!("{} is not a grayscale image", path.display());
+    }
+    let values: Vec<f32> = pixel_data.to_vec_frame(0)?;
+    if values.len() != expected_len {
+        bail!(
+            "{} has {} samples, expected {}",
+            path.display(),
+            values.len(),
+            expected_len
+        );
+    }
+    Ok(values)
+}
+
+struct NiftiHeader {
+    dims: [u16; 3],
+    spacing: [f64; 3],
+    orientation: [f64; 6],
+    origin: [f64; 3],
+}
+
+impl NiftiHeader {
+    /// NIfTI-1 single-file header (348 bytes) plus the empty 4-byte extension,
+    /// describing FLOAT32 voxels with an sform mapping voxels to RAS mm.
+    fn to_bytes(&self) -> Vec<u8> {
+        const DT_FLOAT32: i16 = 16;
+        let mut buf = vec![0u8; 352];
+        let put_i16 =
+            |buf: &mut [u8], at: usize, v: i16| buf[at..at + 2].copy_from_slice(&v.to_le_bytes());
+        let put_f32 = |buf: &mut [u8], at: usize, v: f64| {
+            buf[at..at + 4].copy_from_slice(&(v as f32).to_le_bytes())
+        };
+
+        buf[0..4].copy_from_slice(&348i32.to_le_bytes());
+        buf[38] = b'r';
+
+        // dim: rank 3, then x/y/z sizes; unused dims are 1
+        let dim = [
+            3,
+            self.dims[0] as i16,
+            self.dims[1] as i16,
+            self.dims[2] as i16,
+            1,
+            1,
+            1,
+            1,
+        ];
+        for (i, d) in dim.iter().enumerate() {
+            put_i16(&mut buf, 40 + i * 2, *d);
+        }
+        put_i16(&mut buf, 70, DT_FLOAT32);
+        put_i16(&mut buf, 72, 32);
+
+        // pixdim[0] is qfac; then voxel sizes in mm
+        let pixdim = [1.0, self.spacing[0], self.spacing[1], self.spacing[2]];
+        for (i, p) in pixdim.iter().enumerate() {
+            put_f32(&mut buf, 76 + i * 4, *p);
+        }
+        put_f32(&mut buf, 108, 352.0); // vox_offset
+        put_f32(&mut buf, 112, 1.0); // scl_slope
+        buf[123] = 2; // xyzt_units: millimeters
+
+        // sform (code 1 = scanner anatomical); DICOM is LPS, NIfTI is RAS
+        put_i16(&mut buf, 254, 1);
+        let row = &self.orientation[..3];
+        let col = &self.orientation[3..];
+        let normal = slice_normal(&self.orientation);
+        for axis in 0..3 {
+            let flip = if axis < 2 { -1.0 } else { 1.0 };
+            let srow = [
+                flip * row[axis] * self.spacing[0],
+                flip * col[axis] * self.spacing[1],
+                flip * normal[axis] * self.spacing[2],
+                flip * self.origin[axis],
+            ];
+            for (i, v) in srow.iter().enumerate() {
+                put_f32(&mut buf, 280 + axis * 16 + i * 4, *v);
+            }
+        }
+
+        buf[344..348].copy_from_slice(b"n+1\0");
+        buf
+    }
+}
@@ -0,0 +1,100 @@
+use dicom::core::Tag;
+use dicom_object::DefaultDicomObject;
+use dicom_pixeldata::image::{DynamicImage, Luma, Rgb};
+
+/// One 1-bit overlay plane from a 60xx group.
+struct OverlayPlane {
+    rows: u32,
+    columns: u32,
+    /// 1-based (row, column) of the plane's top left pixel in the image
+    origin: (i64, i64),
+    /// Packed bits, least significant bit first, row by row
+    data: Vec<u8>,
+}
+
+impl OverlayPlane {
+    fn is_set(&self, row: u32, column: u32) -> bool {
+        let index = row as usize * self.columns as usize + column as usize;
+        self.data
+            .get(index / 8)
+            .map(|byte| byte & (1 << (index % 8)) != 0)
+            .unwrap_or(false)
+    }
+}
+
+/// Draw every overlay plane (groups 6000-601E) onto the image in white.
+/// Returns the number of planes drawn; planes embedded in the pixel data's
+/// unused high bits (no OverlayData element) are not supported and skipped.
+pub fn burn_overlays(obj: &DefaultDicomObject, image: &mut DynamicImage) -> usize {
+    let planes: Vec<OverlayPlane> = (0x6000u16..=0x601E)
+        .step_by(2)
+        .filter_map(|group| read_plane(obj, group))
+        .collect();
+
+    if planes.is_empty() {
+        return 0;
+    }
+
+    // Draw in a format with a well-defined "white"
+    if !matches!(
+        image,
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLuma16(_) | DynamicImage::ImageRgb8(_)
+    ) {
+        *image = DynamicImage::ImageRgb8(image.to_rgb8());
+    }
+    let (width, height) = (image.width() as i64, image.height() as i64);
+
+    for plane in &planes {
+        for row in 0..plane.rows {
+            for column in 0..plane.columns {
+                if !plane.is_set(row, column) {
+                    continue;
+                }
+                let y = plane.origin.0 - 1 + row as i64;
+                let x = plane.origin.1 - 1 + column as i64;
+                if x < 0 || y < 0 || x >= width || y >= height {
+                    continue;
+                }
+                let (x, y) = (x as u32, y as u32);
+                match image {
+                    DynamicImage::ImageLuma8(img) => img.put_pixel(x, y, Luma([u8::MAX])),
+                    DynamicImage::ImageLuma16(img) => img.put_pixel(x, y, Luma([u16::MAX])),
+                    DynamicImage::ImageRgb8(img) => img.put_pixel(x, y, Rgb([u8::MAX; 3])),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    planes.len()
+}
+
+fn read_plane(obj: &DefaultDicomObject, group: u16) -> Option<OverlayPlane> {
+    let int = |element: u16| -> Option<u32> {
+        obj.element(Tag(group, element))
+            .ok()
+            .and_then(|e| e.to_int().ok())
+    };
+
+    let data = obj.element(Tag(group, 0x3000)).ok()?.to_bytes().ok()?;
+    let rows = int(0x0010)?;
+    let columns = int(0x0011)?;
+    if int(0x0100).unwrap_or(1) != 1 {
+        return None;
+    }
+
+    let origin = obj
+        .element(Tag(group, 0x0050))
+        .ok()
+        .and_then(|e| e.to_multi_int::<i64>().ok())
+        .filter(|values| values.len() == 2)
+        .map(|values| (values[0], values[1]))
+        .unwrap_or((1, 1));
+
+    Some(OverlayPlane {
+        rows,
+        columns,
+        origin,
+        data: data.into_owned(),
+    })
+}
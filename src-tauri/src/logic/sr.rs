@@ -0,0 +1,195 @@
+use crate::models::metadata::dicom_text;
+use crate::utils::discovery::collect_dicom_files;
+use anyhow::{bail, Context, Result};
+use dicom::core::Tag;
+use dicom::object::InMemDicomObject;
+use dicom_object::{open_file, DefaultDicomObject};
+use owo_colors::OwoColorize;
+use rayon::prelude::*;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const VALUE_TYPE: Tag = Tag(0x0040, 0xA040);
+const CONCEPT_NAME_CODE_SEQUENCE: Tag = Tag(0x0040, 0xA043);
+const CONTENT_SEQUENCE: Tag = Tag(0x0040, 0xA730);
+const TEXT_VALUE: Tag = Tag(0x0040, 0xA160);
+const MEASURED_VALUE_SEQUENCE: Tag = Tag(0x0040, 0xA300);
+const NUMERIC_VALUE: Tag = Tag(0x0040, 0xA30A);
+const MEASUREMENT_UNITS_CODE_SEQUENCE: Tag = Tag(0x0040, 0x08EA);
+const CONCEPT_CODE_SEQUENCE: Tag = Tag(0x0040, 0xA168);
+const CODE_VALUE: Tag = Tag(0x0008, 0x0100);
+const CODE_MEANING: Tag = Tag(0x0008, 0x0104);
+
+#[derive(Clone, serde::Serialize)]
+pub struct SrReport {
+    pub documents_written: usize,
+    /// Files that are not Structured Reports
+    pub skipped: usize,
+    pub failed_files: Vec<String>,
+    pub output_folder: PathBuf,
+}
+
+enum SrOutcome {
+    Written,
+    NotSr,
+}
+
+/// Render the content tree of every Structured Report under `input_folder`
+/// to a Markdown document, mirroring the input folders under `output_folder`.
+pub fn extract_sr_text(input_folder: &Path, output_folder: &Path) -> Result<SrReport> {
+    if !input_folder.exists() {
+        bail!("Input folder '{}' does not exist", input_folder.display());
+    }
+
+    fs::create_dir_all(output_folder)
+        .with_context(|| format!("Unable to create output folder {}", output_folder.display()))?;
+
+    let outcomes: Vec<(PathBuf, Result<SrOutcome>)> = collect_dicom_files(input_folder)
+        .into_par_iter()
+        .map(|path| {
+            let relative = path.strip_prefix(input_folder).unwrap_or(&path);
+            let md_path = output_folder.join(relative).with_extension("md");
+            let outcome = extract_single_file(&path, &md_path);
+            (path, outcome)
+        })
+        .collect();
+
+    let mut documents_written = 0usize;
+    let mut skipped = 0usize;
+    let mut failed_files = Vec::new();
+    for (path, outcome) in outcomes {
+        match outcome {
+            Ok(SrOutcome::Written) => documents_written += 1,
+            Ok(SrOutcome::NotSr) => skipped += 1,
+            Err(e) => {
+                crate::utils::logging::warn(format_args!(
+                    "{} Failed to extract {}:\n{:#}",
+                    "✖".red(),
+                    path.display(),
+                    e
+                ));
+                failed_files.push(path.to_string_lossy().to_string());
+            }
+        }
+    }
+
+    Ok(SrReport {
+        documents_written,
+        skipped,
+        failed_files,
+        output_folder: output_folder.to_path_buf(),
+    })
+}
+
+fn extract_single_file(dicom_path: &Path, md_path: &Path) -> Result<SrOutcome> {
+    let obj: DefaultDicomObject = open_file(dicom_path)
+        .with_context(|| format!("Failed to open DICOM file {}", dicom_path.display()))?;
+
+    let is_sr = dicom_text(&obj, Tag(0x0008, 0x0060)).as_deref() == Some("SR")
+        || obj.element(CONTENT_SEQUENCE).is_ok();
+    if !is_sr {
+        return Ok(SrOutcome::NotSr);
+    }
+
+    let title = concept_name(&obj).unwrap_or_else(|| "Structured Report".to_string());
+    let mut document = format!("# {}\n\n", title);
+    for (label, tag) in [
+        ("Study date", Tag(0x0008, 0x0020)),
+        ("Series description", Tag(0x0008, 0x103E)),
+        ("Completion", Tag(0x0040, 0xA491)),
+        ("Verification", Tag(0x0040, 0xA493)),
+    ] {
+        if let Some(value) = dicom_text(&obj, tag) {
+            let _ = writeln!(document, "- {}: {}", label, value);
+        }
+    }
+    document.push('\n');
+
+    for item in content_items(&obj) {
+        render_item(item, 0, &mut document);
+    }
+
+    if let Some(parent) = md_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(md_path, document)
+        .with_context(|| format!("Unable to write {}", md_path.display()))?;
+    Ok(SrOutcome::Written)
+}
+
+/// One Markdown list entry per content item, children nested below it.
+fn render_item(item: &InMemDicomObject, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    let value_type = text(item, VALUE_TYPE).unwrap_or_default();
+    let name = concept_name(item).unwrap_or_else(|| value_type.clone());
+
+    let value = match value_type.as_str() {
+        "CONTAINER" => None,
+        "TEXT" => text(item, TEXT_VALUE),
+        "NUM" => numeric_value(item),
+        "CODE" => first_item(item, CONCEPT_CODE_SEQUENCE).and_then(code_meaning),
+        "DATE" => text(item, Tag(0x0040, 0xA121)),
+        "TIME" => text(item, Tag(0x0040, 0xA122)),
+        "DATETIME" => text(item, Tag(0x0040, 0xA120)),
+        "PNAME" => text(item, Tag(0x0040, 0xA123)),
+        "UIDREF" => text(item, Tag(0x0040, 0xA124)),
+        other => Some(format!("({})", other)),
+    };
+
+    match value {
+        // Keep multi-line TEXT values inside their list entry
+        Some(value) => {
+            let value = value.replace('\n', &format!("\n{}  ", indent));
+            let _ = writeln!(out, "{}- **{}**: {}", indent, name, value);
+        }
+        None => {
+            let _ = writeln!(out, "{}- **{}**", indent, name);
+        }
+    }
+
+    for child in content_items(item) {
+        render_item(child, depth + 1, out);
+    }
+}
+
+/// "12.5 mm": the value with its UCUM code (or meaning if there is none).
+fn numeric_value(item: &InMemDicomObject) -> Option<String> {
+    let measured = first_item(item, MEASURED_VALUE_SEQUENCE)?;
+    let value = text(measured, NUMERIC_VALUE)?;
+    let unit = first_item(measured, MEASUREMENT_UNITS_CODE_SEQUENCE)
+        .and_then(|units| text(units, CODE_VALUE).or_else(|| code_meaning(units)))
+        // UCUM "1" means dimensionless
+        .filter(|unit| unit != "1");
+    Some(match unit {
+        Some(unit) => format!("{} {}", value, unit),
+        None => value,
+    })
+}
+
+fn concept_name(item: &InMemDicomObject) -> Option<String> {
+    first_item(item, CONCEPT_NAME_CODE_SEQUENCE).and_then(code_meaning)
+}
+
+fn code_meaning(code: &InMemDicomObject) -> Option<String> {
+    text(code, CODE_MEANING)
+}
+
+fn content_items(item: &InMemDicomObject) -> &[InMemDicomObject] {
+    item.element(CONTENT_SEQUENCE)
+        .ok()
+        .and_then(|e| e.items())
+        .unwrap_or(&[])
+}
+
+fn first_item(item: &InMemDicomObject, tag: Tag) -> Option<&InMemDicomObject> {
+    item.element(tag).ok()?.items()?.first()
+}
+
+fn text(item: &InMemDicomObject, tag: Tag) -> Option<String> {
+    item.element(tag)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+        .filter(|s| !s.is_empty())
+}
@@ -1,14 +1,18 @@
+use crate::models::metadata::TextDecoder;
 use crate::utils::discovery::collect_dicom_files;
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use dicom::core::dictionary::DataDictionary;
 use dicom::core::Tag;
-use dicom::object::open_file;
+use dicom::object::{DefaultDicomObject, OpenFileOptions};
 
 use rayon::prelude::*;
-use serde::Serialize;
-use std::collections::HashMap;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 #[derive(Debug, Serialize, Clone)]
 pub struct TagStat {
@@ -18,7 +22,86 @@ pub struct TagStat {
     pub value_counts: HashMap<String, usize>,
 }
 
-pub struct StatsCache(pub std::sync::Mutex<HashMap<(String, Vec<(u16, u16)>), Vec<TagStat>>>);
+impl TagStat {
+    /// (value, count) pairs, most frequent first; ties in value order.
+    pub fn sorted_counts(&self) -> Vec<(&str, usize)> {
+        let mut counts: Vec<(&str, usize)> = self
+            .value_counts
+            .iter()
+            .map(|(value, &count)| (value.as_str(), count))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        counts
+    }
+}
+
+/// Write `tag,name,value,count` rows for every tag in `stats`, each tag's
+/// values sorted by frequency.
+pub fn write_stats_csv(stats: &[TagStat], output_file: &Path) -> Result<()> {
+    if let Some(parent) = output_file.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut wtr = csv::Writer::from_path(output_file)
+        .with_context(|| format!("Unable to create {}", output_file.display()))?;
+    wtr.write_record(["tag", "name", "value", "count"])?;
+    for stat in stats {
+        let tag = format!("{:04X},{:04X}", stat.group, stat.element);
+        for (value, count) in stat.sorted_counts() {
+            wtr.write_record([tag.as_str(), &stat.name, value, &count.to_string()])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Labels counted in place of values that are not plain text. They are
+/// configurable so they can be told apart from real values that happen to
+/// read "Missing" or "Binary".
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default)]
+pub struct Placeholders {
+    /// The tag is absent from the file
+    pub missing: String,
+    /// The tag is present with a zero-length value
+    pub empty: String,
+    /// The value cannot be shown as text (e.g. OB/OW/SQ)
+    pub binary: String,
+}
+
+impl Default for Placeholders {
+    fn default() -> Self {
+        Self {
+            missing: "Missing".to_string(),
+            empty: String::new(),
+            binary: "Binary".to_string(),
+        }
+    }
+}
+
+impl Placeholders {
+    fn label(&self, value: &TagValue) -> String {
+        match value {
+            TagValue::Missing => self.missing.clone(),
+            TagValue::Empty => self.empty.clone(),
+            TagValue::Binary => self.binary.clone(),
+            TagValue::Text(text) => text.clone(),
+        }
+    }
+}
+
+/// A tag's value in one file, kept apart from the labels that are shown for
+/// it so the cache does not depend on the placeholders in use.
+#[derive(Clone, Debug)]
+enum TagValue {
+    Missing,
+    Empty,
+    Binary,
+    Text(String),
+}
+
+pub type StatsCacheKey = (String, Vec<(u16, u16)>, Placeholders);
+
+pub struct StatsCache(pub std::sync::Mutex<HashMap<StatsCacheKey, Vec<TagStat>>>);
 
 impl Default for StatsCache {
     fn default() -> Self {
@@ -26,6 +109,91 @@ impl Default for StatsCache {
     }
 }
 
+/// Per-file tag values shared by the stats and tag details passes, so the
+/// GUI's usual stats-then-details sequence parses each file only once.
+/// Only values that were actually requested are kept, not whole objects.
+#[derive(Default)]
+pub struct HeaderCache(pub Mutex<HashMap<PathBuf, CachedHeader>>);
+
+pub struct CachedHeader {
+    modified: Option<SystemTime>,
+    values: HashMap<(u16, u16), TagValue>,
+}
+
+impl HeaderCache {
+    /// Values of `tags` for one file, in the same order. The file is only
+    /// parsed when a tag is not cached yet or it changed on disk. Returns
+    /// `None` if the file cannot be opened.
+    fn tag_values(&self, path: &Path, tags: &[(u16, u16)]) -> Option<Vec<TagValue>> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+
+        {
+            let cache = self.0.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(entry) = cache.get(path) {
+                if entry.modified == modified {
+                    let cached: Option<Vec<TagValue>> = tags
+                        .iter()
+                        .map(|tag| entry.values.get(tag).cloned())
+                        .collect();
+                    if cached.is_some() {
+                        return cached;
+                    }
+                }
+            }
+        }
+
+        // Stop before PixelData unless its status was asked for: text tags
+        // never need the (possibly huge) pixel data to be read, let alone decoded
+        let wants_pixel_status = tags.contains(&PIXEL_DATA);
+        let mut options = OpenFileOptions::new();
+        if !wants_pixel_status {
+            options = options.read_until(Tag(PIXEL_DATA.0, PIXEL_DATA.1));
+        }
+        let obj = options.open_file(path).ok()?;
+        let values: Vec<TagValue> = tags
+            .iter()
+            .map(|&(group, element)| tag_value(&obj, group, element))
+            .collect();
+
+        let mut cache = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = cache
+            .entry(path.to_path_buf())
+            .or_insert_with(|| CachedHeader {
+                modified,
+                values: HashMap::new(),
+            });
+        if entry.modified != modified {
+            entry.modified = modified;
+            entry.values.clear();
+        }
+        entry
+            .values
+            .extend(tags.iter().copied().zip(values.iter().cloned()));
+
+        Some(values)
+    }
+}
+
+const PIXEL_DATA: (u16, u16) = (0x7fe0, 0x0010);
+
+fn tag_value(obj: &DefaultDicomObject, group: u16, element: u16) -> TagValue {
+    if (group, element) == PIXEL_DATA {
+        return match crate::models::metadata::extract_pixel_data_status(obj).as_str() {
+            "Missing" => TagValue::Missing,
+            "Binary" => TagValue::Binary,
+            status => TagValue::Text(status.to_string()),
+        };
+    }
+    let Ok(elem) = obj.element(Tag(group, element)) else {
+        return TagValue::Missing;
+    };
+    match TextDecoder::of(obj).element_text(elem) {
+        Some(v) if v.is_empty() => TagValue::Empty,
+        Some(v) => TagValue::Text(v),
+        None => TagValue::Binary,
+    }
+}
+
 #[derive(Clone, Serialize)]
 pub struct StatsProgress {
     pub current: usize,
@@ -35,6 +203,8 @@ pub struct StatsProgress {
 pub fn calculate_stats<F>(
     folder: &Path,
     tags: Vec<(u16, u16)>,
+    headers: &HeaderCache,
+    placeholders: &Placeholders,
     progress_callback: F,
 ) -> Result<Vec<TagStat>>
 where
@@ -58,25 +228,11 @@ where
                     progress_callback(StatsProgress { current, total });
                 }
 
-                if let Ok(obj) = open_file(file_path) {
-                    for &(group, element) in &tags {
-                        let tag = Tag(group, element);
-
-                        let value = if (group, element) == (0x7fe0, 0x0010) {
-                            crate::models::metadata::extract_pixel_data_status(&obj)
-                        } else if let Ok(elem) = obj.element(tag) {
-                            if let Ok(v) = elem.to_str() {
-                                v.to_string()
-                            } else {
-                                "Binary".to_string()
-                            }
-                        } else {
-                            "Missing".to_string()
-                        };
-
+                if let Some(values) = headers.tag_values(file_path, &tags) {
+                    for (&(group, element), value) in tags.iter().zip(values) {
                         acc.entry((group, element))
                             .or_default()
-                            .entry(value)
+                            .entry(placeholders.label(&value))
                             .and_modify(|c| *c += 1)
                             .or_insert(1);
                     }
@@ -123,9 +279,14 @@ where
 pub struct TagValueDetail {
     pub value: String,
     pub count: usize,
+    /// The first `MAX_LISTED_FILES` files with this value in path order, so
+    /// the list is the same on every refresh; empty in summary mode
     pub files: Vec<String>,
 }
 
+/// Files listed per value by `get_tag_details`.
+const MAX_LISTED_FILES: usize = 100;
+
 #[derive(Debug, Serialize)]
 pub struct TagDetails {
     pub group: u16,
@@ -134,10 +295,15 @@ pub struct TagDetails {
     pub values: Vec<TagValueDetail>,
 }
 
+/// Distribution of one tag's values. With `summary_only` only the counts
+/// are computed, for charts that do not need the per-value file lists.
 pub fn get_tag_details<F>(
     folder: &Path,
     group: u16,
     element: u16,
+    headers: &HeaderCache,
+    placeholders: &Placeholders,
+    summary_only: bool,
     progress_callback: F,
 ) -> Result<TagDetails>
 where
@@ -148,33 +314,28 @@ where
     let processed_count = AtomicUsize::new(0);
     let tag = Tag(group, element);
 
-    // Map: Value -> Vec<FilePath>
-    let value_map: HashMap<String, Vec<String>> = files
+    // Map: Value -> (count, first files). `files` is sorted and each fold
+    // sees an ascending run of it, so keeping the first files of every run
+    // and the smallest of every merge yields the first files overall.
+    let value_map: HashMap<String, (usize, Vec<&Path>)> = files
         .par_iter()
         .fold(
             || HashMap::new(),
-            |mut acc: HashMap<String, Vec<String>>, file_path| {
+            |mut acc: HashMap<String, (usize, Vec<&Path>)>, file_path| {
                 let current = processed_count.fetch_add(1, Ordering::Relaxed) + 1;
                 if current % 10 == 0 || current == total {
                     progress_callback(StatsProgress { current, total });
                 }
 
-                if let Ok(obj) = open_file(file_path) {
-                    let value = if (group, element) == (0x7fe0, 0x0010) {
-                        crate::models::metadata::extract_pixel_data_status(&obj)
-                    } else if let Ok(elem) = obj.element(tag) {
-                        if let Ok(v) = elem.to_str() {
-                            v.to_string()
-                        } else {
-                            "Binary".to_string()
-                        }
-                    } else {
-                        "Missing".to_string()
-                    };
-
-                    acc.entry(value)
-                        .or_default()
-                        .push(file_path.to_string_lossy().to_string());
+                if let Some(value) = headers
+                    .tag_values(file_path, &[(group, element)])
+                    .and_then(|values| values.into_iter().next())
+                {
+                    let (count, listed) = acc.entry(placeholders.label(&value)).or_default();
+                    *count += 1;
+                    if !summary_only && listed.len() < MAX_LISTED_FILES {
+                        listed.push(file_path);
+                    }
                 }
 
                 acc
@@ -183,8 +344,12 @@ where
         .reduce(
             || HashMap::new(),
             |mut acc, part| {
-                for (val, mut file_paths) in part {
-                    acc.entry(val).or_default().append(&mut file_paths);
+                for (val, (count, file_paths)) in part {
+                    let (total, listed) = acc.entry(val).or_default();
+                    *total += count;
+                    listed.extend(file_paths);
+                    listed.sort_unstable();
+                    listed.truncate(MAX_LISTED_FILES);
                 }
                 acc
             },
@@ -197,19 +362,18 @@ where
 
     let mut values: Vec<TagValueDetail> = value_map
         .into_iter()
-        .map(|(value, files)| {
-            let count = files.len();
-            let truncated_files = files.into_iter().take(100).collect();
-            TagValueDetail {
-                value,
-                count,
-                files: truncated_files,
-            }
+        .map(|(value, (count, files))| TagValueDetail {
+            value,
+            count,
+            files: files
+                .into_iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
         })
         .collect();
 
-    // Sort by count descending
-    values.sort_by(|a, b| b.count.cmp(&a.count));
+    // Sort by count descending, ties by value
+    values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
 
     Ok(TagDetails {
         group,
@@ -218,3 +382,305 @@ where
         values,
     })
 }
+
+#[derive(Debug, Serialize)]
+pub struct ExtractReport {
+    pub rows: usize,
+    /// Files that could not be opened; their value is "Unreadable"
+    pub unreadable: usize,
+    pub output_file: PathBuf,
+}
+
+/// Write one `file_name,file_path,<Keyword>` row per discovered file to the
+/// CSV at `output_file`, in path order. Unlike `calculate_stats`, values
+/// are not aggregated.
+pub fn extract_tag_values(
+    folder: &Path,
+    group: u16,
+    element: u16,
+    output_file: &Path,
+) -> Result<ExtractReport> {
+    if !folder.exists() {
+        bail!("Input folder '{}' does not exist", folder.display());
+    }
+
+    let headers = HeaderCache::default();
+    let placeholders = Placeholders::default();
+    let files = collect_dicom_files(folder);
+    // Collecting a parallel iterator keeps the (sorted) file order
+    let values: Vec<Option<String>> = files
+        .par_iter()
+        .map(|file_path| {
+            headers
+                .tag_values(file_path, &[(group, element)])
+                .and_then(|values| values.into_iter().next())
+                .map(|value| placeholders.label(&value))
+        })
+        .collect();
+
+    let name = dicom::dictionary_std::StandardDataDictionary
+        .by_tag(Tag(group, element))
+        .map(|e| e.alias.to_string())
+        .unwrap_or_else(|| format!("{:04X},{:04X}", group, element));
+
+    if let Some(parent) = output_file.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut wtr = csv::Writer::from_path(output_file)
+        .with_context(|| format!("Unable to create {}", output_file.display()))?;
+    wtr.write_record(["file_name", "file_path", name.as_str()])?;
+
+    let mut unreadable = 0usize;
+    for (file_path, value) in files.iter().zip(values) {
+        let value = value.unwrap_or_else(|| {
+            unreadable += 1;
+            "Unreadable".to_string()
+        });
+        let file_name = file_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown");
+        wtr.write_record([file_name, &file_path.to_string_lossy(), &value])?;
+    }
+    wtr.flush()?;
+
+    Ok(ExtractReport {
+        rows: files.len(),
+        unreadable,
+        output_file: output_file.to_path_buf(),
+    })
+}
+
+/// Tags that commonly carry PHI, checked by `scan_phi`.
+pub const PHI_TAGS: [(u16, u16); 15] = [
+    (0x0010, 0x0010), // PatientName
+    (0x0010, 0x0020), // PatientID
+    (0x0010, 0x0030), // PatientBirthDate
+    (0x0010, 0x1000), // OtherPatientIDs
+    (0x0010, 0x1040), // PatientAddress
+    (0x0010, 0x2154), // PatientTelephoneNumbers
+    (0x0008, 0x0090), // ReferringPhysicianName
+    (0x0008, 0x1050), // PerformingPhysicianName
+    (0x0008, 0x1070), // OperatorsName
+    (0x0008, 0x0080), // InstitutionName
+    (0x0008, 0x0081), // InstitutionAddress
+    (0x0008, 0x1010), // StationName
+    (0x0008, 0x0050), // AccessionNumber
+    (0x0020, 0x0010), // StudyID
+    (0x0008, 0x0020), // StudyDate
+];
+
+/// How many files populate one PHI tag.
+#[derive(Debug, Serialize)]
+pub struct PhiTagSummary {
+    pub group: u16,
+    pub element: u16,
+    pub name: String,
+    /// Files with a non-empty value
+    pub populated: usize,
+    /// Files where the tag is present but empty
+    pub empty: usize,
+    /// Files without the tag
+    pub missing: usize,
+}
+
+/// The `PHI_TAGS` values of one file, in `PHI_TAGS` order; missing and
+/// empty tags are both "".
+#[derive(Debug, Serialize)]
+pub struct PhiFile {
+    pub file_path: String,
+    pub values: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PhiReport {
+    pub tags: Vec<PhiTagSummary>,
+    /// Readable files, in path order
+    pub files: Vec<PhiFile>,
+    pub unreadable: usize,
+}
+
+/// Read the `PHI_TAGS` of every file under `folder` without modifying
+/// anything, as a preview of what anonymization would have to remove.
+pub fn scan_phi(folder: &Path) -> Result<PhiReport> {
+    if !folder.exists() {
+        bail!("Input folder '{}' does not exist", folder.display());
+    }
+
+    let headers = HeaderCache::default();
+    let files = collect_dicom_files(folder);
+    // Collecting a parallel iterator keeps the (sorted) file order
+    let values: Vec<Option<Vec<TagValue>>> = files
+        .par_iter()
+        .map(|file_path| headers.tag_values(file_path, &PHI_TAGS))
+        .collect();
+
+    let mut tags: Vec<PhiTagSummary> = PHI_TAGS
+        .iter()
+        .map(|&(group, element)| PhiTagSummary {
+            group,
+            element,
+            name: dicom::dictionary_std::StandardDataDictionary
+                .by_tag(Tag(group, element))
+                .map(|e| e.alias.to_string())
+                .unwrap_or_else(|| "Unknown".to_string()),
+            populated: 0,
+            empty: 0,
+            missing: 0,
+        })
+        .collect();
+    let mut phi_files = Vec::new();
+    let mut unreadable = 0usize;
+    for (file_path, values) in files.iter().zip(values) {
+        let Some(values) = values else {
+            unreadable += 1;
+            continue;
+        };
+        let values = values
+            .into_iter()
+            .zip(tags.iter_mut())
+            .map(|(value, summary)| match value {
+                TagValue::Missing => {
+                    summary.missing += 1;
+                    String::new()
+                }
+                TagValue::Empty => {
+                    summary.empty += 1;
+                    String::new()
+                }
+                TagValue::Binary => {
+                    summary.populated += 1;
+                    Placeholders::default().binary
+                }
+                TagValue::Text(text) => {
+                    summary.populated += 1;
+                    text
+                }
+            })
+            .collect();
+        phi_files.push(PhiFile {
+            file_path: file_path.to_string_lossy().to_string(),
+            values,
+        });
+    }
+
+    Ok(PhiReport {
+        tags,
+        files: phi_files,
+        unreadable,
+    })
+}
+
+/// Write one `file_path,<Keyword>...` row per file of `report`.
+pub fn write_phi_csv(report: &PhiReport, output_file: &Path) -> Result<()> {
+    if let Some(parent) = output_file.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut wtr = csv::Writer::from_path(output_file)
+        .with_context(|| format!("Unable to create {}", output_file.display()))?;
+    wtr.write_record(
+        std::iter::once("file_path").chain(report.tags.iter().map(|tag| tag.name.as_str())),
+    )?;
+    for file in &report.files {
+        wtr.write_record(
+            std::iter::once(file.file_path.as_str()).chain(file.values.iter().map(String::as_str)),
+        )?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// A series whose files do not all share one in-plane geometry.
+#[derive(Debug, Serialize)]
+pub struct InconsistentSeries {
+    pub series_uid: String,
+    pub file_count: usize,
+    /// "Rows x Columns, spacing PixelSpacing" -> number of files, most
+    /// frequent first
+    pub geometries: Vec<(String, usize)>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeriesCheckReport {
+    /// Series that were checked
+    pub series: usize,
+    /// Files without a SeriesInstanceUID, not assigned to any series
+    pub without_series: usize,
+    pub unreadable: usize,
+    pub inconsistent: Vec<InconsistentSeries>,
+}
+
+/// Group the files under `folder` by SeriesInstanceUID and report every
+/// series whose Rows, Columns or PixelSpacing differ between files, since
+/// such a series cannot be stacked into one volume.
+pub fn check_series_geometry(folder: &Path) -> Result<SeriesCheckReport> {
+    if !folder.exists() {
+        bail!("Input folder '{}' does not exist", folder.display());
+    }
+
+    const TAGS: [(u16, u16); 4] = [
+        (0x0020, 0x000E),
+        (0x0028, 0x0010),
+        (0x0028, 0x0011),
+        (0x0028, 0x0030),
+    ];
+    let headers = HeaderCache::default();
+    let placeholders = Placeholders::default();
+    let values: Vec<Option<Vec<TagValue>>> = collect_dicom_files(folder)
+        .par_iter()
+        .map(|file_path| headers.tag_values(file_path, &TAGS))
+        .collect();
+
+    let mut unreadable = 0usize;
+    let mut without_series = 0usize;
+    // Series UID -> geometry -> file count
+    let mut series: BTreeMap<String, HashMap<String, usize>> = BTreeMap::new();
+    for values in values {
+        let Some(values) = values else {
+            unreadable += 1;
+            continue;
+        };
+        let value = |index: usize| {
+            placeholders
+                .label(&values[index])
+                .trim_end_matches(['\0', ' '])
+                .to_string()
+        };
+        let series_uid = match &values[0] {
+            TagValue::Text(uid) => uid.trim_end_matches(['\0', ' ']).to_string(),
+            _ => String::new(),
+        };
+        if series_uid.is_empty() {
+            without_series += 1;
+            continue;
+        }
+        let geometry = format!("{} x {}, spacing {}", value(1), value(2), value(3));
+        *series
+            .entry(series_uid)
+            .or_default()
+            .entry(geometry)
+            .or_default() += 1;
+    }
+
+    let checked = series.len();
+    let inconsistent = series
+        .into_iter()
+        .filter(|(_, geometries)| geometries.len() > 1)
+        .map(|(series_uid, geometries)| {
+            let mut geometries: Vec<(String, usize)> = geometries.into_iter().collect();
+            geometries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            InconsistentSeries {
+                series_uid,
+                file_count: geometries.iter().map(|(_, count)| count).sum(),
+                geometries,
+            }
+        })
+        .collect();
+
+    Ok(SeriesCheckReport {
+        series: checked,
+        without_series,
+        unreadable,
+        inconsistent,
+    })
+}
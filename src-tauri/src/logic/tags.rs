@@ -1,7 +1,12 @@
+use crate::models::metadata::TextDecoder;
 use anyhow::{Context, Result};
 use dicom::core::dictionary::DataDictionary;
 use dicom::core::header::Header;
-use dicom::object::open_file;
+use dicom::core::VR;
+use dicom::encoding::TransferSyntaxIndex;
+use dicom::object::{open_file, DefaultDicomObject};
+use dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
 use serde::Serialize;
 use std::path::Path;
 
@@ -17,16 +22,36 @@ pub struct DicomTag {
 pub fn read_all_tags(path: &Path) -> Result<Vec<DicomTag>> {
     let obj =
         open_file(path).with_context(|| format!("Failed to open DICOM file {}", path.display()))?;
-    let mut tags = Vec::new();
+    Ok(object_tags(&obj))
+}
+
+fn object_tags(obj: &DefaultDicomObject) -> Vec<DicomTag> {
+    let transfer_syntax = obj.meta().transfer_syntax();
+    let mut tags = vec![transfer_syntax_entry(transfer_syntax)];
+    let text = TextDecoder::of(obj);
+    // Implicit VR files do not store the VR; the parser fills in the
+    // dictionary VR of known tags, so all of those are marked with '*'
+    let implicit_vr =
+        transfer_syntax.trim_end_matches(['\0', ' ']) == IMPLICIT_VR_LITTLE_ENDIAN.uid();
 
-    for element in obj.into_iter() {
+    for element in obj.iter() {
         let tag = element.tag();
-        let name = dicom::dictionary_std::StandardDataDictionary
-            .by_tag(tag)
+        let entry = dicom::dictionary_std::StandardDataDictionary.by_tag(tag);
+        let name = entry
             .map(|e| e.alias.to_string())
             .unwrap_or_else(|| "Unknown".to_string());
 
-        let value = if let Ok(v) = element.to_str() {
+        // Explicit VR files can still store UN for a known tag; show the
+        // dictionary VR instead, marked the same way
+        let vr = match (element.vr(), entry.map(|e| e.vr.relaxed())) {
+            (vr, Some(_)) if implicit_vr && vr != VR::UN => format!("{}*", vr.to_string()),
+            (VR::UN, Some(dictionary_vr)) if dictionary_vr != VR::UN => {
+                format!("{}*", dictionary_vr.to_string())
+            }
+            (vr, _) => vr.to_string().to_string(),
+        };
+
+        let value = if let Some(v) = text.element_text(element) {
             // if tag PixelData then skip 7FE0,0010
             if (tag.0, tag.1) == (0x7fe0, 0x0010) {
                 "<binary data>".to_string()
@@ -41,10 +66,80 @@ pub fn read_all_tags(path: &Path) -> Result<Vec<DicomTag>> {
             group: tag.0,
             element: tag.1,
             name,
-            vr: element.vr().to_string().to_string(),
+            vr,
             value,
         });
     }
 
-    Ok(tags)
+    tags
+}
+
+/// Leading TransferSyntaxUID (0002,0010) entry from the file meta group,
+/// which the dataset iteration does not include, with its name and VR
+/// encoding so a file's encoding can be checked alongside its content.
+fn transfer_syntax_entry(uid: &str) -> DicomTag {
+    let uid = uid.trim_end_matches(['\0', ' ']);
+    let value = match TransferSyntaxRegistry.get(uid) {
+        Some(ts) => format!(
+            "{} ({}, {} VR)",
+            uid,
+            ts.name(),
+            // The registry does not expose the VR encoding; only the
+            // implicit VR syntaxes say so in their name
+            if ts.name().starts_with("Implicit") {
+                "implicit"
+            } else {
+                "explicit"
+            }
+        ),
+        None => format!("{} (unknown transfer syntax)", uid),
+    };
+    DicomTag {
+        group: 0x0002,
+        element: 0x0010,
+        name: "TransferSyntaxUID".to_string(),
+        vr: VR::UI.to_string().to_string(),
+        value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::{DataElement, PrimitiveValue, Tag};
+    use dicom::object::meta::FileMetaTableBuilder;
+    use dicom::object::InMemDicomObject;
+
+    fn object_with_syntax(transfer_syntax: &str) -> DefaultDicomObject {
+        InMemDicomObject::from_element_iter([
+            DataElement::new(Tag(0x0008, 0x0060), VR::CS, PrimitiveValue::from("CT")),
+            DataElement::new(Tag(0x0028, 0x0010), VR::US, PrimitiveValue::from(2u16)),
+            DataElement::new(Tag(0x0009, 0x1001), VR::UN, PrimitiveValue::from(vec![1u8])),
+        ])
+        .with_meta(
+            FileMetaTableBuilder::new()
+                .transfer_syntax(transfer_syntax)
+                .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+                .media_storage_sop_instance_uid("1.2.3.4"),
+        )
+        .unwrap()
+    }
+
+    fn vrs(obj: &DefaultDicomObject) -> Vec<String> {
+        object_tags(obj)
+            .into_iter()
+            .skip(1)
+            .map(|tag| tag.vr)
+            .collect()
+    }
+
+    #[test]
+    fn implicit_vr_marks_dictionary_vrs() {
+        let implicit = object_with_syntax("1.2.840.10008.1.2");
+        // The private tag is not in the dictionary
+        assert_eq!(vrs(&implicit), ["CS*", "UN", "US*"]);
+
+        let explicit = object_with_syntax("1.2.840.10008.1.2.1");
+        assert_eq!(vrs(&explicit), ["CS", "UN", "US"]);
+    }
 }
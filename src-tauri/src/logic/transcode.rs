@@ -0,0 +1,104 @@
+use crate::utils::discovery::collect_dicom_files;
+use anyhow::{bail, Context, Result};
+use dicom::encoding::TransferSyntaxIndex;
+use dicom_object::{open_file, DefaultDicomObject};
+use dicom_pixeldata::Transcode;
+use dicom_transfer_syntax_registry::entries::EXPLICIT_VR_LITTLE_ENDIAN;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use owo_colors::OwoColorize;
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, serde::Serialize)]
+pub struct TranscodeReport {
+    /// Files whose compressed pixel data was decoded
+    pub decompressed: usize,
+    /// Files that were already uncompressed and only re-encoded
+    pub already_uncompressed: usize,
+    pub failed_files: Vec<String>,
+    pub output_folder: PathBuf,
+}
+
+enum TranscodeOutcome {
+    Decompressed,
+    AlreadyUncompressed,
+}
+
+/// Rewrite every DICOM file under `input_folder` as Explicit VR Little
+/// Endian with native (decompressed) pixel data, mirroring the input
+/// folders under `output_folder`. All other elements are kept.
+pub fn transcode_to_uncompressed(
+    input_folder: &Path,
+    output_folder: &Path,
+) -> Result<TranscodeReport> {
+    if !input_folder.exists() {
+        bail!("Input folder '{}' does not exist", input_folder.display());
+    }
+
+    fs::create_dir_all(output_folder)
+        .with_context(|| format!("Unable to create output folder {}", output_folder.display()))?;
+
+    let outcomes: Vec<(PathBuf, Result<TranscodeOutcome>)> = collect_dicom_files(input_folder)
+        .into_par_iter()
+        .map(|path| {
+            let relative = path.strip_prefix(input_folder).unwrap_or(&path);
+            let output_path = output_folder.join(relative);
+            let outcome = transcode_single_file(&path, &output_path);
+            (path, outcome)
+        })
+        .collect();
+
+    let mut decompressed = 0usize;
+    let mut already_uncompressed = 0usize;
+    let mut failed_files = Vec::new();
+    for (path, outcome) in outcomes {
+        match outcome {
+            Ok(TranscodeOutcome::Decompressed) => decompressed += 1,
+            Ok(TranscodeOutcome::AlreadyUncompressed) => already_uncompressed += 1,
+            Err(e) => {
+                crate::utils::logging::warn(format_args!(
+                    "{} Failed to transcode {}:\n{:#}",
+                    "✖".red(),
+                    path.display(),
+                    e
+                ));
+                failed_files.push(path.to_string_lossy().to_string());
+            }
+        }
+    }
+
+    Ok(TranscodeReport {
+        decompressed,
+        already_uncompressed,
+        failed_files,
+        output_folder: output_folder.to_path_buf(),
+    })
+}
+
+fn transcode_single_file(dicom_path: &Path, output_path: &Path) -> Result<TranscodeOutcome> {
+    let mut obj: DefaultDicomObject = open_file(dicom_path)
+        .with_context(|| format!("Failed to open DICOM file {}", dicom_path.display()))?;
+
+    let ts_uid = obj.meta().transfer_syntax().to_string();
+    let encapsulated = TransferSyntaxRegistry
+        .get(&ts_uid)
+        .with_context(|| format!("Unknown transfer syntax {}", ts_uid))?
+        .is_encapsulated_pixel_data();
+
+    // Also normalizes implicit VR and big endian files
+    obj.transcode(&EXPLICIT_VR_LITTLE_ENDIAN.erased())
+        .with_context(|| format!("Unable to decode pixel data ({})", ts_uid))?;
+
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    obj.write_to_file(output_path)
+        .with_context(|| format!("Unable to write {}", output_path.display()))?;
+
+    Ok(if encapsulated {
+        TranscodeOutcome::Decompressed
+    } else {
+        TranscodeOutcome::AlreadyUncompressed
+    })
+}
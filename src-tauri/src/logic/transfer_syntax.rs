@@ -0,0 +1,132 @@
+use crate::utils::discovery::collect_dicom_files;
+use anyhow::{bail, Result};
+use dicom::core::Tag;
+use dicom::encoding::TransferSyntaxIndex;
+use dicom::object::OpenFileOptions;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TransferSyntaxCount {
+    pub uid: String,
+    pub name: String,
+    pub count: usize,
+}
+
+/// Resolve a transfer syntax UID to its registered name, if known.
+pub fn transfer_syntax_name(uid: &str) -> Option<String> {
+    TransferSyntaxRegistry
+        .get(uid.trim_end_matches(['\0', ' ']))
+        .map(|ts| ts.name().to_string())
+}
+
+/// Tally how many files in `folder` use each transfer syntax.
+/// Only the file meta group is read, so this is cheap even for large files.
+pub fn summarize_transfer_syntaxes(folder: &Path) -> Result<Vec<TransferSyntaxCount>> {
+    if !folder.exists() {
+        bail!("Input folder '{}' does not exist", folder.display());
+    }
+
+    let files = collect_dicom_files(folder);
+
+    let counts: HashMap<String, usize> = files
+        .par_iter()
+        .fold(
+            HashMap::new,
+            |mut acc: HashMap<String, usize>, file_path| {
+                let uid = OpenFileOptions::new()
+                    .read_until(Tag(0x0008, 0x0000))
+                    .open_file(file_path)
+                    .map(|obj| {
+                        obj.meta()
+                            .transfer_syntax()
+                            .trim_end_matches(['\0', ' '])
+                            .to_string()
+                    })
+                    .unwrap_or_default();
+                *acc.entry(uid).or_default() += 1;
+                acc
+            },
+        )
+        .reduce(HashMap::new, |mut acc, part| {
+            for (uid, count) in part {
+                *acc.entry(uid).or_default() += count;
+            }
+            acc
+        });
+
+    let mut result: Vec<TransferSyntaxCount> = counts
+        .into_iter()
+        .map(|(uid, count)| {
+            let name = if uid.is_empty() {
+                "Unreadable".to_string()
+            } else {
+                transfer_syntax_name(&uid).unwrap_or_else(|| "Unknown".to_string())
+            };
+            TransferSyntaxCount { uid, name, count }
+        })
+        .collect();
+
+    // Sort by count descending
+    result.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.uid.cmp(&b.uid)));
+    Ok(result)
+}
+
+/// Transfer syntaxes commonly found in the wild, in the order `codec_support`
+/// lists them.
+const COMMON_TRANSFER_SYNTAXES: [&str; 16] = [
+    "1.2.840.10008.1.2",
+    "1.2.840.10008.1.2.1",
+    "1.2.840.10008.1.2.1.99",
+    "1.2.840.10008.1.2.2",
+    "1.2.840.10008.1.2.4.50",
+    "1.2.840.10008.1.2.4.51",
+    "1.2.840.10008.1.2.4.57",
+    "1.2.840.10008.1.2.4.70",
+    "1.2.840.10008.1.2.4.80",
+    "1.2.840.10008.1.2.4.81",
+    "1.2.840.10008.1.2.4.90",
+    "1.2.840.10008.1.2.4.91",
+    "1.2.840.10008.1.2.4.201",
+    "1.2.840.10008.1.2.4.202",
+    "1.2.840.10008.1.2.5",
+    "1.2.840.10008.1.2.4.110",
+];
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CodecSupport {
+    pub uid: String,
+    /// Registered name, or "Unknown" if this build does not know the UID
+    pub name: String,
+    /// Files in this transfer syntax can be read and their pixel data decoded
+    pub decode: bool,
+    /// Pixel data can be written in this transfer syntax (e.g. by transcoding)
+    pub encode: bool,
+}
+
+/// Which of the common transfer syntaxes this build can decode and encode,
+/// according to the codecs registered in `TransferSyntaxRegistry`. The
+/// registry reflects the enabled dicom-pixeldata features (e.g. openjpeg,
+/// charls), so this is what a conversion will actually be able to do.
+pub fn codec_support() -> Vec<CodecSupport> {
+    COMMON_TRANSFER_SYNTAXES
+        .iter()
+        .map(|&uid| match TransferSyntaxRegistry.get(uid) {
+            Some(ts) => CodecSupport {
+                uid: uid.to_string(),
+                name: ts.name().to_string(),
+                decode: ts.can_decode_all(),
+                encode: ts.is_fully_supported(),
+            },
+            None => CodecSupport {
+                uid: uid.to_string(),
+                name: "Unknown".to_string(),
+                decode: false,
+                encode: false,
+            },
+        })
+        .collect()
+}
@@ -1,12 +1,24 @@
-use crate::logic::convert::{convert_single_file, FileOutcome};
-use crate::utils::discovery::collect_dicom_files;
+use crate::logic::convert::{
+    convert_single_file, read_series_uid, sanitize_uid, series_png_path, FileOutcome, OutputFormat,
+    PngCompression, WindowSelection,
+};
+use crate::logic::html_index::{write_html_index, IndexEntry};
+use crate::logic::montage::{write_contact_sheets, MontageEntry};
+use crate::models::metadata::file_sha256;
+use crate::utils::discovery::{
+    collect_dicom_files_with_progress, DiscoveryOptions, DiscoveryProgress,
+};
 use crate::utils::logging::LogEntry;
-use anyhow::{bail, Context, Result};
+use crate::utils::metadata_export::{CsvFormat, MetadataColumns};
+use crate::utils::zip_export::ZipExport;
+use anyhow::{anyhow, bail, Context, Result};
 use owo_colors::OwoColorize;
 use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 #[derive(Clone, serde::Serialize)]
 pub struct ConversionReport {
@@ -16,7 +28,217 @@ pub struct ConversionReport {
     pub skipped_non_image: usize,
     pub failed_files: Vec<String>,
     pub skipped_files: Vec<String>,
+    /// Transfer syntax name -> number of files that failed to convert
+    pub failures_by_transfer_syntax: HashMap<String, usize>,
+    /// Output folder, or the zip archive when one was written
     pub output_folder: PathBuf,
+    /// The run was stopped early; files not yet started are not counted
+    pub cancelled: bool,
+    /// With `OnError::Abort`, the file whose failure stopped the run
+    pub aborted_by: Option<String>,
+    /// Writing the outputs failed (e.g. the disk is full) and the run was
+    /// stopped; files recorded before that are kept
+    pub write_error: Option<String>,
+}
+
+/// Default number of finished files buffered for the writer thread.
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct ConvertOptions {
+    pub save_excel: bool,
+    /// Delimiter and BOM of the metadata CSVs
+    pub csv_format: CsvFormat,
+    /// Columns of the metadata CSVs; the Parquet file keeps its fixed schema
+    pub metadata_columns: MetadataColumns,
+    /// Also write metadata_all.parquet with typed columns
+    pub save_parquet: bool,
+    pub flatten_output: bool,
+    /// Place PNGs under `<SeriesInstanceUID>/<InstanceNumber>.png`
+    /// instead of mirroring the input folders
+    pub group_by_series: bool,
+    /// Folder under the output root that holds the PNGs; `None` uses
+    /// `png_file`, an empty name the root itself
+    pub output_subdir: Option<String>,
+    /// Mirror the folders below this one instead of below the input
+    /// folder; files outside it are written under their file name
+    pub relative_to: Option<PathBuf>,
+    pub discovery: DiscoveryOptions,
+    /// Write a pHYs chunk derived from PixelSpacing into each PNG
+    pub embed_spacing: bool,
+    /// PNG, or TIFF for tools that expect it
+    pub output_format: OutputFormat,
+    /// With TIFF output, write every frame of a multi-frame file as one
+    /// page of a single TIFF instead of only the first frame
+    pub tiff_multipage: bool,
+    /// Re-read each written file and check it decodes to the expected
+    /// dimensions, rewriting it once if not; costs a decode per file
+    pub verify_output: bool,
+    /// Encoding speed vs. size of the PNGs
+    pub png_compression: PngCompression,
+    /// Bound on results waiting for the writer thread; decoding blocks when full
+    pub channel_capacity: usize,
+    /// Leave skipped and failed files out of the metadata CSV/Parquet; they
+    /// are still recorded in the log and error CSVs. Off by default: those
+    /// files get a row with whatever header fields could be read.
+    pub metadata_only_success: bool,
+    /// Record a SHA-256 of each source file in the metadata
+    pub hash: bool,
+    /// Record min/max/mean pixel values in the metadata (scans every pixel)
+    pub pixel_stats: bool,
+    /// Draw overlay planes (60xx,3000) onto the PNG
+    pub with_overlays: bool,
+    /// Invert the final image, after MONOCHROME1 and PresentationLUTShape
+    /// handling and overlays; a MONOCHROME1 file comes out as stored
+    pub invert: bool,
+    /// Columns of the per-series contact sheet; `None` writes no sheets
+    pub contact_sheet_columns: Option<usize>,
+    /// Write an `index.html` gallery of the converted PNGs in the output root
+    pub html_index: bool,
+    /// Group files by SeriesInstanceUID before converting, series by series,
+    /// and report each file's position within its series in the progress
+    /// events. Reads every header once more up front.
+    pub series_progress: bool,
+    /// (center, width) used instead of the file's own VOI window
+    pub window: Option<(f64, f64)>,
+    /// Pick one of the file's own windows instead of the first
+    pub window_selection: Option<WindowSelection>,
+    /// (low, high) percentiles used as the window for files that have no
+    /// window or VOI LUT of their own, instead of plain min-max
+    pub auto_window: Option<(f64, f64)>,
+    /// For files without a window of their own and no `window`, use a
+    /// default for their Modality (CT soft tissue, MR auto window, CR/DX
+    /// full stored range) instead of min-max normalization
+    pub modality_defaults: bool,
+    /// Reconvert files whose PNG already exists instead of skipping them
+    pub overwrite: bool,
+    /// What to do when the output root already holds files, e.g. from an
+    /// earlier run on another input folder with the same name
+    pub if_exists: IfExists,
+    /// Write the PNGs, metadata and logs into this zip archive instead of
+    /// an output folder. Contact sheets are not written.
+    pub zip: Option<PathBuf>,
+    /// Keep going after a file fails, or stop the whole run
+    pub on_error: OnError,
+    /// Checked before each file; once set, remaining files are not started
+    pub cancel: Arc<AtomicBool>,
+}
+
+/// What a conversion or anonymization does when a file fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Record the failure and process the remaining files
+    #[default]
+    Continue,
+    /// Stop starting new files, as if cancelled; files already in progress
+    /// still finish and are reported
+    Abort,
+}
+
+/// What a conversion does when its output root exists and is not empty.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IfExists {
+    /// Write into the existing folder, skipping PNGs that are already there
+    /// and adding to its metadata and logs
+    #[default]
+    Append,
+    /// Refuse to start
+    Error,
+    /// Use the first free `<root>_2`, `<root>_3`, ... instead
+    Suffix,
+}
+
+/// Cancellation flags of the conversions and anonymizations in progress,
+/// by run id. Managed as Tauri state so `cancel_conversion` can stop one
+/// run without affecting others started alongside it.
+#[derive(Default)]
+pub struct ActiveRuns(Mutex<HashMap<String, Arc<AtomicBool>>>);
+
+impl ActiveRuns {
+    /// Register a run under a new id; it is removed when the guard drops.
+    pub fn start(&self) -> RunGuard<'_> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id.clone(), cancel.clone());
+        RunGuard {
+            runs: self,
+            id,
+            cancel,
+        }
+    }
+
+    /// Set the flag of run `id`. Returns false when no such run is active,
+    /// e.g. because it already finished.
+    pub fn cancel(&self, id: &str) -> bool {
+        let runs = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        match runs.get(id) {
+            Some(cancel) => {
+                cancel.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// One registered run of `ActiveRuns`.
+pub struct RunGuard<'a> {
+    runs: &'a ActiveRuns,
+    pub id: String,
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl Drop for RunGuard<'_> {
+    fn drop(&mut self) {
+        self.runs
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            save_excel: false,
+            csv_format: CsvFormat::default(),
+            metadata_columns: MetadataColumns::default(),
+            save_parquet: false,
+            flatten_output: false,
+            group_by_series: false,
+            output_subdir: None,
+            relative_to: None,
+            discovery: DiscoveryOptions::default(),
+            embed_spacing: false,
+            verify_output: false,
+            output_format: OutputFormat::default(),
+            tiff_multipage: false,
+            png_compression: PngCompression::default(),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            metadata_only_success: false,
+            hash: false,
+            pixel_stats: false,
+            with_overlays: false,
+            invert: false,
+            contact_sheet_columns: None,
+            html_index: false,
+            series_progress: false,
+            window: None,
+            window_selection: None,
+            auto_window: None,
+            modality_defaults: false,
+            overwrite: false,
+            if_exists: IfExists::default(),
+            zip: None,
+            on_error: OnError::default(),
+            cancel: Arc::default(),
+        }
+    }
 }
 
 #[derive(Clone, serde::Serialize)]
@@ -25,23 +247,80 @@ pub struct ProgressPayload {
     pub total: usize,
     pub filename: String,
     pub status: String,
+    /// Position within the file's series, only set with `series_progress`
+    #[serde(flatten)]
+    pub series: Option<SeriesPosition>,
 }
 
-pub fn convert_dicom_to_png<F, G>(
+#[derive(Clone, serde::Serialize)]
+pub struct SeriesPosition {
+    /// `None` for the series of all files without a SeriesInstanceUID
+    pub series_uid: Option<String>,
+    /// 1-based position of this series among all series of the run
+    pub series_index: usize,
+    pub series_count: usize,
+    /// Files of this series started so far, including this one
+    pub series_current: usize,
+    pub series_total: usize,
+}
+
+/// One series of a run with `series_progress`.
+struct SeriesProgress {
+    uid: Option<String>,
+    total: usize,
+    started: AtomicUsize,
+}
+
+/// Stable-sort `tasks` so each series is contiguous, series in order of
+/// their first file, and return the series with the index of each task's.
+fn group_tasks_by_series(
+    tasks: &mut Vec<(PathBuf, PathBuf, PathBuf)>,
+) -> (Vec<SeriesProgress>, Vec<usize>) {
+    let uids: Vec<Option<String>> = tasks
+        .par_iter()
+        .map(|(dicom_path, _, _)| read_series_uid(dicom_path))
+        .collect();
+
+    let mut series: Vec<SeriesProgress> = Vec::new();
+    let mut index_of: HashMap<Option<String>, usize> = HashMap::new();
+    let mut task_series: Vec<usize> = Vec::with_capacity(tasks.len());
+    for uid in uids {
+        let index = *index_of.entry(uid.clone()).or_insert_with(|| {
+            series.push(SeriesProgress {
+                uid,
+                total: 0,
+                started: AtomicUsize::new(0),
+            });
+            series.len() - 1
+        });
+        series[index].total += 1;
+        task_series.push(index);
+    }
+
+    let mut grouped: Vec<_> = std::mem::take(tasks).into_iter().zip(task_series).collect();
+    grouped.sort_by_key(|(_, series_index)| *series_index);
+    let (grouped_tasks, task_series) = grouped.into_iter().unzip();
+    *tasks = grouped_tasks;
+    (series, task_series)
+}
+
+pub fn convert_dicom_to_png<D, F, G>(
     input_folder: &Path,
     output_folder: &Path,
-    save_excel: bool,
-    flatten_output: bool,
+    options: &ConvertOptions,
+    discovery_callback: D,
     progress_callback: F,
     log_callback: G,
 ) -> Result<ConversionReport>
 where
+    D: Fn(DiscoveryProgress),
     F: Fn(ProgressPayload) + Sync + Send,
     G: Fn(LogEntry) + Sync + Send + 'static,
 {
     if !input_folder.exists() {
         bail!("Input folder '{}' does not exist", input_folder.display());
     }
+    let output_base = OutputBase::new(input_folder, options.relative_to.as_deref())?;
 
     // Determine the input folder name for the output directory
     let input_name = input_folder
@@ -49,12 +328,22 @@ where
         .and_then(|n| n.to_str())
         .unwrap_or("dicom");
 
-    let root_output_path = if flatten_output {
+    // A zip archive is assembled from a staging folder that only ever holds
+    // the metadata and log files
+    let root_output_path = if options.zip.is_some() {
+        std::env::temp_dir().join(format!("dicom-app-{}", uuid::Uuid::new_v4()))
+    } else if options.flatten_output {
         output_folder.to_path_buf()
     } else {
         output_folder.join(format!("{}_output", input_name))
     };
-    let png_output_path = root_output_path.join("png_file");
+    let root_output_path = if options.zip.is_some() {
+        root_output_path
+    } else {
+        resolve_output_root(root_output_path, options.if_exists)?
+    };
+    let png_output_path =
+        root_output_path.join(options.output_subdir.as_deref().unwrap_or("png_file"));
 
     fs::create_dir_all(&png_output_path).with_context(|| {
         format!(
@@ -63,215 +352,413 @@ where
         )
     })?;
 
-    let dicom_files = collect_dicom_files(input_folder);
+    let dicom_files =
+        collect_dicom_files_with_progress(input_folder, &options.discovery, discovery_callback)?;
     let mut tasks = Vec::new();
 
     for path in dicom_files {
-        let png_path = build_png_path(input_folder, &png_output_path, &path);
+        let relative = output_base.relative(&path);
+        let mut png_path = png_output_path.join(&relative);
+        png_path.set_extension(options.output_format.extension());
         // Removed pre-check: if png_path.exists() { continue; }
 
-        let folder_relative = path
+        let folder_relative = relative
             .parent()
-            .and_then(|p| p.strip_prefix(input_folder).ok())
             .map(PathBuf::from)
             .unwrap_or_else(|| PathBuf::from("."));
 
         tasks.push((path, png_path, folder_relative));
     }
 
+    if options.group_by_series {
+        let extension = options.output_format.extension();
+        let series_paths: Vec<PathBuf> = tasks
+            .par_iter()
+            .map(|(dicom_path, _, _)| series_png_path(dicom_path, &png_output_path, extension))
+            .collect();
+        for (task, png_path) in tasks.iter_mut().zip(series_paths) {
+            task.2 = png_path
+                .parent()
+                .and_then(|p| p.strip_prefix(&png_output_path).ok())
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("."));
+            task.1 = png_path;
+        }
+    }
+    dedupe_output_paths(&mut tasks);
+
+    let (series, task_series) = if options.series_progress {
+        group_tasks_by_series(&mut tasks)
+    } else {
+        (Vec::new(), Vec::new())
+    };
+
     let total = tasks.len();
     let processed_count = AtomicUsize::new(0);
+    let aborted_by: Mutex<Option<PathBuf>> = Mutex::new(None);
 
-    // Channel for sending results to the writer thread
-    let (tx, rx) = std::sync::mpsc::channel::<(PathBuf, Result<FileOutcome>, PathBuf)>();
+    // Bounded channel for sending results to the writer thread, so workers
+    // block instead of piling up results when the writer falls behind
+    let (tx, rx) = std::sync::mpsc::sync_channel::<(PathBuf, Result<FileOutcome>, PathBuf)>(
+        options.channel_capacity.max(1),
+    );
 
     // Spawn writer thread
     let writer_handle = std::thread::spawn({
         let png_output_path = png_output_path.clone();
         let root_output_path = root_output_path.clone();
+        let save_excel = options.save_excel;
+        let csv_format = options.csv_format;
+        let metadata_columns = options.metadata_columns.clone();
+        let save_parquet = options.save_parquet;
+        let metadata_only_success = options.metadata_only_success;
+        let overwrite = options.overwrite;
+        let contact_sheet_columns = options.contact_sheet_columns;
+        let html_index = options.html_index;
+        let zip_path = options.zip.clone();
+        let cancel = options.cancel.clone();
         move || -> Result<ConversionReport> {
             let mut successful = 0usize;
             let mut failed_files = Vec::new();
             let mut skipped_files = Vec::new();
             let mut logs: Vec<LogEntry> = Vec::new();
             let mut skipped_count = 0usize;
+            let mut failures_by_transfer_syntax: HashMap<String, usize> = HashMap::new();
+            let mut montage_entries: Vec<MontageEntry> = Vec::new();
+            let mut index_entries: Vec<IndexEntry> = Vec::new();
 
             // Initialize metadata writer if needed
             let mut metadata_writer = if save_excel {
                 Some(crate::utils::metadata_export::MetadataWriter::new(
+                    &root_output_path,
                     &png_output_path,
+                    csv_format,
+                    metadata_columns,
+                )?)
+            } else {
+                None
+            };
+
+            let mut parquet_writer = if save_parquet {
+                Some(crate::utils::parquet_export::ParquetMetadataWriter::new(
+                    &root_output_path,
                 )?)
             } else {
                 None
             };
 
             // Initialize log writer
-            let mut log_writer = crate::utils::logging::LogWriter::new(&root_output_path)?;
-
-            for (dicom_path, outcome, folder_relative) in rx {
-                match outcome {
-                    Ok(FileOutcome::Converted(mut metadata)) => {
-                        metadata.folder_relative = folder_relative;
-                        if let Some(writer) = &mut metadata_writer {
-                            writer.write_record(&metadata)?;
+            let mut log_writer =
+                crate::utils::logging::LogWriter::new(&root_output_path, overwrite)?;
+            let mut error_writer = crate::utils::errors::ErrorWriter::new(&root_output_path)?;
+
+            // Only this thread writes to the archive
+            let mut zip_export = match &zip_path {
+                Some(zip_path) => Some(ZipExport::create(zip_path, &root_output_path)?),
+                None => None,
+            };
+
+            // A failed write stops the run instead of the whole report being
+            // lost; every writer flushes per row, so what was recorded stays
+            let recorded = (|| -> Result<()> {
+                for (dicom_path, outcome, folder_relative) in rx {
+                    match outcome {
+                        Ok(FileOutcome::Converted(mut metadata, png_path, png)) => {
+                            metadata.folder_relative = folder_relative;
+                            if let (Some(zip_export), Some(png)) = (&mut zip_export, png) {
+                                zip_export.add_png(&png_path, &png)?;
+                            }
+                            if html_index {
+                                index_entries.push(IndexEntry {
+                                    png_path: png_path
+                                        .strip_prefix(&root_output_path)
+                                        .unwrap_or(&png_path)
+                                        .to_path_buf(),
+                                    metadata: metadata.clone(),
+                                });
+                            }
+                            if contact_sheet_columns.is_some() {
+                                montage_entries.push(MontageEntry {
+                                    series_uid: metadata
+                                        .series_instance_uid
+                                        .as_deref()
+                                        .map(sanitize_uid)
+                                        .filter(|uid| !uid.is_empty())
+                                        .unwrap_or_else(|| "unsorted".to_string()),
+                                    instance_number: metadata.instance_number,
+                                    png_path,
+                                });
+                            }
+                            if let Some(writer) = &mut metadata_writer {
+                                writer.write_record(&metadata)?;
+                            }
+                            if let Some(writer) = &mut parquet_writer {
+                                writer.write_record(&metadata)?;
+                            }
+                            successful += 1;
+                            let message = if metadata.decoded_with_fallback {
+                                "Converted successfully (fallback decoder)"
+                            } else {
+                                "Converted successfully"
+                            };
+                            let entry = LogEntry {
+                                file_name: dicom_path
+                                    .file_name()
+                                    .and_then(|n| n.to_str())
+                                    .unwrap_or("unknown")
+                                    .to_string(),
+                                file_path: dicom_path.to_string_lossy().to_string(),
+                                success: true,
+                                status: "Success".to_string(),
+                                message: message.to_string(),
+                                conversion_type: "PNG".to_string(),
+                            };
+                            log_callback(entry.clone());
+                            log_writer.write_entry(&entry)?;
+                            logs.push(entry);
                         }
-                        successful += 1;
-                        let entry = LogEntry {
-                            file_name: dicom_path
-                                .file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string(),
-                            file_path: dicom_path.to_string_lossy().to_string(),
-                            success: true,
-                            status: "Success".to_string(),
-                            message: "Converted successfully".to_string(),
-                            conversion_type: "PNG".to_string(),
-                        };
-                        log_callback(entry.clone());
-                        log_writer.write_entry(&entry)?;
-                        logs.push(entry);
-                    }
-                    Ok(FileOutcome::Skipped {
-                        mut metadata,
-                        reason,
-                    }) => {
-                        metadata.folder_relative = folder_relative;
-                        if let Some(writer) = &mut metadata_writer {
-                            writer.write_record(&metadata)?;
+                        Ok(FileOutcome::Skipped {
+                            mut metadata,
+                            reason,
+                        }) => {
+                            metadata.folder_relative = folder_relative;
+                            if !metadata_only_success {
+                                if let Some(writer) = &mut metadata_writer {
+                                    writer.write_record(&metadata)?;
+                                }
+                                if let Some(writer) = &mut parquet_writer {
+                                    writer.write_record(&metadata)?;
+                                }
+                            }
+                            skipped_count += 1;
+                            skipped_files.push(
+                                dicom_path
+                                    .file_name()
+                                    .and_then(|name| name.to_str())
+                                    .map(String::from)
+                                    .unwrap_or_else(|| dicom_path.to_string_lossy().to_string()),
+                            );
+                            crate::utils::logging::info(format_args!(
+                                "{} Skipping {} ({reason})",
+                                "∙".cyan(),
+                                dicom_path.display()
+                            ));
+                            let entry = LogEntry {
+                                file_name: dicom_path
+                                    .file_name()
+                                    .and_then(|n| n.to_str())
+                                    .unwrap_or("unknown")
+                                    .to_string(),
+                                file_path: dicom_path.to_string_lossy().to_string(),
+                                success: true,
+                                status: "Skipped".to_string(),
+                                message: reason.clone(),
+                                conversion_type: "PNG".to_string(),
+                            };
+                            log_callback(entry.clone());
+                            log_writer.write_entry(&entry)?;
+                            logs.push(entry);
                         }
-                        skipped_count += 1;
-                        skipped_files.push(
-                            dicom_path
-                                .file_name()
-                                .and_then(|name| name.to_str())
-                                .map(String::from)
-                                .unwrap_or_else(|| dicom_path.to_string_lossy().to_string()),
-                        );
-                        println!(
-                            "{} Skipping {} ({reason})",
-                            "∙".cyan(),
-                            dicom_path.display()
-                        );
-                        let entry = LogEntry {
-                            file_name: dicom_path
-                                .file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string(),
-                            file_path: dicom_path.to_string_lossy().to_string(),
-                            success: true,
-                            status: "Skipped".to_string(),
-                            message: reason.clone(),
-                            conversion_type: "PNG".to_string(),
-                        };
-                        log_callback(entry.clone());
-                        log_writer.write_entry(&entry)?;
-                        logs.push(entry);
-                    }
-                    Ok(FileOutcome::Failed {
-                        mut metadata,
-                        error,
-                    }) => {
-                        metadata.folder_relative = folder_relative;
-                        // Optionally write failed metadata too? Original code did register it.
-                        if let Some(writer) = &mut metadata_writer {
-                            writer.write_record(&metadata)?;
+                        Ok(FileOutcome::Failed {
+                            mut metadata,
+                            error,
+                            transfer_syntax,
+                        }) => {
+                            metadata.folder_relative = folder_relative;
+                            *failures_by_transfer_syntax
+                                .entry(transfer_syntax)
+                                .or_insert(0) += 1;
+                            // Failed files keep a row by default so the sheet
+                            // lists every image file that was found
+                            if !metadata_only_success {
+                                if let Some(writer) = &mut metadata_writer {
+                                    writer.write_record(&metadata)?;
+                                }
+                                if let Some(writer) = &mut parquet_writer {
+                                    writer.write_record(&metadata)?;
+                                }
+                            }
+                            crate::utils::logging::warn(format_args!(
+                                "{} Failed to convert {}:\n{:#}",
+                                "✖".red(),
+                                dicom_path.display(),
+                                error
+                            ));
+                            error_writer.write_error(&dicom_path, &error)?;
+                            failed_files.push(
+                                dicom_path
+                                    .file_name()
+                                    .and_then(|name| name.to_str())
+                                    .map(String::from)
+                                    .unwrap_or_else(|| dicom_path.to_string_lossy().to_string()),
+                            );
+                            let entry = LogEntry {
+                                file_name: dicom_path
+                                    .file_name()
+                                    .and_then(|n| n.to_str())
+                                    .unwrap_or("unknown")
+                                    .to_string(),
+                                file_path: dicom_path.to_string_lossy().to_string(),
+                                success: false,
+                                status: "Failed".to_string(),
+                                message: error.to_string(),
+                                conversion_type: "PNG".to_string(),
+                            };
+                            log_callback(entry.clone());
+                            log_writer.write_entry(&entry)?;
+                            logs.push(entry);
+                        }
+                        Err(err) => {
+                            // Not even the file meta could be read
+                            *failures_by_transfer_syntax
+                                .entry("Unreadable".to_string())
+                                .or_insert(0) += 1;
+                            crate::utils::logging::warn(format_args!(
+                                "{} Critical error processing {}:\n{:#}",
+                                "✖".red(),
+                                dicom_path.display(),
+                                err
+                            ));
+                            error_writer.write_error(&dicom_path, &err)?;
+                            failed_files.push(
+                                dicom_path
+                                    .file_name()
+                                    .and_then(|name| name.to_str())
+                                    .map(String::from)
+                                    .unwrap_or_else(|| dicom_path.to_string_lossy().to_string()),
+                            );
+                            let entry = LogEntry {
+                                file_name: dicom_path
+                                    .file_name()
+                                    .and_then(|n| n.to_str())
+                                    .unwrap_or("unknown")
+                                    .to_string(),
+                                file_path: dicom_path.to_string_lossy().to_string(),
+                                success: false,
+                                status: "Failed".to_string(),
+                                message: err.to_string(),
+                                conversion_type: "PNG".to_string(),
+                            };
+                            log_callback(entry.clone());
+                            log_writer.write_entry(&entry)?;
+                            logs.push(entry);
                         }
-                        eprintln!(
-                            "{} Failed to convert {}:\n{:#}",
-                            "✖".red(),
-                            dicom_path.display(),
-                            error
-                        );
-                        failed_files.push(
-                            dicom_path
-                                .file_name()
-                                .and_then(|name| name.to_str())
-                                .map(String::from)
-                                .unwrap_or_else(|| dicom_path.to_string_lossy().to_string()),
-                        );
-                        let entry = LogEntry {
-                            file_name: dicom_path
-                                .file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string(),
-                            file_path: dicom_path.to_string_lossy().to_string(),
-                            success: false,
-                            status: "Failed".to_string(),
-                            message: error.to_string(),
-                            conversion_type: "PNG".to_string(),
-                        };
-                        log_callback(entry.clone());
-                        log_writer.write_entry(&entry)?;
-                        logs.push(entry);
-                    }
-                    Err(err) => {
-                        eprintln!(
-                            "{} Critical error processing {}:\n{:#}",
-                            "✖".red(),
-                            dicom_path.display(),
-                            err
-                        );
-                        failed_files.push(
-                            dicom_path
-                                .file_name()
-                                .and_then(|name| name.to_str())
-                                .map(String::from)
-                                .unwrap_or_else(|| dicom_path.to_string_lossy().to_string()),
-                        );
-                        let entry = LogEntry {
-                            file_name: dicom_path
-                                .file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string(),
-                            file_path: dicom_path.to_string_lossy().to_string(),
-                            success: false,
-                            status: "Failed".to_string(),
-                            message: err.to_string(),
-                            conversion_type: "PNG".to_string(),
-                        };
-                        log_callback(entry.clone());
-                        log_writer.write_entry(&entry)?;
-                        logs.push(entry);
                     }
                 }
+                Ok(())
+            })();
+            let mut write_error = recorded.err().map(|e| {
+                cancel.store(true, Ordering::Relaxed);
+                describe_write_error(&e)
+            });
+
+            // Without its footer the Parquet file would be unreadable
+            if let Some(writer) = parquet_writer {
+                if let Err(e) = writer.finish() {
+                    write_error.get_or_insert_with(|| describe_write_error(&e));
+                }
             }
 
+            if let Some(columns) = contact_sheet_columns.filter(|_| zip_path.is_none()) {
+                // A broken sheet should not fail a finished conversion
+                if let Err(e) = write_contact_sheets(montage_entries, &root_output_path, columns) {
+                    crate::utils::logging::warn(format_args!(
+                        "{} Failed to write contact sheets: {:#}",
+                        "⚠".yellow(),
+                        e
+                    ));
+                }
+            }
+
+            // Written last so it can link the contact sheets; with --zip it
+            // lands in the staging folder and is archived with the logs
+            if html_index {
+                if let Err(e) = write_html_index(index_entries, &root_output_path) {
+                    crate::utils::logging::warn(format_args!(
+                        "{} Failed to write HTML index: {:#}",
+                        "⚠".yellow(),
+                        e
+                    ));
+                }
+            }
+
+            let output_folder = match zip_export {
+                Some(zip_export) => {
+                    // Close the staged files before they are archived
+                    drop((metadata_writer, log_writer, error_writer));
+                    match zip_export.finish() {
+                        Ok(zip_path) => zip_path,
+                        Err(e) => {
+                            write_error.get_or_insert_with(|| describe_write_error(&e));
+                            root_output_path
+                        }
+                    }
+                }
+                None => root_output_path,
+            };
+
             Ok(ConversionReport {
                 total,
                 successful,
-                failed: total.saturating_sub(successful + skipped_count),
+                failed: failed_files.len(),
                 skipped_non_image: skipped_count,
                 failed_files,
                 skipped_files,
-                output_folder: root_output_path,
+                failures_by_transfer_syntax,
+                output_folder,
+                cancelled: false,
+                aborted_by: None,
+                write_error,
             })
         }
     });
 
-    tasks
-        .par_iter()
-        .for_each_with(tx, |tx, (dicom_path, png_path, folder_relative)| {
+    tasks.par_iter().enumerate().for_each_with(
+        tx,
+        |tx, (index, (dicom_path, png_path, folder_relative))| {
+            if options.cancel.load(Ordering::Relaxed) {
+                return;
+            }
+
             let current = processed_count.fetch_add(1, Ordering::Relaxed) + 1;
             let filename = dicom_path
                 .file_name()
                 .and_then(|n| n.to_str())
                 .unwrap_or("unknown")
                 .to_string();
+            let series_position = task_series.get(index).map(|&series_index| {
+                let entry = &series[series_index];
+                SeriesPosition {
+                    series_uid: entry.uid.clone(),
+                    series_index: series_index + 1,
+                    series_count: series.len(),
+                    series_current: entry.started.fetch_add(1, Ordering::Relaxed) + 1,
+                    series_total: entry.total,
+                }
+            });
+            let payload = |filename: String, status: &str| ProgressPayload {
+                current,
+                total,
+                filename,
+                status: status.to_string(),
+                series: series_position.clone(),
+            };
 
-            if png_path.exists() {
-                progress_callback(ProgressPayload {
-                    current,
-                    total,
-                    filename: filename.clone(),
-                    status: "skipped".to_string(),
-                });
+            if !options.overwrite && png_path.exists() {
+                progress_callback(payload(filename.clone(), "skipped"));
 
                 // Try to read metadata from DICOM file for the report
-                let metadata = crate::logic::convert::extract_metadata(dicom_path).ok();
+                let metadata = crate::logic::convert::extract_metadata(
+                    dicom_path,
+                    &options.metadata_columns.tags(),
+                )
+                .ok()
+                .map(|mut metadata| {
+                    if options.hash {
+                        metadata.file_hash = file_sha256(dicom_path).ok();
+                    }
+                    metadata
+                });
 
                 let _ = tx.send((
                     dicom_path.clone(),
@@ -284,33 +771,198 @@ where
                 return;
             }
 
-            progress_callback(ProgressPayload {
-                current,
-                total,
-                filename,
-                status: "converting".to_string(),
-            });
+            progress_callback(payload(filename, "converting"));
 
-            let outcome = convert_single_file(dicom_path, png_path);
+            let outcome = convert_single_file(dicom_path, png_path, options);
+            if matches!(outcome, Ok(FileOutcome::Failed { .. }) | Err(_)) {
+                abort_on_error(options.on_error, &options.cancel, &aborted_by, dicom_path);
+            }
             let _ = tx.send((dicom_path.clone(), outcome, folder_relative.clone()));
+        },
+    );
+
+    // Wait for writer thread to finish; it still records every file that
+    // completed before a cancellation
+    let mut report = writer_handle
+        .join()
+        .map_err(|_| anyhow!("Writer thread panicked"))??;
+    // A write error stops the run through the same flag
+    report.cancelled = options.cancel.load(Ordering::Relaxed) && report.write_error.is_none();
+    report.aborted_by = aborted_path(aborted_by);
+    Ok(report)
+}
+
+/// Give every task its own output path. Several inputs can map to the same
+/// image, e.g. duplicate InstanceNumbers within a series with
+/// `group_by_series`, or `a.dcm` next to `a.ima`; later ones in path order
+/// get `_2`, `_3`, ... appended to the file stem instead of overwriting the
+/// first.
+fn dedupe_output_paths(tasks: &mut [(PathBuf, PathBuf, PathBuf)]) {
+    let mut used = HashSet::new();
+    for (dicom_path, png_path, _) in tasks.iter_mut() {
+        if used.insert(png_path.clone()) {
+            continue;
+        }
+        let stem = png_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let extension = png_path.extension().map(|ext| ext.to_os_string());
+        let unique = (2..)
+            .map(|n| {
+                let mut candidate = png_path.with_file_name(format!("{}_{}", stem, n));
+                if let Some(extension) = &extension {
+                    candidate.set_extension(extension);
+                }
+                candidate
+            })
+            .find(|candidate| used.insert(candidate.clone()))
+            .unwrap_or_else(|| png_path.clone());
+        crate::utils::logging::warn(format_args!(
+            "{} {} maps to the same output as another file; writing {}",
+            "⚠".yellow(),
+            dicom_path.display(),
+            unique.display()
+        ));
+        *png_path = unique;
+    }
+}
+
+/// Apply `if_exists` to the computed output root.
+fn resolve_output_root(root: PathBuf, if_exists: IfExists) -> Result<PathBuf> {
+    let in_use = |path: &Path| {
+        fs::read_dir(path)
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false)
+    };
+    if if_exists == IfExists::Append || !in_use(&root) {
+        return Ok(root);
+    }
+    if if_exists == IfExists::Error {
+        bail!(
+            "Output folder '{}' already exists and is not empty",
+            root.display()
+        );
+    }
+    let name = root
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    Ok((2..)
+        .map(|n| root.with_file_name(format!("{}_{}", name, n)))
+        .find(|path| !in_use(path))
+        .expect("unbounded suffix range"))
+}
+
+/// Message for a failed output write, calling out a full disk.
+pub(crate) fn describe_write_error(error: &anyhow::Error) -> String {
+    let disk_full = error.chain().any(|cause| {
+        let io_error = cause.downcast_ref::<std::io::Error>().or_else(|| {
+            match cause.downcast_ref::<csv::Error>().map(csv::Error::kind) {
+                Some(csv::ErrorKind::Io(e)) => Some(e),
+                _ => None,
+            }
         });
+        io_error.is_some_and(|e| e.kind() == std::io::ErrorKind::StorageFull)
+    });
+    if disk_full {
+        format!("Output disk full: {:#}", error)
+    } else {
+        format!("Write failed: {:#}", error)
+    }
+}
+
+/// With `OnError::Abort`, stop the run after `failed_path` failed. Only
+/// the first failure is recorded, and none if the run was already cancelled.
+pub(crate) fn abort_on_error(
+    on_error: OnError,
+    cancel: &AtomicBool,
+    aborted_by: &Mutex<Option<PathBuf>>,
+    failed_path: &Path,
+) {
+    if on_error == OnError::Abort && !cancel.swap(true, Ordering::Relaxed) {
+        *aborted_by.lock().unwrap_or_else(|e| e.into_inner()) = Some(failed_path.to_path_buf());
+    }
+}
 
-    // Wait for writer thread to finish
-    writer_handle.join().unwrap()
+pub(crate) fn aborted_path(aborted_by: Mutex<Option<PathBuf>>) -> Option<String> {
+    aborted_by
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .map(|path| path.to_string_lossy().to_string())
 }
 
-fn build_png_path(input_folder: &Path, output_folder: &Path, dicom_path: &Path) -> PathBuf {
-    let relative: PathBuf = dicom_path
-        .strip_prefix(input_folder)
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| {
-            dicom_path
-                .file_name()
+/// Folder whose layout the output mirrors: the input folder, or a base
+/// folder given instead.
+pub(crate) struct OutputBase {
+    input_folder: PathBuf,
+    /// Canonical input folder and base folder, when a base is given. Files
+    /// are found under the input folder as given, so they are compared via
+    /// the canonical input folder instead of canonicalizing each one.
+    canonical: Option<(PathBuf, PathBuf)>,
+}
+
+impl OutputBase {
+    pub(crate) fn new(input_folder: &Path, relative_to: Option<&Path>) -> Result<Self> {
+        let canonical = match relative_to {
+            Some(base) => {
+                let base = fs::canonicalize(base)
+                    .with_context(|| format!("Unable to access base folder {}", base.display()))?;
+                let input = fs::canonicalize(input_folder).with_context(|| {
+                    format!("Unable to access input folder {}", input_folder.display())
+                })?;
+                Some((input, base))
+            }
+            None => None,
+        };
+        Ok(Self {
+            input_folder: input_folder.to_path_buf(),
+            canonical,
+        })
+    }
+
+    /// `path` relative to the base folder, or only its file name when it
+    /// lies outside.
+    pub(crate) fn relative(&self, path: &Path) -> PathBuf {
+        let relative = match &self.canonical {
+            None => path
+                .strip_prefix(&self.input_folder)
+                .ok()
+                .map(PathBuf::from),
+            Some((input, base)) => {
+                let absolute = match path.strip_prefix(&self.input_folder) {
+                    Ok(rest) => input.join(rest),
+                    Err(_) => path.to_path_buf(),
+                };
+                absolute.strip_prefix(base).ok().map(PathBuf::from)
+            }
+        };
+        relative.unwrap_or_else(|| {
+            path.file_name()
                 .map(PathBuf::from)
                 .unwrap_or_else(|| PathBuf::from("unknown"))
-        });
+        })
+    }
+}
 
-    let mut png_path = output_folder.join(relative);
-    png_path.set_extension("png");
-    png_path
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelling_a_run_leaves_the_others_running() {
+        let runs = ActiveRuns::default();
+        let first = runs.start();
+        let second = runs.start();
+        assert_ne!(first.id, second.id);
+
+        assert!(runs.cancel(&first.id));
+        assert!(first.cancel.load(Ordering::Relaxed));
+        assert!(!second.cancel.load(Ordering::Relaxed));
+
+        let finished = second.id.clone();
+        drop(second);
+        assert!(!runs.cancel(&finished));
+    }
 }
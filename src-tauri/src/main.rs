@@ -1,7 +1,6 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use clap::Parser;
 use dicom_app_lib::cli::Cli;
 
 fn main() {
@@ -9,7 +8,7 @@ fn main() {
 
     // If there are arguments (more than just the program name), try to run as CLI
     if args.len() > 1 {
-        match Cli::try_parse() {
+        match Cli::try_parse_with_preset() {
             Ok(cli) => {
                 dicom_app_lib::cli::run_cli(cli);
             }
@@ -1,14 +1,31 @@
 use chrono::NaiveDate;
-use dicom::core::Tag;
-use dicom_object::DefaultDicomObject;
-use std::path::PathBuf;
+use dicom::core::dictionary::{UidDictionary, UidDictionaryEntry};
+use dicom::core::{Tag, VR};
+use dicom::encoding::text::{SpecificCharacterSet, TextCodec};
+use dicom_dictionary_std::StandardSopClassDictionary;
+use dicom_object::mem::InMemElement;
+use dicom_object::{DefaultDicomObject, InMemDicomObject};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
 
 #[derive(Clone, Debug, Default)]
 pub struct FileMetadata {
     pub folder_relative: PathBuf,
     pub file_name: String,
     pub study_date: Option<String>,
+    /// Keyword of the attribute `study_date` was read from; StudyDate
+    /// unless it was empty and a later date was used instead
+    pub study_date_source: Option<String>,
     pub modality: Option<String>,
+    pub sop_class_uid: Option<String>,
+    pub sop_class_name: Option<String>,
+    pub series_instance_uid: Option<String>,
+    pub instance_number: Option<i32>,
+    /// ImageType values as stored, e.g. `ORIGINAL\PRIMARY\AXIAL`
+    pub image_type: Option<String>,
     pub manufacturer: Option<String>,
     pub study_description: Option<String>,
     pub series_description: Option<String>,
@@ -17,16 +34,137 @@ pub struct FileMetadata {
     pub im_width: Option<u32>,
     pub im_height: Option<u32>,
     pub pixel_spacing: Option<String>,
+    /// ImagePositionPatient of every frame of an enhanced multi-frame
+    /// object, in frame order, e.g. `0\0\1; 0\0\2`
+    pub frame_positions: Option<String>,
+    pub spacing_embedded: Option<bool>,
+    /// Intensity range and mean after the modality LUT, when pixel
+    /// statistics are enabled
+    pub pixel_min: Option<f64>,
+    pub pixel_max: Option<f64>,
+    pub pixel_mean: Option<f64>,
+    pub decoded_with_fallback: bool,
+    /// Hex SHA-256 of the source file bytes, when hashing is enabled
+    pub file_hash: Option<String>,
+    /// Attributes requested as extra metadata columns, by tag; absent
+    /// when the file has no value
+    pub extra_tags: HashMap<Tag, String>,
+}
+
+/// `dicom_text` of each of `tags` that has a value. Attributes an enhanced
+/// multi-frame object only stores in its functional groups, such as
+/// ImagePositionPatient, are read for the first frame.
+pub fn extra_tag_values(obj: &DefaultDicomObject, tags: &[Tag]) -> HashMap<Tag, String> {
+    tags.iter()
+        .filter_map(|&tag| {
+            dicom_text(obj, tag)
+                .or_else(|| frame_text(obj, 0, tag))
+                .map(|value| (tag, value))
+        })
+        .collect()
+}
+
+/// `tag` as it applies to `frame` (0-based) of an enhanced multi-frame
+/// object: from a functional group macro of the frame's item in
+/// PerFrameFunctionalGroupsSequence (5200,9230), else of the
+/// SharedFunctionalGroupsSequence (5200,9229).
+pub fn frame_element(obj: &DefaultDicomObject, frame: u32, tag: Tag) -> Option<&InMemElement> {
+    let groups = |sequence: Tag, index: usize| obj.element(sequence).ok()?.items()?.get(index);
+    [
+        groups(Tag(0x5200, 0x9230), frame as usize),
+        groups(Tag(0x5200, 0x9229), 0),
+    ]
+    .into_iter()
+    .flatten()
+    .flat_map(|item| item.iter())
+    .find_map(|group| group.items()?.first()?.element(tag).ok())
+}
+
+/// ImagePositionPatient of each frame of an enhanced multi-frame object,
+/// joined with "; ", with an empty entry for frames that have none. `None`
+/// for other objects.
+pub fn frame_positions(obj: &DefaultDicomObject) -> Option<String> {
+    let frames = obj.element(Tag(0x5200, 0x9230)).ok()?.items()?.len() as u32;
+    let positions: Vec<String> = (0..frames)
+        .map(|frame| frame_text(obj, frame, Tag(0x0020, 0x0032)).unwrap_or_default())
+        .collect();
+    positions
+        .iter()
+        .any(|position| !position.is_empty())
+        .then(|| positions.join("; "))
+}
+
+/// `dicom_text` of `frame_element`.
+pub fn frame_text(obj: &DefaultDicomObject, frame: u32, tag: Tag) -> Option<String> {
+    frame_element(obj, frame, tag)
+        .and_then(|element| TextDecoder::of(obj).element_text(element))
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
 }
 
 pub fn dicom_text(obj: &DefaultDicomObject, tag: Tag) -> Option<String> {
     obj.element(tag)
         .ok()
-        .and_then(|element| element.to_str().ok())
+        .and_then(|element| TextDecoder::of(obj).element_text(element))
         .map(|value| value.trim().to_string())
         .filter(|value| !value.is_empty())
 }
 
+/// Text of a data set's elements, decoded per its SpecificCharacterSet
+/// (0008,0005). dicom-rs decodes while parsing but only looks at the first
+/// value, so with ISO 2022 code extensions such as `\ISO 2022 IR 87` the
+/// text was read in the default repertoire. That decoding is Latin-1 and
+/// lossless, so it is undone and the bytes are decoded again with the first
+/// supported extension.
+#[derive(Clone, Debug, Default)]
+pub struct TextDecoder(Option<SpecificCharacterSet>);
+
+impl TextDecoder {
+    pub fn of(obj: &InMemDicomObject) -> Self {
+        let Some(values) = obj
+            .element(Tag(0x0008, 0x0005))
+            .ok()
+            .and_then(|e| e.to_multi_str().ok())
+        else {
+            return Self(None);
+        };
+        let Some((first, extensions)) = values.split_first() else {
+            return Self(None);
+        };
+        let is_default =
+            |charset: &SpecificCharacterSet| *charset == SpecificCharacterSet::ISO_IR_6;
+        if SpecificCharacterSet::from_code(first.trim())
+            .is_some_and(|charset| !is_default(&charset))
+        {
+            // Already decoded correctly by the parser
+            return Self(None);
+        }
+        Self(
+            extensions
+                .iter()
+                .filter_map(|code| SpecificCharacterSet::from_code(code.trim()))
+                .find(|charset| !is_default(charset)),
+        )
+    }
+
+    /// The element's value as text, or `None` for binary values.
+    pub fn element_text(&self, element: &InMemElement) -> Option<String> {
+        let text = element.to_str().ok()?;
+        let affected = matches!(
+            element.vr(),
+            VR::SH | VR::LO | VR::ST | VR::LT | VR::UT | VR::UC | VR::PN
+        );
+        let redecoded = self.0.as_ref().filter(|_| affected).and_then(|charset| {
+            let bytes = text
+                .chars()
+                .map(|c| u8::try_from(c as u32).ok())
+                .collect::<Option<Vec<u8>>>()?;
+            charset.decode(&bytes).ok()
+        });
+        Some(redecoded.unwrap_or_else(|| text.into_owned()))
+    }
+}
+
 pub fn dicom_date(obj: &DefaultDicomObject, tag: Tag) -> Option<String> {
     let raw = dicom_text(obj, tag)?;
     let cleaned: String = raw.chars().filter(|c| c.is_ascii_digit()).collect();
@@ -38,10 +176,41 @@ pub fn dicom_date(obj: &DefaultDicomObject, tag: Tag) -> Option<String> {
         .map(|date| date.format("%Y-%m-%d").to_string())
 }
 
+/// Dates tried in order when StudyDate is empty, e.g. in secondary captures.
+pub const STUDY_DATE_FALLBACKS: [(Tag, &str); 4] = [
+    (Tag(0x0008, 0x0020), "StudyDate"),
+    (Tag(0x0008, 0x0021), "SeriesDate"),
+    (Tag(0x0008, 0x0022), "AcquisitionDate"),
+    (Tag(0x0008, 0x0023), "ContentDate"),
+];
+
+/// The first valid date of `STUDY_DATE_FALLBACKS` with its keyword.
+pub fn study_date(obj: &DefaultDicomObject) -> Option<(String, String)> {
+    STUDY_DATE_FALLBACKS
+        .iter()
+        .find_map(|&(tag, source)| dicom_date(obj, tag).map(|date| (date, source.to_string())))
+}
+
+/// Human-readable name of a SOP class UID, e.g. "CT Image Storage".
+pub fn sop_class_name(uid: &str) -> Option<String> {
+    StandardSopClassDictionary
+        .by_uid(uid.trim_end_matches(['\0', ' ']))
+        .map(|entry| entry.name().to_string())
+}
+
 pub fn pixel_spacing(obj: &DefaultDicomObject) -> Option<String> {
     dicom_text(obj, Tag(0x0028, 0x0030)).map(|raw| raw.replace('\\', ", "))
 }
 
+/// PixelSpacing as (row spacing, column spacing) in millimeters.
+pub fn pixel_spacing_mm(obj: &DefaultDicomObject) -> Option<(f64, f64)> {
+    let raw = dicom_text(obj, Tag(0x0028, 0x0030))?;
+    let mut values = raw.split('\\').map(|v| v.trim().parse::<f64>().ok());
+    let row = values.next()??;
+    let col = values.next()??;
+    (row > 0.0 && col > 0.0).then_some((row, col))
+}
+
 pub fn extract_pixel_data_status(obj: &DefaultDicomObject) -> String {
     use dicom_pixeldata::PixelDecoder;
     if obj.element(Tag(0x7FE0, 0x0010)).is_err() {
@@ -56,3 +225,103 @@ pub fn extract_pixel_data_status(obj: &DefaultDicomObject) -> String {
         }
     }
 }
+
+/// SHA-256 of the file contents as lowercase hex, streamed so large files
+/// are not loaded into memory.
+pub fn file_sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::value::DataSetSequence;
+    use dicom::core::{DataElement, PrimitiveValue};
+    use dicom_object::meta::FileMetaTableBuilder;
+
+    fn file_object(elements: impl IntoIterator<Item = InMemElement>) -> DefaultDicomObject {
+        InMemDicomObject::from_element_iter(elements)
+            .with_meta(
+                FileMetaTableBuilder::new()
+                    .transfer_syntax("1.2.840.10008.1.2.1")
+                    .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+                    .media_storage_sop_instance_uid("1.2.3.4"),
+            )
+            .unwrap()
+    }
+
+    fn sequence(tag: Tag, items: Vec<InMemDicomObject>) -> InMemElement {
+        DataElement::new(tag, VR::SQ, DataSetSequence::from(items))
+    }
+
+    /// Functional group item with a PlanePositionSequence.
+    fn position_group(position: &str) -> InMemDicomObject {
+        let plane = InMemDicomObject::from_element_iter([DataElement::new(
+            Tag(0x0020, 0x0032),
+            VR::DS,
+            PrimitiveValue::from(position),
+        )]);
+        InMemDicomObject::from_element_iter([sequence(Tag(0x0020, 0x9113), vec![plane])])
+    }
+
+    /// Object with SpecificCharacterSet `charsets` and InstitutionName
+    /// `name` as the parser stores it.
+    fn institution_object(charsets: &[&str], name: &str) -> DefaultDicomObject {
+        let charsets = charsets.iter().map(|c| c.to_string()).collect();
+        file_object([
+            DataElement::new(Tag(0x0008, 0x0005), VR::CS, PrimitiveValue::Strs(charsets)),
+            DataElement::new(Tag(0x0008, 0x0080), VR::LO, PrimitiveValue::from(name)),
+        ])
+    }
+
+    #[test]
+    fn text_decoder_redecodes_iso_2022_extensions() {
+        let institution = Tag(0x0008, 0x0080);
+        // Latin-1 bytes read in the default repertoire come out unchanged
+        let obj = institution_object(&["", "ISO 2022 IR 100"], "Zürich Klinik");
+        assert_eq!(
+            dicom_text(&obj, institution).as_deref(),
+            Some("Zürich Klinik")
+        );
+
+        // 0xE1 is á in Latin-1 but α in ISO 8859-7
+        let obj = institution_object(&["", "ISO 2022 IR 126"], "\u{e1}");
+        assert_eq!(dicom_text(&obj, institution).as_deref(), Some("α"));
+
+        // The parser already used a non-default first value
+        let obj = institution_object(&["ISO_IR 100", "ISO 2022 IR 126"], "\u{e1}");
+        assert_eq!(dicom_text(&obj, institution).as_deref(), Some("á"));
+    }
+
+    #[test]
+    fn frame_element_reads_the_frames_own_group() {
+        let obj = file_object([sequence(
+            Tag(0x5200, 0x9230),
+            vec![position_group("0\\0\\1"), position_group("0\\0\\2")],
+        )]);
+        let position = Tag(0x0020, 0x0032);
+        assert_eq!(frame_text(&obj, 0, position).as_deref(), Some("0\\0\\1"));
+        assert_eq!(frame_text(&obj, 1, position).as_deref(), Some("0\\0\\2"));
+        assert!(frame_element(&obj, 2, position).is_none());
+        assert!(frame_element(&obj, 0, Tag(0x0028, 0x1050)).is_none());
+        assert_eq!(frame_positions(&obj).as_deref(), Some("0\\0\\1; 0\\0\\2"));
+        assert_eq!(frame_positions(&file_object([])), None);
+    }
+
+    #[test]
+    fn frame_element_falls_back_to_the_shared_group() {
+        let obj = file_object([
+            sequence(Tag(0x5200, 0x9229), vec![position_group("1\\2\\3")]),
+            sequence(Tag(0x5200, 0x9230), vec![InMemDicomObject::new_empty()]),
+        ]);
+        let position = Tag(0x0020, 0x0032);
+        assert_eq!(frame_text(&obj, 0, position).as_deref(), Some("1\\2\\3"));
+        // Root values are not part of the functional groups
+        let extra = extra_tag_values(&obj, &[position]);
+        assert_eq!(extra.get(&position).map(String::as_str), Some("1\\2\\3"));
+        assert_eq!(frame_positions(&obj).as_deref(), Some("1\\2\\3"));
+    }
+}
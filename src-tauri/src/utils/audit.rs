@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use dicom::core::dictionary::DataDictionary;
+use dicom::core::Tag;
+use std::fs::File;
+use std::path::Path;
+
+/// What anonymization did to one requested tag of one file.
+/// Original values are deliberately never recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    Replaced,
+    /// The element was deleted, e.g. PixelData with `--strip-pixel-data`
+    Removed,
+    /// PatientID replaced by its salted hash (`--salt`)
+    Pseudonymized,
+    /// PatientAge recomputed from replaced dates, or emptied when they no
+    /// longer give an age
+    Recomputed,
+    /// Matched a tag range, but the replacement does not fit the element's
+    /// VR, so its value was cleared instead
+    Emptied,
+    NotPresent,
+    Error,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Replaced => "replaced",
+            AuditAction::Removed => "removed",
+            AuditAction::Pseudonymized => "pseudonymized",
+            AuditAction::Recomputed => "recomputed",
+            AuditAction::Emptied => "emptied",
+            AuditAction::NotPresent => "not-present",
+            AuditAction::Error => "error",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditEntry {
+    pub tag: Tag,
+    pub action: AuditAction,
+}
+
+pub struct AuditWriter {
+    wtr: csv::Writer<File>,
+}
+
+impl AuditWriter {
+    pub fn new(output_folder: &Path) -> Result<Self> {
+        let audit_path = output_folder.join("audit.csv");
+        let mut wtr = csv::Writer::from_path(&audit_path)
+            .with_context(|| format!("Failed to open audit file: {}", audit_path.display()))?;
+
+        wtr.write_record(["file_name", "file_path", "tag", "tag_name", "action"])?;
+        wtr.flush()?;
+
+        Ok(Self { wtr })
+    }
+
+    pub fn write_entries(
+        &mut self,
+        file_name: &str,
+        file_path: &str,
+        entries: &[AuditEntry],
+    ) -> Result<()> {
+        for entry in entries {
+            let tag_name = dicom::dictionary_std::StandardDataDictionary
+                .by_tag(entry.tag)
+                .map(|e| e.alias.to_string())
+                .unwrap_or_else(|| "Unknown".to_string());
+
+            self.wtr.write_record([
+                file_name,
+                file_path,
+                &format!("{:04X},{:04X}", entry.tag.0, entry.tag.1),
+                &tag_name,
+                entry.action.as_str(),
+            ])?;
+        }
+        self.wtr.flush()?;
+        Ok(())
+    }
+}
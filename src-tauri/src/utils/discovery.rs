@@ -1,40 +1,495 @@
-use std::fs::File;
-use std::io::Read;
+use anyhow::{bail, Context, Result};
+use dicom::encoding::TransferSyntaxIndex;
+use dicom_object::meta::FileMetaTableBuilder;
+use dicom_object::{DefaultDicomObject, InMemDicomObject};
+use dicom_transfer_syntax_registry::entries::{
+    EXPLICIT_VR_LITTLE_ENDIAN, IMPLICIT_VR_LITTLE_ENDIAN,
+};
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
+#[derive(Clone, Debug, Default)]
+pub struct DiscoveryOptions {
+    /// Also accept extensionless files without a Part 10 header
+    /// whose first data element parses as a group 0008 tag.
+    pub force: bool,
+    /// Glob patterns matched against the path relative to the input folder;
+    /// matching files and directories are skipped.
+    pub exclude: Vec<String>,
+    /// Process exactly these files instead of walking the input folder;
+    /// `force` and `exclude` do not apply to them.
+    pub files: Option<Vec<PathBuf>>,
+    /// Keep only the first N files (in path order) after discovery
+    pub max_files: Option<usize>,
+    /// Only process files whose Modality (0008,0060) is one of these (case
+    /// insensitive); empty allows all. Needs the header, so it is checked
+    /// per file rather than during discovery.
+    pub only_modality: Vec<String>,
+    /// Also walk files and directories whose name starts with '.', such as
+    /// macOS `.DS_Store` and `._` AppleDouble files, which are skipped by
+    /// default since they never parse as DICOM.
+    pub include_hidden: bool,
+    /// Also accept files whose "DICM" magic sits at offset 0, written by
+    /// tools that drop the 128-byte preamble. Off by default so the common
+    /// case stays a single comparison.
+    pub lenient_preamble: bool,
+}
+
+impl DiscoveryOptions {
+    /// Skip reason for a file whose modality is not in `only_modality`.
+    pub fn modality_filter(&self, modality: Option<&str>) -> Option<String> {
+        if self.only_modality.is_empty() {
+            return None;
+        }
+        let modality = modality.map(str::trim).filter(|m| !m.is_empty());
+        match modality {
+            Some(m) if self.only_modality.iter().any(|a| a.eq_ignore_ascii_case(m)) => None,
+            Some(m) => Some(format!("filtered: Modality {}", m)),
+            None => Some("filtered: no Modality".to_string()),
+        }
+    }
+}
+
 pub fn collect_dicom_files(input_folder: &Path) -> Vec<PathBuf> {
+    // The default options have no patterns, so building the matcher cannot fail
+    collect_dicom_files_with(input_folder, &DiscoveryOptions::default()).unwrap_or_default()
+}
+
+/// Files examined between two discovery progress reports.
+const PROGRESS_INTERVAL: usize = 500;
+
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct DiscoveryProgress {
+    /// Files examined so far
+    pub scanned: usize,
+    /// Files accepted as DICOM so far
+    pub found: usize,
+}
+
+pub fn collect_dicom_files_with(
+    input_folder: &Path,
+    options: &DiscoveryOptions,
+) -> Result<Vec<PathBuf>> {
+    collect_dicom_files_with_progress(input_folder, options, |_| {})
+}
+
+/// Like `collect_dicom_files_with`, reporting progress every few hundred
+/// files examined and once at the end, since probing file headers of a
+/// large tree can take a while.
+pub fn collect_dicom_files_with_progress<P>(
+    input_folder: &Path,
+    options: &DiscoveryOptions,
+    progress: P,
+) -> Result<Vec<PathBuf>>
+where
+    P: Fn(DiscoveryProgress),
+{
+    let mut files = match &options.files {
+        Some(files) => files.clone(),
+        None => walk_matches(input_folder, options, &progress)?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect(),
+    };
+    // WalkDir order depends on the filesystem; sort so logs, outputs and
+    // the --max-files subset are the same on every machine
+    files.sort();
+    if let Some(max_files) = options.max_files {
+        files.truncate(max_files);
+    }
+    Ok(files)
+}
+
+/// Which discovery rule accepted a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchRule {
+    /// .dcm, .dicom or .ima extension
+    Extension,
+    /// "DICM" at byte offset 128
+    Magic,
+    /// "DICM" at byte offset 0, accepted because of `lenient_preamble`
+    NoPreamble,
+    /// Headerless data set accepted because of `force`
+    Forced,
+}
+
+#[derive(Debug, Default)]
+pub struct DiscoveryCount {
+    pub total: usize,
+    pub by_extension: usize,
+    pub by_magic: usize,
+    pub by_no_preamble: usize,
+    pub by_force: usize,
+}
+
+/// Count the files discovery would pick up in `input_folder`, split by the
+/// rule that matched. Only file names and the first bytes are read.
+pub fn count_dicom_files(
+    input_folder: &Path,
+    options: &DiscoveryOptions,
+) -> Result<DiscoveryCount> {
+    let mut count = DiscoveryCount::default();
+    for (_, rule) in walk_matches(input_folder, options, &|_| {})? {
+        count.total += 1;
+        match rule {
+            MatchRule::Extension => count.by_extension += 1,
+            MatchRule::Magic => count.by_magic += 1,
+            MatchRule::NoPreamble => count.by_no_preamble += 1,
+            MatchRule::Forced => count.by_force += 1,
+        }
+    }
+    Ok(count)
+}
+
+fn walk_matches(
+    input_folder: &Path,
+    options: &DiscoveryOptions,
+    progress: &dyn Fn(DiscoveryProgress),
+) -> Result<Vec<(PathBuf, MatchRule)>> {
+    let exclude = build_exclude_set(&options.exclude)?;
     let mut files = Vec::new();
-    for entry in WalkDir::new(input_folder).into_iter().filter_map(Result::ok) {
+    let mut scanned = 0usize;
+    // Reading the header is a round trip per file, which dominates on
+    // network shares, so extensionless files are probed in parallel one
+    // progress interval at a time
+    let mut pending = Vec::with_capacity(PROGRESS_INTERVAL);
+    for entry in WalkDir::new(input_folder)
+        .into_iter()
+        .filter_entry(|entry| {
+            let relative = entry
+                .path()
+                .strip_prefix(input_folder)
+                .unwrap_or(entry.path());
+            if relative.as_os_str().is_empty() {
+                // The input folder itself may be hidden
+                return true;
+            }
+            (options.include_hidden || !is_hidden(entry)) && !exclude.is_match(relative)
+        })
+        .filter_map(Result::ok)
+    {
         if entry.file_type().is_file() {
-            let path = entry.path().to_path_buf();
-            if looks_like_dicom(&path) {
-                files.push(path);
+            pending.push(entry.into_path());
+            scanned += 1;
+            if scanned.is_multiple_of(PROGRESS_INTERVAL) {
+                probe_pending(&mut pending, &mut files, options);
+                progress(DiscoveryProgress {
+                    scanned,
+                    found: files.len(),
+                });
+            }
+        }
+    }
+    probe_pending(&mut pending, &mut files, options);
+    progress(DiscoveryProgress {
+        scanned,
+        found: files.len(),
+    });
+    Ok(files)
+}
+
+/// Match every pending path, keeping walk order in `files`.
+fn probe_pending(
+    pending: &mut Vec<PathBuf>,
+    files: &mut Vec<(PathBuf, MatchRule)>,
+    options: &DiscoveryOptions,
+) {
+    let matched: Vec<(PathBuf, MatchRule)> = pending
+        .par_drain(..)
+        .filter_map(|path| match_rule(&path, options).map(|rule| (path, rule)))
+        .collect();
+    files.extend(matched);
+}
+
+fn is_hidden(entry: &walkdir::DirEntry) -> bool {
+    entry.file_name().to_string_lossy().starts_with('.')
+}
+
+/// Read a newline-delimited list of file paths (blank lines and lines
+/// starting with '#' are ignored). Every path must exist. Returns the
+/// canonical paths and their deepest common ancestor folder, which stands
+/// in for the input folder when building the output structure.
+pub fn read_input_list(list_file: &Path) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let content = fs::read_to_string(list_file)
+        .with_context(|| format!("Unable to read input list {}", list_file.display()))?;
+
+    let mut files = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let path = fs::canonicalize(line).with_context(|| {
+            format!(
+                "{}:{}: Unable to access {}",
+                list_file.display(),
+                index + 1,
+                line
+            )
+        })?;
+        if !path.is_file() {
+            bail!(
+                "{}:{}: {} is not a file",
+                list_file.display(),
+                index + 1,
+                line
+            );
+        }
+        files.push(path);
+    }
+
+    let Some(first) = files.first() else {
+        bail!("Input list {} contains no files", list_file.display());
+    };
+    let mut root = first.parent().unwrap_or(first).to_path_buf();
+    for path in &files[1..] {
+        while !path.starts_with(&root) {
+            if !root.pop() {
+                break;
             }
         }
     }
-    files
+
+    Ok((root, files))
 }
 
-fn looks_like_dicom(path: &Path) -> bool {
+fn build_exclude_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(
+            Glob::new(pattern).with_context(|| format!("Invalid exclude pattern '{}'", pattern))?,
+        );
+    }
+    Ok(builder.build()?)
+}
+
+/// .dcm, .dicom or .ima, in any case.
+pub fn has_dicom_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| matches!(ext.to_ascii_lowercase().as_str(), "dcm" | "dicom" | "ima"))
+}
+
+fn match_rule(path: &Path, options: &DiscoveryOptions) -> Option<MatchRule> {
     const MAGIC_OFFSET: usize = 128;
     const BUFFER_SIZE: usize = MAGIC_OFFSET + 4;
 
-    let extension_matches = path
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| matches!(ext.to_ascii_lowercase().as_str(), "dcm" | "dicom" | "ima"))
-        .unwrap_or(false);
+    if has_dicom_extension(path) {
+        return Some(MatchRule::Extension);
+    }
 
-    if extension_matches {
-        return true;
+    // Files shorter than the preamble can still be headerless data sets,
+    // so read whatever is there instead of requiring the full buffer
+    let mut buffer = [0u8; BUFFER_SIZE];
+    let len = File::open(path)
+        .and_then(|mut file| read_prefix(&mut file, &mut buffer))
+        .ok()?;
+    let buffer = &buffer[..len];
+
+    if len == BUFFER_SIZE && &buffer[MAGIC_OFFSET..] == b"DICM" {
+        return Some(MatchRule::Magic);
+    }
+    // The reader detects the magic at either offset on its own, so these
+    // files open without further options
+    if options.lenient_preamble && buffer.starts_with(b"DICM") {
+        return Some(MatchRule::NoPreamble);
+    }
+    if options.force && path.extension().is_none() && looks_like_raw_dataset(buffer) {
+        return Some(MatchRule::Forced);
     }
+    None
+}
 
-    if let Ok(mut file) = File::open(path) {
-        let mut buffer = [0u8; BUFFER_SIZE];
-        if file.read_exact(&mut buffer).is_ok() {
-            return &buffer[MAGIC_OFFSET..] == b"DICM";
+/// Fill `buffer` from the start of `file`, stopping early at end of file,
+/// so the probe needs no heap allocation. Returns the number of bytes read.
+fn read_prefix(file: &mut File, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match file.read(&mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
         }
     }
-    false
+    Ok(len)
+}
+
+/// Lightweight check for a headerless (non-Part 10) data set, similar to
+/// pydicom's `force=True`: the file must start with a little endian group
+/// 0008 element, either with an explicit VR or a plausible implicit length.
+fn looks_like_raw_dataset(buffer: &[u8]) -> bool {
+    raw_dataset_syntax(buffer).is_some()
+}
+
+/// Transfer syntax of a headerless data set starting with `buffer`:
+/// Explicit VR Little Endian when the first element has a VR, Implicit VR
+/// Little Endian when it has a plausible length instead.
+fn raw_dataset_syntax(buffer: &[u8]) -> Option<&'static str> {
+    if buffer.len() < 8 {
+        return None;
+    }
+
+    let group = u16::from_le_bytes([buffer[0], buffer[1]]);
+    if group != 0x0008 {
+        return None;
+    }
+
+    let explicit_vr = buffer[4].is_ascii_uppercase() && buffer[5].is_ascii_uppercase();
+    if explicit_vr {
+        return Some(EXPLICIT_VR_LITTLE_ENDIAN.uid());
+    }
+
+    let implicit_length = u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
+    (implicit_length < 0x0001_0000 || implicit_length == 0xFFFF_FFFF)
+        .then(|| IMPLICIT_VR_LITTLE_ENDIAN.uid())
+}
+
+/// Read a headerless data set as accepted by `force`, in the transfer
+/// syntax its first element implies, and give it a file meta group so it
+/// is handled like any Part 10 file. Returns `Ok(None)` when the file does
+/// not look like one.
+pub fn open_headerless(path: &Path) -> Result<Option<DefaultDicomObject>> {
+    let mut file = File::open(path)?;
+    let mut buffer = [0u8; 8];
+    let len = read_prefix(&mut file, &mut buffer)?;
+    let Some(uid) = raw_dataset_syntax(&buffer[..len]) else {
+        return Ok(None);
+    };
+    let transfer_syntax = TransferSyntaxRegistry
+        .get(uid)
+        .with_context(|| format!("Transfer syntax {} is not registered", uid))?;
+
+    file.seek(SeekFrom::Start(0))?;
+    let obj = InMemDicomObject::read_dataset_with_ts(BufReader::new(file), transfer_syntax)
+        .context("Failed to read headerless data set")?;
+    let obj = obj
+        .with_meta(FileMetaTableBuilder::new().transfer_syntax(uid))
+        .context("Headerless data set needs SOPClassUID and SOPInstanceUID")?;
+    Ok(Some(obj))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh directory under the system temp dir for one test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dicom-app-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// `len` bytes starting with an explicit VR SpecificCharacterSet element.
+    fn raw_dataset(len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; len];
+        bytes[..8].copy_from_slice(&[0x08, 0x00, 0x05, 0x00, b'C', b'S', 0x00, 0x00]);
+        bytes
+    }
+
+    #[test]
+    fn read_prefix_stops_at_end_of_file() {
+        let dir = temp_dir("read-prefix");
+        let path = dir.join("short");
+        fs::write(&path, raw_dataset(100)).unwrap();
+
+        let mut buffer = [0u8; 132];
+        let len = read_prefix(&mut File::open(&path).unwrap(), &mut buffer).unwrap();
+        assert_eq!(len, 100);
+        assert_eq!(buffer[..8], raw_dataset(8)[..]);
+        let mut buffer = [0u8; 8];
+        let len = read_prefix(&mut File::open(&path).unwrap(), &mut buffer).unwrap();
+        assert_eq!(len, 8);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn match_rule_probes_short_and_full_prefixes() {
+        let dir = temp_dir("match-rule");
+        let forced = DiscoveryOptions {
+            force: true,
+            ..Default::default()
+        };
+        let lenient = DiscoveryOptions {
+            lenient_preamble: true,
+            ..Default::default()
+        };
+        let default = DiscoveryOptions::default();
+
+        // A 100-byte headerless data set, shorter than the preamble
+        let short = dir.join("short");
+        fs::write(&short, raw_dataset(100)).unwrap();
+        assert_eq!(match_rule(&short, &forced), Some(MatchRule::Forced));
+        assert_eq!(match_rule(&short, &default), None);
+
+        // Exactly a preamble plus the magic
+        let mut part10 = vec![0u8; 128];
+        part10.extend_from_slice(b"DICM");
+        let magic = dir.join("magic");
+        fs::write(&magic, &part10).unwrap();
+        assert_eq!(match_rule(&magic, &default), Some(MatchRule::Magic));
+
+        let mut no_preamble = b"DICM".to_vec();
+        no_preamble.resize(132, 0);
+        let bare = dir.join("bare");
+        fs::write(&bare, &no_preamble).unwrap();
+        assert_eq!(match_rule(&bare, &lenient), Some(MatchRule::NoPreamble));
+        assert_eq!(match_rule(&bare, &default), None);
+
+        let named = dir.join("named.DCM");
+        fs::write(&named, b"").unwrap();
+        assert_eq!(match_rule(&named, &default), Some(MatchRule::Extension));
+        assert_eq!(match_rule(&dir.join("missing"), &forced), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn collected_files_are_sorted() {
+        let dir = temp_dir("ordering");
+        // Created out of order so directory order cannot line up by chance
+        for name in [
+            "b/2.dcm",
+            "a/z.dcm",
+            "b/1.dcm",
+            "c.dcm",
+            "a/sub/m.dcm",
+            "a/y.dcm",
+        ] {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+
+        let files = collect_dicom_files(&dir);
+        let relative: Vec<_> = files
+            .iter()
+            .map(|path| path.strip_prefix(&dir).unwrap().to_path_buf())
+            .collect();
+        let expected: Vec<PathBuf> = [
+            "a/sub/m.dcm",
+            "a/y.dcm",
+            "a/z.dcm",
+            "b/1.dcm",
+            "b/2.dcm",
+            "c.dcm",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(relative, expected);
+
+        let options = DiscoveryOptions {
+            max_files: Some(2),
+            ..Default::default()
+        };
+        let first = collect_dicom_files_with(&dir, &options).unwrap();
+        assert_eq!(first, files[..2]);
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
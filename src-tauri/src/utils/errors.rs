@@ -0,0 +1,37 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::path::Path;
+
+/// `errors.csv` in the output root: one row per failed file with the full
+/// error chain, so large batches can be triaged without the terminal output.
+pub struct ErrorWriter {
+    wtr: csv::Writer<File>,
+}
+
+impl ErrorWriter {
+    pub fn new(output_folder: &Path) -> Result<Self> {
+        let errors_path = output_folder.join("errors.csv");
+        let mut wtr = csv::Writer::from_path(&errors_path)
+            .with_context(|| format!("Failed to open error file: {}", errors_path.display()))?;
+
+        wtr.write_record(["file_name", "file_path", "error"])?;
+        wtr.flush()?;
+
+        Ok(Self { wtr })
+    }
+
+    pub fn write_error(&mut self, dicom_path: &Path, error: &anyhow::Error) -> Result<()> {
+        let file_name = dicom_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown");
+
+        self.wtr.write_record([
+            file_name,
+            &dicom_path.to_string_lossy(),
+            &format!("{:#}", error),
+        ])?;
+        self.wtr.flush()?;
+        Ok(())
+    }
+}
@@ -1,7 +1,139 @@
 use anyhow::{Context, Result};
+use std::fmt::Display;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// How much per-file console output is printed. Final summaries and fatal
+/// errors are always printed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+static EVENTS: AtomicBool = AtomicBool::new(false);
+
+/// Machine-readable progress output, selected with `--events`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventFormat {
+    /// One JSON object per line on stderr, named by its `event` field
+    Jsonl,
+}
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+/// Send `info` and `debug` output to stderr from now on, for commands that
+/// write their result (e.g. image bytes) to stdout.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+/// Report progress as events from now on. The per-file console output is
+/// dropped and warnings become `warning` events, so stderr carries nothing
+/// else; stdout keeps the final summary.
+pub fn enable_events(format: EventFormat) {
+    match format {
+        EventFormat::Jsonl => EVENTS.store(true, Ordering::Relaxed),
+    }
+}
+
+fn events_enabled() -> bool {
+    EVENTS.load(Ordering::Relaxed)
+}
+
+#[derive(serde::Serialize)]
+struct Event<'a, T> {
+    event: &'a str,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+#[derive(serde::Serialize)]
+struct Warning {
+    message: String,
+}
+
+/// Write `data` (a struct such as `LogEntry`) as a line
+/// `{"event": <event>, ...fields}` to stderr, if events are enabled.
+pub fn event<T: serde::Serialize>(event: &str, data: &T) {
+    if !events_enabled() {
+        return;
+    }
+    if let Ok(line) = serde_json::to_string(&Event { event, data }) {
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+}
+
+/// `text` without ANSI color sequences.
+fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip the parameters up to the final letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+fn print(message: impl Display) {
+    if STDOUT_RESERVED.load(Ordering::Relaxed) {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+/// Per-file progress to stdout; silenced by `--quiet` and by events.
+pub fn info(message: impl Display) {
+    if verbosity() >= Verbosity::Normal && !events_enabled() {
+        print(message);
+    }
+}
+
+/// Per-file problems to stderr; silenced by `--quiet`. With events enabled
+/// they are `warning` events instead, regardless of verbosity.
+pub fn warn(message: impl Display) {
+    if events_enabled() {
+        event(
+            "warning",
+            &Warning {
+                message: strip_ansi(&message.to_string()),
+            },
+        );
+    } else if verbosity() >= Verbosity::Normal {
+        eprintln!("{}", message);
+    }
+}
+
+/// Extra detail only shown with `--verbose`; silenced by events.
+pub fn debug(message: impl Display) {
+    if verbosity() >= Verbosity::Verbose && !events_enabled() {
+        print(message);
+    }
+}
 
 #[derive(Clone, serde::Serialize)]
 pub struct LogEntry {
@@ -18,13 +150,17 @@ pub struct LogWriter {
 }
 
 impl LogWriter {
-    pub fn new(output_folder: &Path) -> Result<Self> {
+    /// Appends to an existing `logs.csv` unless `truncate` is set, in which
+    /// case rows from earlier runs are discarded.
+    pub fn new(output_folder: &Path, truncate: bool) -> Result<Self> {
         let log_path = output_folder.join("logs.csv");
-        let file_exists = log_path.exists();
+        let file_exists = !truncate && log_path.exists();
 
         let mut file = OpenOptions::new()
             .create(true)
-            .append(true)
+            .write(true)
+            .append(!truncate)
+            .truncate(truncate)
             .open(&log_path)
             .with_context(|| format!("Failed to open log file: {}", log_path.display()))?;
 
@@ -53,7 +189,7 @@ pub fn write_logs(output_folder: &Path, logs: &[LogEntry]) -> Result<()> {
     if logs.is_empty() {
         return Ok(());
     }
-    let mut writer = LogWriter::new(output_folder)?;
+    let mut writer = LogWriter::new(output_folder, false)?;
     for log in logs {
         writer.write_entry(log)?;
     }
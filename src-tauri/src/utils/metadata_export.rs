@@ -1,73 +1,311 @@
 use crate::models::metadata::FileMetadata;
-use anyhow::Result;
-use std::fs::File;
-use std::path::Path;
+use anyhow::{Context, Result};
+use dicom::core::dictionary::DataDictionary;
+use dicom::core::Tag;
+use dicom::dictionary_std::StandardDataDictionary;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const HEADERS: [&str; 23] = [
+    "F_name",
+    "Study_date",
+    "Study_date_source",
+    "Modality",
+    "Sop_class_uid",
+    "Sop_class_name",
+    "Manufacturer",
+    "Study_description",
+    "Series_description",
+    "Instance_number",
+    "Image_type",
+    "Institution_name",
+    "Pixel_data",
+    "Im_width",
+    "Im_height",
+    "Pixel_spacing",
+    "Frame_positions",
+    "Spacing_embedded",
+    "Pixel_min",
+    "Pixel_max",
+    "Pixel_mean",
+    "Decoded_with_fallback",
+    "Hash",
+];
+
+/// How the metadata CSVs are laid out, for spreadsheet apps whose locale
+/// expects something other than comma-separated plain UTF-8.
+#[derive(Clone, Copy, Debug)]
+pub struct CsvFormat {
+    pub delimiter: u8,
+    /// Start each file with a UTF-8 byte order mark
+    pub bom: bool,
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            bom: false,
+        }
+    }
+}
+
+/// One column of the metadata CSVs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MetadataColumn {
+    /// A built-in column, by its position in `HEADERS`
+    Field(usize),
+    /// Any other attribute, read from each file; the name is its header
+    Tag(String, Tag),
+}
+
+/// Which columns the metadata CSVs hold, in order. Defaults to every
+/// built-in column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetadataColumns(pub Vec<MetadataColumn>);
+
+impl Default for MetadataColumns {
+    fn default() -> Self {
+        Self((0..HEADERS.len()).map(MetadataColumn::Field).collect())
+    }
+}
+
+impl MetadataColumns {
+    /// Parse a comma-separated list of built-in column names (e.g.
+    /// "F_name,Modality", case-insensitive), DICOM keywords (e.g.
+    /// "PatientAge") or tags as eight hex digits (e.g. "00181030").
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let columns: Vec<MetadataColumn> = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                if let Some(index) = HEADERS
+                    .iter()
+                    .position(|header| header.eq_ignore_ascii_case(name))
+                {
+                    return Ok(MetadataColumn::Field(index));
+                }
+                if let Some(entry) = StandardDataDictionary.by_name(name) {
+                    return Ok(MetadataColumn::Tag(
+                        entry.alias.to_string(),
+                        entry.tag.inner(),
+                    ));
+                }
+                match u32::from_str_radix(name, 16) {
+                    Ok(tag) if name.len() == 8 => {
+                        let tag = Tag((tag >> 16) as u16, tag as u16);
+                        let header = StandardDataDictionary
+                            .by_tag(tag)
+                            .map(|entry| entry.alias.to_string())
+                            .unwrap_or_else(|| format!("{:04X},{:04X}", tag.0, tag.1));
+                        Ok(MetadataColumn::Tag(header, tag))
+                    }
+                    _ => Err(format!(
+                        "Invalid column: {}. Expected one of {}, a DICOM keyword or a tag as eight hex digits",
+                        name,
+                        HEADERS.join(", ")
+                    )),
+                }
+            })
+            .collect::<Result<_, String>>()?;
+        if columns.is_empty() {
+            return Err("No columns given".to_string());
+        }
+        Ok(Self(columns))
+    }
+
+    /// Attributes outside `FileMetadata` that must be read from each file.
+    pub fn tags(&self) -> Vec<Tag> {
+        self.0
+            .iter()
+            .filter_map(|column| match column {
+                MetadataColumn::Tag(_, tag) => Some(*tag),
+                MetadataColumn::Field(_) => None,
+            })
+            .collect()
+    }
+
+    fn headers(&self) -> Vec<&str> {
+        self.0
+            .iter()
+            .map(|column| match column {
+                MetadataColumn::Field(index) => HEADERS[*index],
+                MetadataColumn::Tag(name, _) => name.as_str(),
+            })
+            .collect()
+    }
+
+    fn record(&self, metadata: &FileMetadata) -> Vec<String> {
+        let mut fields = record_fields(metadata);
+        self.0
+            .iter()
+            .map(|column| match column {
+                MetadataColumn::Field(index) => std::mem::take(&mut fields[*index]),
+                MetadataColumn::Tag(_, tag) => {
+                    metadata.extra_tags.get(tag).cloned().unwrap_or_default()
+                }
+            })
+            .collect()
+    }
+}
 
 pub struct MetadataWriter {
     wtr: csv::Writer<File>,
+    format: CsvFormat,
+    columns: MetadataColumns,
+    output_folder: PathBuf,
+    // Subfolders (`folder_relative`) whose `metadata.csv` this run created
+    started_folders: HashSet<PathBuf>,
 }
 
 impl MetadataWriter {
-    pub fn new(output_folder: &Path) -> Result<Self> {
-        let metadata_root = output_folder
-            .parent()
-            .map(Path::to_path_buf)
-            .unwrap_or_else(|| output_folder.to_path_buf());
-
+    /// `metadata_all.csv` goes in `metadata_root`, the per-subfolder
+    /// `metadata.csv` files under `output_folder`.
+    pub fn new(
+        metadata_root: &Path,
+        output_folder: &Path,
+        format: CsvFormat,
+        columns: MetadataColumns,
+    ) -> Result<Self> {
         let all_path = metadata_root.join("metadata_all.csv");
-        let mut wtr = csv::Writer::from_path(all_path)?;
-
-        // Write headers
-        wtr.write_record(&[
-            "F_name",
-            "Study_date",
-            "Modality",
-            "Manufacturer",
-            "Study_description",
-            "Series_description",
-            "Institution_name",
-            "Pixel_data",
-            "Im_width",
-            "Im_height",
-            "Pixel_spacing",
-        ])?;
-
-        wtr.flush()?;
-
-        Ok(Self { wtr })
+        let wtr = create_writer(&all_path, format, &columns)?;
+
+        Ok(Self {
+            wtr,
+            format,
+            columns,
+            output_folder: output_folder.to_path_buf(),
+            started_folders: HashSet::new(),
+        })
     }
 
     pub fn write_record(&mut self, metadata: &FileMetadata) -> Result<()> {
-        self.wtr.write_record(&[
-            &metadata.file_name,
-            metadata.study_date.as_deref().unwrap_or(""),
-            metadata.modality.as_deref().unwrap_or(""),
-            metadata.manufacturer.as_deref().unwrap_or(""),
-            metadata.study_description.as_deref().unwrap_or(""),
-            metadata.series_description.as_deref().unwrap_or(""),
-            metadata.institution_name.as_deref().unwrap_or(""),
-            metadata.pixel_data.as_deref().unwrap_or(""),
-            &metadata.im_width.map(|v| v.to_string()).unwrap_or_default(),
-            &metadata
-                .im_height
-                .map(|v| v.to_string())
-                .unwrap_or_default(),
-            metadata.pixel_spacing.as_deref().unwrap_or(""),
-        ])?;
+        let record = self.columns.record(metadata);
+        self.wtr.write_record(&record)?;
 
         // Flush immediately to ensure data is saved incrementally
         self.wtr.flush()?;
 
+        // Mirror the row into the subfolder's own metadata.csv. It is
+        // reopened for every row: a patient/study/series tree can have more
+        // folders than the process may keep files open.
+        let folder = self.output_folder.join(&metadata.folder_relative);
+        let path = folder.join("metadata.csv");
+        let mut folder_wtr = if self
+            .started_folders
+            .insert(metadata.folder_relative.clone())
+        {
+            fs::create_dir_all(&folder).with_context(|| {
+                format!("Unable to create metadata folder {}", folder.display())
+            })?;
+            create_writer(&path, self.format, &self.columns)?
+        } else {
+            let file = OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("Unable to open metadata file {}", path.display()))?;
+            csv::WriterBuilder::new()
+                .delimiter(self.format.delimiter)
+                .from_writer(file)
+        };
+        folder_wtr.write_record(&record)?;
+        folder_wtr.flush()?;
+
         Ok(())
     }
 }
 
-pub fn write_metadata_report(all_metadata: &[FileMetadata], output_folder: &Path) -> Result<()> {
+fn create_writer(
+    path: &Path,
+    format: CsvFormat,
+    columns: &MetadataColumns,
+) -> Result<csv::Writer<File>> {
+    let mut file = File::create(path)
+        .with_context(|| format!("Unable to create metadata file {}", path.display()))?;
+    if format.bom {
+        file.write_all(b"\xEF\xBB\xBF")?;
+    }
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(format.delimiter)
+        .from_writer(file);
+
+    // Write headers
+    wtr.write_record(columns.headers())?;
+    wtr.flush()?;
+
+    Ok(wtr)
+}
+
+fn record_fields(metadata: &FileMetadata) -> Vec<String> {
+    vec![
+        metadata.file_name.clone(),
+        metadata.study_date.clone().unwrap_or_default(),
+        metadata.study_date_source.clone().unwrap_or_default(),
+        metadata.modality.clone().unwrap_or_default(),
+        metadata.sop_class_uid.clone().unwrap_or_default(),
+        metadata.sop_class_name.clone().unwrap_or_default(),
+        metadata.manufacturer.clone().unwrap_or_default(),
+        metadata.study_description.clone().unwrap_or_default(),
+        metadata.series_description.clone().unwrap_or_default(),
+        metadata
+            .instance_number
+            .map(|v| v.to_string())
+            .unwrap_or_default(),
+        metadata.image_type.clone().unwrap_or_default(),
+        metadata.institution_name.clone().unwrap_or_default(),
+        metadata.pixel_data.clone().unwrap_or_default(),
+        metadata.im_width.map(|v| v.to_string()).unwrap_or_default(),
+        metadata
+            .im_height
+            .map(|v| v.to_string())
+            .unwrap_or_default(),
+        metadata.pixel_spacing.clone().unwrap_or_default(),
+        metadata.frame_positions.clone().unwrap_or_default(),
+        metadata
+            .spacing_embedded
+            .map(|embedded| if embedded { "Yes" } else { "No" }.to_string())
+            .unwrap_or_default(),
+        metadata
+            .pixel_min
+            .map(|v| v.to_string())
+            .unwrap_or_default(),
+        metadata
+            .pixel_max
+            .map(|v| v.to_string())
+            .unwrap_or_default(),
+        metadata
+            .pixel_mean
+            .map(|v| format!("{:.3}", v))
+            .unwrap_or_default(),
+        if metadata.decoded_with_fallback {
+            "Yes"
+        } else {
+            "No"
+        }
+        .to_string(),
+        metadata.file_hash.clone().unwrap_or_default(),
+    ]
+}
+
+pub fn write_metadata_report(
+    all_metadata: &[FileMetadata],
+    output_folder: &Path,
+    format: CsvFormat,
+) -> Result<()> {
     if all_metadata.is_empty() {
         return Ok(());
     }
 
-    let mut writer = MetadataWriter::new(output_folder)?;
+    let metadata_root = output_folder.parent().unwrap_or(output_folder);
+    let mut writer = MetadataWriter::new(
+        metadata_root,
+        output_folder,
+        format,
+        MetadataColumns::default(),
+    )?;
     for metadata in all_metadata {
         writer.write_record(metadata)?;
     }
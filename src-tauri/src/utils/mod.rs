@@ -1,3 +1,9 @@
+pub mod audit;
 pub mod discovery;
+pub mod errors;
 pub mod logging;
 pub mod metadata_export;
+pub mod parquet_export;
+pub mod retry;
+pub mod watch;
+pub mod zip_export;
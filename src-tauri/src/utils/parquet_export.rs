@@ -0,0 +1,203 @@
+use crate::models::metadata::FileMetadata;
+use anyhow::{Context, Result};
+use arrow_array::builder::{
+    BooleanBuilder, Date32Builder, Float64Builder, Int32Builder, ListBuilder, StringBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use chrono::NaiveDate;
+use parquet::arrow::ArrowWriter;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Rows buffered before a record batch is flushed to the file.
+const BATCH_SIZE: usize = 4096;
+
+/// Typed, columnar counterpart of `metadata_all.csv`, written as
+/// `metadata_all.parquet` in the same folder.
+pub struct ParquetMetadataWriter {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    pending: Vec<FileMetadata>,
+}
+
+impl ParquetMetadataWriter {
+    pub fn new(metadata_root: &Path) -> Result<Self> {
+        let parquet_path = metadata_root.join("metadata_all.parquet");
+        let file = File::create(&parquet_path).with_context(|| {
+            format!("Unable to create metadata file {}", parquet_path.display())
+        })?;
+
+        let schema = Arc::new(schema());
+        let writer = ArrowWriter::try_new(file, schema.clone(), None)?;
+
+        Ok(Self {
+            writer,
+            schema,
+            pending: Vec::with_capacity(BATCH_SIZE),
+        })
+    }
+
+    pub fn write_record(&mut self, metadata: &FileMetadata) -> Result<()> {
+        self.pending.push(metadata.clone());
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Write any buffered rows and the Parquet footer. Must be called once
+    /// all records are written, otherwise the file is unreadable.
+    pub fn finish(mut self) -> Result<()> {
+        self.flush_batch()?;
+        self.writer.close()?;
+        Ok(())
+    }
+
+    fn flush_batch(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut file_name = StringBuilder::new();
+        let mut study_date = Date32Builder::new();
+        let mut study_date_source = StringBuilder::new();
+        let mut modality = StringBuilder::new();
+        let mut sop_class_uid = StringBuilder::new();
+        let mut sop_class_name = StringBuilder::new();
+        let mut manufacturer = StringBuilder::new();
+        let mut study_description = StringBuilder::new();
+        let mut series_description = StringBuilder::new();
+        let mut instance_number = Int32Builder::new();
+        let mut image_type = StringBuilder::new();
+        let mut institution_name = StringBuilder::new();
+        let mut pixel_data = StringBuilder::new();
+        let mut im_width = Int32Builder::new();
+        let mut im_height = Int32Builder::new();
+        let mut pixel_spacing = ListBuilder::new(Float64Builder::new()).with_field(Field::new(
+            "item",
+            DataType::Float64,
+            false,
+        ));
+        let mut frame_positions = StringBuilder::new();
+        let mut spacing_embedded = BooleanBuilder::new();
+        let mut pixel_min = Float64Builder::new();
+        let mut pixel_max = Float64Builder::new();
+        let mut pixel_mean = Float64Builder::new();
+        let mut decoded_with_fallback = BooleanBuilder::new();
+        let mut file_hash = StringBuilder::new();
+
+        for metadata in self.pending.drain(..) {
+            file_name.append_value(&metadata.file_name);
+            study_date.append_option(metadata.study_date.as_deref().and_then(days_since_epoch));
+            study_date_source.append_option(metadata.study_date_source);
+            modality.append_option(metadata.modality);
+            sop_class_uid.append_option(metadata.sop_class_uid);
+            sop_class_name.append_option(metadata.sop_class_name);
+            manufacturer.append_option(metadata.manufacturer);
+            study_description.append_option(metadata.study_description);
+            series_description.append_option(metadata.series_description);
+            instance_number.append_option(metadata.instance_number);
+            image_type.append_option(metadata.image_type);
+            institution_name.append_option(metadata.institution_name);
+            pixel_data.append_option(metadata.pixel_data);
+            im_width.append_option(metadata.im_width.and_then(|v| i32::try_from(v).ok()));
+            im_height.append_option(metadata.im_height.and_then(|v| i32::try_from(v).ok()));
+            match metadata.pixel_spacing.as_deref().and_then(spacing_values) {
+                Some(values) => {
+                    pixel_spacing.values().append_slice(&values);
+                    pixel_spacing.append(true);
+                }
+                None => pixel_spacing.append(false),
+            }
+            frame_positions.append_option(metadata.frame_positions);
+            spacing_embedded.append_option(metadata.spacing_embedded);
+            pixel_min.append_option(metadata.pixel_min);
+            pixel_max.append_option(metadata.pixel_max);
+            pixel_mean.append_option(metadata.pixel_mean);
+            decoded_with_fallback.append_value(metadata.decoded_with_fallback);
+            file_hash.append_option(metadata.file_hash);
+        }
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(file_name.finish()),
+            Arc::new(study_date.finish()),
+            Arc::new(study_date_source.finish()),
+            Arc::new(modality.finish()),
+            Arc::new(sop_class_uid.finish()),
+            Arc::new(sop_class_name.finish()),
+            Arc::new(manufacturer.finish()),
+            Arc::new(study_description.finish()),
+            Arc::new(series_description.finish()),
+            Arc::new(instance_number.finish()),
+            Arc::new(image_type.finish()),
+            Arc::new(institution_name.finish()),
+            Arc::new(pixel_data.finish()),
+            Arc::new(im_width.finish()),
+            Arc::new(im_height.finish()),
+            Arc::new(pixel_spacing.finish()),
+            Arc::new(frame_positions.finish()),
+            Arc::new(spacing_embedded.finish()),
+            Arc::new(pixel_min.finish()),
+            Arc::new(pixel_max.finish()),
+            Arc::new(pixel_mean.finish()),
+            Arc::new(decoded_with_fallback.finish()),
+            Arc::new(file_hash.finish()),
+        ];
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        self.writer.write(&batch)?;
+        Ok(())
+    }
+}
+
+/// Same column names as the CSV export, with native types.
+fn schema() -> Schema {
+    let text = |name: &str| Field::new(name, DataType::Utf8, true);
+    Schema::new(vec![
+        Field::new("F_name", DataType::Utf8, false),
+        Field::new("Study_date", DataType::Date32, true),
+        text("Study_date_source"),
+        text("Modality"),
+        text("Sop_class_uid"),
+        text("Sop_class_name"),
+        text("Manufacturer"),
+        text("Study_description"),
+        text("Series_description"),
+        Field::new("Instance_number", DataType::Int32, true),
+        text("Image_type"),
+        text("Institution_name"),
+        text("Pixel_data"),
+        Field::new("Im_width", DataType::Int32, true),
+        Field::new("Im_height", DataType::Int32, true),
+        Field::new(
+            "Pixel_spacing",
+            DataType::List(Arc::new(Field::new("item", DataType::Float64, false))),
+            true,
+        ),
+        text("Frame_positions"),
+        Field::new("Spacing_embedded", DataType::Boolean, true),
+        Field::new("Pixel_min", DataType::Float64, true),
+        Field::new("Pixel_max", DataType::Float64, true),
+        Field::new("Pixel_mean", DataType::Float64, true),
+        Field::new("Decoded_with_fallback", DataType::Boolean, false),
+        text("Hash"),
+    ])
+}
+
+/// Accepts both the normalized `YYYY-MM-DD` form and raw DICOM `YYYYMMDD`.
+fn days_since_epoch(date: &str) -> Option<i32> {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1)?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y%m%d"))
+        .ok()?;
+    i32::try_from((date - epoch).num_days()).ok()
+}
+
+/// Splits "0.5, 0.5" (as exported) or raw "0.5\0.5" into numbers.
+fn spacing_values(spacing: &str) -> Option<Vec<f64>> {
+    spacing
+        .split([',', '\\'])
+        .map(|v| v.trim().parse::<f64>().ok())
+        .collect()
+}
@@ -0,0 +1,59 @@
+use crate::utils::discovery::open_headerless;
+use dicom_object::{open_file, DefaultDicomObject, ReadError};
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// Attempts after the first failed open, waiting 200, 400 and 800 ms.
+const RETRIES: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+/// `open_file` that retries when the file could not be opened or read
+/// because another process holds it, e.g. an antivirus scanner on Windows.
+/// Files without a file meta group, as accepted by discovery's `force`,
+/// are read as headerless data sets. Other parse errors fail immediately;
+/// after the last retry the IO error is returned as usual.
+pub fn open_file_with_retry(path: &Path) -> anyhow::Result<DefaultDicomObject> {
+    let mut backoff = INITIAL_BACKOFF;
+    for _ in 0..RETRIES {
+        match open_file(path) {
+            Err(e) if is_transient(&e) => {
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+            result => return or_headerless(path, result),
+        }
+    }
+    or_headerless(path, open_file(path))
+}
+
+/// `result`, or the headerless data set in `path` if the file meta group
+/// could not be read because there is none (short data sets fail while
+/// reading the preamble).
+fn or_headerless(
+    path: &Path,
+    result: Result<DefaultDicomObject, ReadError>,
+) -> anyhow::Result<DefaultDicomObject> {
+    match result {
+        Err(e @ ReadError::OpenFile { .. }) => Err(e.into()),
+        Err(e) => match open_headerless(path) {
+            Ok(Some(obj)) => Ok(obj),
+            Ok(None) => Err(e.into()),
+            Err(headerless_error) => Err(headerless_error),
+        },
+        result => Ok(result?),
+    }
+}
+
+fn is_transient(error: &ReadError) -> bool {
+    let source = match error {
+        ReadError::OpenFile { source, .. } | ReadError::ReadFile { source, .. } => source,
+        _ => return false,
+    };
+    // Locks only surface as access errors on Windows (ERROR_SHARING_VIOLATION,
+    // ERROR_LOCK_VIOLATION); elsewhere PermissionDenied is a real
+    // permission problem that waiting will not fix
+    cfg!(windows)
+        && (source.kind() == io::ErrorKind::PermissionDenied
+            || matches!(source.raw_os_error(), Some(32 | 33)))
+}
@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// Recursively watches a folder for new or changed files.
+pub struct FolderWatcher {
+    // Dropping the watcher stops the events
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    ignored: Option<PathBuf>,
+}
+
+impl FolderWatcher {
+    /// Events for paths under `ignored` (e.g. an output folder nested in
+    /// the watched folder) are dropped, so writing output does not
+    /// trigger another pass.
+    pub fn new(folder: &Path, ignored: Option<&Path>) -> Result<Self> {
+        // Event paths are based on the watched path; make both absolute so
+        // they can be compared. The ignored folder may not exist yet.
+        let folder = folder
+            .canonicalize()
+            .with_context(|| format!("Unable to watch {}", folder.display()))?;
+        let ignored = ignored.and_then(|path| {
+            path.canonicalize()
+                .or_else(|_| std::path::absolute(path))
+                .ok()
+        });
+
+        let (tx, events) = channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        watcher
+            .watch(&folder, RecursiveMode::Recursive)
+            .with_context(|| format!("Unable to watch {}", folder.display()))?;
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            ignored,
+        })
+    }
+
+    /// Block until a file is created or modified, then until no further
+    /// changes arrive for `quiet_period`, so files that are still being
+    /// copied in are picked up once, complete.
+    pub fn wait_for_changes(&self, quiet_period: Duration) -> Result<()> {
+        loop {
+            let event = self.events.recv().context("File watcher stopped")?;
+            if self.is_relevant(event?) {
+                break;
+            }
+        }
+
+        loop {
+            match self.events.recv_timeout(quiet_period) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => return Ok(()),
+                Err(RecvTimeoutError::Disconnected) => {
+                    anyhow::bail!("File watcher stopped")
+                }
+            }
+        }
+    }
+
+    fn is_relevant(&self, event: Event) -> bool {
+        if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            return false;
+        }
+        match &self.ignored {
+            Some(ignored) => event.paths.iter().any(|path| !path.starts_with(ignored)),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Single zip archive that replaces the output folder. PNGs are streamed in
+/// from memory; the metadata and log files are written to a staging folder
+/// as usual and added when the archive is finished. Entry names are paths
+/// relative to the staging folder, so the archive has the same layout as a
+/// regular output folder.
+pub struct ZipExport {
+    writer: ZipWriter<BufWriter<File>>,
+    zip_path: PathBuf,
+    staging_folder: PathBuf,
+}
+
+impl ZipExport {
+    pub fn create(zip_path: &Path, staging_folder: &Path) -> Result<Self> {
+        if let Some(parent) = zip_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::create(zip_path)
+            .with_context(|| format!("Unable to create zip archive {}", zip_path.display()))?;
+        Ok(Self {
+            writer: ZipWriter::new(BufWriter::new(file)),
+            zip_path: zip_path.to_path_buf(),
+            staging_folder: staging_folder.to_path_buf(),
+        })
+    }
+
+    /// `path` is where the PNG would have been written in the staging folder.
+    pub fn add_png(&mut self, path: &Path, png: &[u8]) -> Result<()> {
+        // PNG data is already deflated
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(png.len() as u64 >= u32::MAX as u64);
+        self.writer
+            .start_file(self.entry_name(path), options)
+            .with_context(|| format!("Unable to add {} to the zip archive", path.display()))?;
+        self.writer.write_all(png)?;
+        Ok(())
+    }
+
+    /// Add every file left in the staging folder (metadata, logs), close the
+    /// archive and remove the staging folder. Writers of those files must be
+    /// dropped or finished first.
+    pub fn finish(mut self) -> Result<PathBuf> {
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        for entry in WalkDir::new(&self.staging_folder)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+        {
+            let name = self.entry_name(entry.path());
+            self.writer.start_file(name, options)?;
+            let mut file = File::open(entry.path())
+                .with_context(|| format!("Unable to read {}", entry.path().display()))?;
+            std::io::copy(&mut file, &mut self.writer)?;
+        }
+
+        self.writer
+            .finish()
+            .with_context(|| format!("Unable to finish zip archive {}", self.zip_path.display()))?
+            .flush()?;
+        fs::remove_dir_all(&self.staging_folder).with_context(|| {
+            format!(
+                "Unable to remove staging folder {}",
+                self.staging_folder.display()
+            )
+        })?;
+        Ok(self.zip_path)
+    }
+
+    /// Zip entry names always use `/`, whatever the platform separator.
+    fn entry_name(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.staging_folder).unwrap_or(path);
+        relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
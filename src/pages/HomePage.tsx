@@ -20,6 +20,11 @@ interface ProgressPayload {
   status: string;
 }
 
+interface DiscoveryProgress {
+  scanned: number;
+  found: number;
+}
+
 interface ConversionReport {
   total: number;
   successful: number;
@@ -54,6 +59,7 @@ export default function HomePage() {
   // Separate Progress States
   const [anonymizeProgress, setAnonymizeProgress] = createSignal<ProgressPayload | null>(null);
   const [convertProgress, setConvertProgress] = createSignal<ProgressPayload | null>(null);
+  const [discoveryProgress, setDiscoveryProgress] = createSignal<DiscoveryProgress | null>(null);
 
   const [isProcessing, setIsProcessing] = createSignal(false);
 
@@ -80,6 +86,12 @@ export default function HomePage() {
 
   const DialogContent = createPersistent(DialogInfomation)
 
+  // Shown before the first file starts; discovery of a large tree can take a while
+  const waitingText = () =>
+    isProcessing() && discoveryProgress()
+      ? `Scanning files... ${discoveryProgress()?.found} found`
+      : "Waiting...";
+
   const setupListeners = async () => {
     const unlistenConvert = await listen<ProgressPayload>("conversion_progress", (event) => {
       setConvertProgress(event.payload);
@@ -87,6 +99,9 @@ export default function HomePage() {
     const unlistenAnonymize = await listen<ProgressPayload>("anonymization_progress", (event) => {
       setAnonymizeProgress(event.payload);
     });
+    const unlistenDiscovery = await listen<DiscoveryProgress>("discovery_progress", (event) => {
+      setDiscoveryProgress(event.payload);
+    });
     const unlistenLogs = await listen<LogEntry>("log_event", (event) => {
       setLogs((prev) => [...prev, event.payload]);
     });
@@ -94,6 +109,7 @@ export default function HomePage() {
     onCleanup(() => {
       unlistenConvert();
       unlistenAnonymize();
+      unlistenDiscovery();
       unlistenLogs();
     });
   };
@@ -123,6 +139,7 @@ export default function HomePage() {
   const resetState = () => {
     setAnonymizeProgress(null);
     setConvertProgress(null);
+    setDiscoveryProgress(null);
     setConversionReport(null);
     setAnonymizationReport(null);
     setLogs([]);
@@ -347,7 +364,7 @@ export default function HomePage() {
                       </span>
                       {" "}{anonymizeProgress()?.current}/{anonymizeProgress()?.total}: {anonymizeProgress()?.filename}
                     </span>
-                  ) : (anonymizationReport() ? "Completed" : "Waiting...")}
+                  ) : (anonymizationReport() ? "Completed" : waitingText())}
                 </p>
               </div>
             </div>
@@ -379,7 +396,7 @@ export default function HomePage() {
                       </span>
                       {" "}{convertProgress()?.current}/{convertProgress()?.total}: {convertProgress()?.filename}
                     </span>
-                  ) : (conversionReport() ? "Completed" : "Waiting...")}
+                  ) : (conversionReport() ? "Completed" : waitingText())}
                 </p>
               </div>
             </div>